@@ -1,10 +1,13 @@
 use clap::{ArgAction, Parser};
-use djwavfixer::{Result, WavFile};
+use djwavfixer::{
+    BackupMode, ClipHandling, DitherMode, FixOptions, OutputTreeAction, ParseMode, Result,
+    TargetBitDepth, UnfixedFileAction, UnknownChannelLayout, WavFile,
+};
 use std::fmt::Write;
 use std::fs::File;
 use std::io::BufReader;
-use std::num::NonZeroUsize;
-use std::path::PathBuf;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
 use std::{path, thread};
 
 /// Command-line interface for djwavfixer
@@ -37,12 +40,60 @@ pub struct Cli {
     #[arg(long, action=ArgAction::SetTrue)]
     pub fix: bool,
 
+    /// Write fixed copies into this directory, mirroring the source folder structure,
+    /// instead of modifying files in place
+    #[arg(long, requires = "fix")]
+    pub output_dir: Option<PathBuf>,
+
+    /// What to do with files that need no fixing when using `--output-dir`
+    #[arg(long, value_enum, default_value_t = UnfixedFileAction::Skip)]
+    pub unfixed: UnfixedFileAction,
+
+    /// How to keep the previous version of files that get overwritten by a fix
+    #[arg(long, value_enum, default_value_t = BackupMode::None)]
+    pub backup: BackupMode,
+
+    /// Directory for `--backup=content-addressed` backups(default is a `.djwavfixer-backups`
+    /// directory next to each file)
+    #[arg(long)]
+    pub backup_dir: Option<PathBuf>,
+
+    /// Bit depth for files whose samples need converting
+    #[arg(long, value_enum, default_value_t = TargetBitDepth::Bits24)]
+    pub bit_depth: TargetBitDepth,
+
+    /// How to reduce precision when converting to a lower bit depth
+    #[arg(long, value_enum, default_value_t = DitherMode::Tpdf)]
+    pub dither: DitherMode,
+
+    /// What to do with float samples beyond full scale
+    #[arg(long, value_enum, default_value_t = ClipHandling::Clip)]
+    pub clipping: ClipHandling,
+
+    /// Highest sample rate players should get, e.g. 44100 or 48000. Files above it need fixing
+    /// and are resampled down to it
+    #[arg(long)]
+    pub sample_rate: Option<NonZeroU32>,
+
+    /// How to place the channels of multichannel files without a channel mask when downmixing
+    /// them to stereo
+    #[arg(long, value_enum, default_value_t = UnknownChannelLayout::SpeakerOrder)]
+    pub unknown_layout: UnknownChannelLayout,
+
+    /// Whether inconsistent headers reject a file, or are reported and repaired by `--fix`
+    #[arg(long, value_enum, default_value_t = ParseMode::Strict)]
+    pub parse_mode: ParseMode,
+
     /// Log level for the application
     #[arg(long)]
     pub log_level: log::Level,
 }
 
-fn get_files(cli: &Cli, pool: Option<&rayon::ThreadPool>) -> Result<Vec<WavFile<BufReader<File>>>> {
+fn get_files(
+    cli: &Cli,
+    fix_options: &FixOptions,
+    pool: Option<&rayon::ThreadPool>,
+) -> Result<Vec<WavFile<BufReader<File>>>> {
     let path_to_read = path::absolute(&cli.path)?;
     let files = if path_to_read.is_dir() {
         djwavfixer::get_all_wav_files_in_directory(&path_to_read, cli.recursive)?
@@ -56,9 +107,9 @@ fn get_files(cli: &Cli, pool: Option<&rayon::ThreadPool>) -> Result<Vec<WavFile<
     };
 
     let mut read_files = if let Some(pool) = pool {
-        djwavfixer::load_wav_files_rayon(&files, pool)?
+        djwavfixer::load_wav_files_rayon_with_mode(&files, pool, cli.parse_mode)?
     } else {
-        djwavfixer::load_wav_files(&files)?
+        djwavfixer::load_wav_files_with_mode(&files, cli.parse_mode)?
     };
 
     if read_files.is_empty() {
@@ -71,11 +122,11 @@ fn get_files(cli: &Cli, pool: Option<&rayon::ThreadPool>) -> Result<Vec<WavFile<
     }
 
     if cli.ignore_valid {
-        read_files.retain(|file_res| file_res.needs_fixing().unwrap_or(true));
+        read_files.retain(|file_res| file_res.needs_fixing(fix_options).unwrap_or(true));
     }
 
     if cli.ignore_unfixable {
-        read_files.retain(|file_res| file_res.can_fix().unwrap_or_default());
+        read_files.retain(|file_res| file_res.can_fix(fix_options).unwrap_or_default());
     }
 
     if read_files.is_empty() {
@@ -88,6 +139,19 @@ fn get_files(cli: &Cli, pool: Option<&rayon::ThreadPool>) -> Result<Vec<WavFile<
     Ok(read_files)
 }
 
+fn fix_options(cli: &Cli) -> Result<FixOptions> {
+    Ok(FixOptions {
+        backup_mode: cli.backup,
+        backup_directory: cli.backup_dir.as_ref().map(path::absolute).transpose()?,
+        unfixed_file_action: cli.unfixed,
+        target_bit_depth: cli.bit_depth,
+        dither: cli.dither,
+        clip_handling: cli.clipping,
+        target_sample_rate: cli.sample_rate,
+        unknown_channel_layout: cli.unknown_layout,
+    })
+}
+
 fn run_with_cli(cli: Cli) -> Result<()> {
     let num_threads = cli
         .num_threads
@@ -102,18 +166,39 @@ fn run_with_cli(cli: Cli) -> Result<()> {
         })
         .transpose()?;
 
-    let read_files = get_files(&cli, pool.as_ref())?;
+    let fix_options = fix_options(&cli)?;
+    let mut read_files = get_files(&cli, &fix_options, pool.as_ref())?;
     if read_files.is_empty() {
         // Error message already logged in get_files
         return Ok(());
     }
 
+    if cli.fix {
+        match cli.output_dir {
+            Some(ref output_dir) => {
+                let path_to_read = path::absolute(&cli.path)?;
+                let source_root = if path_to_read.is_dir() {
+                    path_to_read.as_path()
+                } else {
+                    path_to_read.parent().unwrap_or(Path::new(""))
+                };
+                fix_files_to_output_tree(
+                    &mut read_files,
+                    source_root,
+                    &path::absolute(output_dir)?,
+                    &fix_options,
+                );
+            }
+            None => fix_files(&mut read_files, &fix_options),
+        }
+    }
+
     log::info!("Found WAV files:");
     let file_information = read_files.iter().enumerate().try_fold(
         "\n".to_string(),
         |mut acc, (file_number, file)| {
             writeln!(acc, "{}:", file_number + 1)?;
-            file.write_information(&mut acc)?;
+            file.write_information(&mut acc, &fix_options)?;
             Result::Ok(acc)
         },
     )?;
@@ -123,9 +208,93 @@ fn run_with_cli(cli: Cli) -> Result<()> {
     Ok(())
 }
 
+fn fix_files(read_files: &mut [WavFile<BufReader<File>>], fix_options: &FixOptions) {
+    let (mut fixed, mut failed) = (0usize, 0usize);
+    for file in read_files {
+        if !file.can_fix(fix_options).unwrap_or_default() {
+            continue;
+        }
+
+        match file.fix_in_place(fix_options) {
+            Ok(()) => {
+                log::info!("Fixed `{}`", file.path().display());
+                fixed += 1;
+            }
+            Err(error) => {
+                log::error!("Failed to fix `{}`: {}", file.path().display(), error);
+                failed += 1;
+            }
+        }
+    }
+
+    log::info!("Fixed {} file(s), {} failed.", fixed, failed);
+}
+
+fn fix_files_to_output_tree(
+    read_files: &mut [WavFile<BufReader<File>>],
+    source_root: &Path,
+    output_root: &Path,
+    fix_options: &FixOptions,
+) {
+    let (mut written, mut failed) = (0usize, 0usize);
+    for file in read_files {
+        if file.needs_fixing(fix_options).unwrap_or(true)
+            && !file.can_fix(fix_options).unwrap_or_default()
+        {
+            log::warn!("Skipping unfixable file `{}`", file.path().display());
+            continue;
+        }
+
+        match file.write_to_output_tree(source_root, output_root, fix_options) {
+            Ok(OutputTreeAction::Skipped) => {}
+            Ok(action) => {
+                log::info!("{:?} `{}`", action, file.path().display());
+                written += 1;
+            }
+            Err(error) => {
+                log::error!("Failed to write `{}`: {}", file.path().display(), error);
+                failed += 1;
+            }
+        }
+    }
+
+    log::info!(
+        "Wrote {} file(s) to `{}`, {} failed.",
+        written,
+        output_root.display(),
+        failed
+    );
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
     simple_logger::init_with_level(cli.log_level).expect("Could not initialize logger");
 
     run_with_cli(cli)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_rate_rejects_zero() {
+        let parse = |sample_rate| {
+            Cli::try_parse_from([
+                "djwavfixer-cli",
+                "music",
+                "--log-level",
+                "info",
+                "--sample-rate",
+                sample_rate,
+            ])
+        };
+        assert!(parse("0").is_err());
+        assert_eq!(
+            parse("48000")
+                .expect("Failed to parse arguments")
+                .sample_rate,
+            NonZeroU32::new(48000)
+        );
+    }
+}
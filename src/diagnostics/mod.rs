@@ -0,0 +1,173 @@
+//! Problems found while parsing headers, collected instead of failing when parsing leniently
+
+use std::fmt;
+
+use crate::DWORD_SIZE;
+use crate::errors::{DJWavFixerError, Result};
+
+/// How the parser treats headers that contradict themselves
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum ParseMode {
+    /// Reject files on the first inconsistent header field
+    #[default]
+    Strict,
+    /// Build the most likely structure and report every problem found on the way
+    Lenient,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    /// Against the specification, but harmless to most readers
+    Warning,
+    /// Rejected by strict parsing
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The header field a diagnostic is about
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HeaderField {
+    /// Size of the `RIFF`, `RF64` or `BW64` chunk
+    RiffSize,
+    SubchunkSize([u8; DWORD_SIZE]),
+    /// The byte that pads an odd-sized subchunk to an even length
+    PadByte([u8; DWORD_SIZE]),
+    CbSize,
+    BlockAlign,
+    AvgBytesPerSecond,
+}
+
+impl HeaderField {
+    fn is_fmt_field(&self) -> bool {
+        matches!(
+            self,
+            HeaderField::CbSize | HeaderField::BlockAlign | HeaderField::AvgBytesPerSecond
+        )
+    }
+}
+
+impl fmt::Display for HeaderField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderField::RiffSize => write!(f, "RIFF size"),
+            HeaderField::SubchunkSize(id) => {
+                write!(f, "'{}' size", String::from_utf8_lossy(id))
+            }
+            HeaderField::PadByte(id) => {
+                write!(f, "pad byte after '{}'", String::from_utf8_lossy(id))
+            }
+            HeaderField::CbSize => write!(f, "fmt cbSize"),
+            HeaderField::BlockAlign => write!(f, "fmt nBlockAlign"),
+            HeaderField::AvgBytesPerSecond => write!(f, "fmt nAvgBytesPerSec"),
+        }
+    }
+}
+
+/// A single problem with a file's headers
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    severity: Severity,
+    offset: u64,
+    field: HeaderField,
+    message: String,
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// Byte offset of the field in the file
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn field(&self) -> HeaderField {
+        self.field
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at byte {} ({}): {}",
+            self.severity, self.offset, self.field, self.message
+        )
+    }
+}
+
+impl From<Diagnostic> for DJWavFixerError {
+    fn from(diagnostic: Diagnostic) -> Self {
+        if diagnostic.field.is_fmt_field() {
+            DJWavFixerError::WaveFormatError(diagnostic.message)
+        } else {
+            DJWavFixerError::RiffHeaderError(diagnostic.message)
+        }
+    }
+}
+
+/// Collects diagnostics while parsing, failing on the first error unless parsing leniently
+#[derive(Debug, Default)]
+pub(crate) struct Diagnostics {
+    mode: ParseMode,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub(crate) fn new(mode: ParseMode) -> Self {
+        Self {
+            mode,
+            diagnostics: vec![],
+        }
+    }
+
+    pub(crate) fn is_lenient(&self) -> bool {
+        self.mode == ParseMode::Lenient
+    }
+
+    /// Records a problem the parser can work around, strict parsing stops at it instead
+    pub(crate) fn error(&mut self, offset: u64, field: HeaderField, message: String) -> Result<()> {
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            offset,
+            field,
+            message,
+        };
+
+        if !self.is_lenient() {
+            return Err(diagnostic.into());
+        }
+        log::warn!("{}", diagnostic);
+        self.diagnostics.push(diagnostic);
+        Ok(())
+    }
+
+    pub(crate) fn warning(&mut self, offset: u64, field: HeaderField, message: String) {
+        let diagnostic = Diagnostic {
+            severity: Severity::Warning,
+            offset,
+            field,
+            message,
+        };
+
+        log::warn!("{}", diagnostic);
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+}
@@ -1,6 +1,9 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::{fmt, io, string};
 
+use crate::WriteStage;
+
 pub type Result<T> = std::result::Result<T, DJWavFixerError>;
 
 #[derive(Clone, Debug, thiserror::Error)]
@@ -15,6 +18,16 @@ pub enum DJWavFixerError {
     RiffHeaderError(String),
     #[error("Invalid WAV format: {0}")]
     WaveFormatError(String),
+    #[error("Invalid metadata: {0}")]
+    MetadataError(String),
+    #[error("Cannot fix file: {0}")]
+    FixError(String),
+    #[error("Failed writing `{}` while {stage}: {source}", path.display())]
+    SafeWriteError {
+        stage: WriteStage,
+        path: PathBuf,
+        source: Box<DJWavFixerError>,
+    },
     #[error("Invalid UTF8 string: {0}")]
     FromUtf8Error(#[from] string::FromUtf8Error),
     #[cfg(feature = "parallel")]
@@ -30,6 +43,20 @@ impl PartialEq for DJWavFixerError {
             (DJWavFixerError::FmtError(_), DJWavFixerError::FmtError(_)) => true,
             (DJWavFixerError::RiffHeaderError(a), DJWavFixerError::RiffHeaderError(b)) => a == b,
             (DJWavFixerError::WaveFormatError(a), DJWavFixerError::WaveFormatError(b)) => a == b,
+            (DJWavFixerError::MetadataError(a), DJWavFixerError::MetadataError(b)) => a == b,
+            (DJWavFixerError::FixError(a), DJWavFixerError::FixError(b)) => a == b,
+            (
+                DJWavFixerError::SafeWriteError {
+                    stage: a_stage,
+                    path: a_path,
+                    source: a_source,
+                },
+                DJWavFixerError::SafeWriteError {
+                    stage: b_stage,
+                    path: b_path,
+                    source: b_source,
+                },
+            ) => a_stage == b_stage && a_path == b_path && a_source == b_source,
             (DJWavFixerError::FromUtf8Error(_), DJWavFixerError::FromUtf8Error(_)) => true,
             #[cfg(feature = "parallel")]
             (DJWavFixerError::ThreadPoolError(a), DJWavFixerError::ThreadPoolError(b)) => {
@@ -2,16 +2,19 @@ use rayon::iter::ParallelIterator;
 use rayon::prelude::IntoParallelRefIterator;
 use std::fs;
 use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::io::{BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
 
 use crate::DJWavFixerError;
+use crate::diagnostics::{Diagnostics, ParseMode};
 use crate::errors::Result;
 use crate::file_loader::get_distinct_wav_files;
-use crate::riff_parser::{FMT_MAGIC, RIFF_MAGIC, RiffFile, WAVE_MAGIC};
+use crate::metadata::Metadata;
+use crate::riff_parser::{FMT_MAGIC, RIFF_CHUNK_HEADER_SIZE, RIFF_MAGIC, RiffFile, WAVE_MAGIC};
 use crate::wav_file::{WavFile, WavFileLoadStatus, WaveFormatExtensible};
+use crate::wav_fixer::BACKUP_DIRECTORY_NAME;
 
-fn _load_riff_file(path: &PathBuf) -> Result<RiffFile<BufReader<File>>> {
+fn _load_riff_file(path: &PathBuf, mode: ParseMode) -> Result<RiffFile<BufReader<File>>> {
     let single_file = File::open(path)?;
     let file_size = single_file.metadata()?.len();
 
@@ -20,13 +23,27 @@ fn _load_riff_file(path: &PathBuf) -> Result<RiffFile<BufReader<File>>> {
     RiffFile::try_new(
         reader,
         file_size - RIFF_MAGIC.len() as u64 - FMT_MAGIC.len() as u64,
+        mode,
     )
 }
 
-fn parse_wav_format(riff_file: &mut RiffFile<BufReader<File>>) -> Result<WaveFormatExtensible> {
+fn parse_wav_format<R: Read + Seek>(
+    riff_file: &mut RiffFile<R>,
+    mode: ParseMode,
+) -> Result<WaveFormatExtensible> {
+    let mut diagnostics = Diagnostics::new(mode);
+    let wave_format_info = parse_wav_format_with(riff_file, &mut diagnostics);
+    riff_file.add_diagnostics(diagnostics);
+    wave_format_info
+}
+
+fn parse_wav_format_with<R: Read + Seek>(
+    riff_file: &mut RiffFile<R>,
+    diagnostics: &mut Diagnostics,
+) -> Result<WaveFormatExtensible> {
     let (reader, chunk) =
         riff_file
-            .get_chunk_and_reader(&RIFF_MAGIC)
+            .get_riff_chunk_and_reader()
             .ok_or(DJWavFixerError::RiffHeaderError(
                 "Missing 'RIFF' chunk".to_string(),
             ))?;
@@ -44,31 +61,80 @@ fn parse_wav_format(riff_file: &mut RiffFile<BufReader<File>>) -> Result<WaveFor
                 "Missing 'fmt ' subchunk".to_string(),
             ))?;
 
-    WaveFormatExtensible::try_from(fmt_subchunk.read_data(reader)?)
+    let fmt_offset = fmt_subchunk.position() + RIFF_CHUNK_HEADER_SIZE as u64;
+    WaveFormatExtensible::parse(fmt_subchunk.read_data(reader)?, fmt_offset, diagnostics)
 }
 
-pub fn load_wav_file(path: &PathBuf) -> WavFile<BufReader<File>> {
-    let riff_file = _load_riff_file(path);
-
-    WavFile {
-        path: path.clone(),
-        load_status: match riff_file {
-            Ok(mut riff_file) => match parse_wav_format(&mut riff_file) {
-                Ok(wave_format_info) => WavFileLoadStatus::Success {
+fn wav_file_from_riff_file<R: Read + Seek>(
+    path: &Path,
+    riff_file: Result<RiffFile<R>>,
+    mode: ParseMode,
+) -> WavFile<R> {
+    let mut metadata = Metadata::default();
+    let load_status = match riff_file {
+        Ok(mut riff_file) => match parse_wav_format(&mut riff_file, mode) {
+            Ok(wave_format_info) => {
+                metadata = Metadata::read(&mut riff_file, wave_format_info.sample_rate);
+                WavFileLoadStatus::Success {
                     riff_file,
                     wave_format_info,
-                },
-                Err(error) => WavFileLoadStatus::WavFileInvalid { riff_file, error },
-            },
-            Err(error) => WavFileLoadStatus::RiffFileInvalid { error },
+                }
+            }
+            Err(error) => WavFileLoadStatus::WavFileInvalid { riff_file, error },
         },
+        Err(error) => WavFileLoadStatus::RiffFileInvalid { error },
+    };
+
+    WavFile {
+        path: path.to_path_buf(),
+        load_status,
+        metadata,
+        fix_report: None,
     }
 }
 
+pub fn load_wav_file(path: &PathBuf) -> WavFile<BufReader<File>> {
+    load_wav_file_with_mode(path, ParseMode::Strict)
+}
+
+/// Loads a WAV file, parsing its headers leniently if `mode` asks for it
+pub fn load_wav_file_with_mode(path: &PathBuf, mode: ParseMode) -> WavFile<BufReader<File>> {
+    wav_file_from_riff_file(path, _load_riff_file(path, mode), mode)
+}
+
+/// Loads a WAV file from an arbitrary reader, `path` is only used for reporting
+#[allow(unused)]
+pub(crate) fn load_wav_reader<R: Read + Seek>(
+    path: &Path,
+    mut reader: R,
+    mode: ParseMode,
+) -> Result<WavFile<R>> {
+    let data_size = reader.seek(std::io::SeekFrom::End(0))?;
+    reader.rewind()?;
+
+    Ok(wav_file_from_riff_file(
+        path,
+        RiffFile::try_new(
+            reader,
+            data_size - RIFF_MAGIC.len() as u64 - FMT_MAGIC.len() as u64,
+            mode,
+        ),
+        mode,
+    ))
+}
+
 pub fn load_wav_files(files: &[PathBuf]) -> Result<Vec<WavFile<BufReader<File>>>> {
+    load_wav_files_with_mode(files, ParseMode::Strict)
+}
+
+/// Loads WAV files, parsing their headers leniently if `mode` asks for it
+pub fn load_wav_files_with_mode(
+    files: &[PathBuf],
+    mode: ParseMode,
+) -> Result<Vec<WavFile<BufReader<File>>>> {
     Ok(get_distinct_wav_files(files)?
         .iter()
-        .map(load_wav_file)
+        .map(|file| load_wav_file_with_mode(file, mode))
         .collect())
 }
 
@@ -76,11 +142,25 @@ pub fn load_wav_files(files: &[PathBuf]) -> Result<Vec<WavFile<BufReader<File>>>
 pub fn load_wav_files_rayon(
     files: &[PathBuf],
     rayon_pool: &rayon::ThreadPool,
+) -> Result<Vec<WavFile<BufReader<File>>>> {
+    load_wav_files_rayon_with_mode(files, rayon_pool, ParseMode::Strict)
+}
+
+/// Loads WAV files on `rayon_pool`, parsing their headers leniently if `mode` asks for it
+#[cfg(feature = "parallel")]
+pub fn load_wav_files_rayon_with_mode(
+    files: &[PathBuf],
+    rayon_pool: &rayon::ThreadPool,
+    mode: ParseMode,
 ) -> Result<Vec<WavFile<BufReader<File>>>> {
     let distinct_files = get_distinct_wav_files(files)?;
 
     Ok(rayon_pool
-        .install(|| distinct_files.par_iter().map(load_wav_file))
+        .install(|| {
+            distinct_files
+                .par_iter()
+                .map(|file| load_wav_file_with_mode(file, mode))
+        })
         .collect())
 }
 
@@ -94,7 +174,10 @@ pub fn get_all_wav_files_in_directory(
             let path = entry.path();
             let file_type = entry.file_type()?;
 
-            if file_type.is_dir() && recursive {
+            if file_type.is_dir() && entry.file_name() == BACKUP_DIRECTORY_NAME {
+                // Never treat our own backups as part of the library
+                Ok(None)
+            } else if file_type.is_dir() && recursive {
                 // Recursively collect from subdirectories
                 let dir_files = get_all_wav_files_in_directory(&path, true)?;
                 Ok((!dir_files.is_empty()).then_some(dir_files))
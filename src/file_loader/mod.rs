@@ -5,7 +5,8 @@ use std::path::PathBuf;
 use crate::errors::Result;
 
 pub use blocking_loader::{
-    get_all_wav_files_in_directory, load_wav_file, load_wav_files, load_wav_files_rayon,
+    get_all_wav_files_in_directory, load_wav_file, load_wav_file_with_mode, load_wav_files,
+    load_wav_files_rayon, load_wav_files_rayon_with_mode, load_wav_files_with_mode,
 };
 
 pub(crate) mod blocking_loader;
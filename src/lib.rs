@@ -1,10 +1,23 @@
+mod diagnostics;
 mod errors;
 mod file_loader;
+mod metadata;
 mod riff_parser;
 mod wav_file;
+mod wav_fixer;
 
+pub use diagnostics::{Diagnostic, HeaderField, ParseMode, Severity};
 pub use errors::{DJWavFixerError, Result};
 pub use file_loader::*;
+pub use metadata::{
+    Acid, BeatGridMarker, BroadcastExtension, Cart, CartTimer, CuePoint, Id3Frame, Id3Tag, InfoTag,
+    Ixml, IxmlTrack, LoopType, Metadata, SampleLoop, Sampler, SeratoBeatGrid, SeratoCue,
+    SeratoLoop, SeratoMarkers, XmlElement,
+};
 pub use wav_file::WavFile;
+pub use wav_fixer::{
+    BackupMode, ClipHandling, DitherMode, FixOptions, FixReport, OutputTreeAction, TargetBitDepth,
+    UnfixedFileAction, UnknownChannelLayout, WriteStage,
+};
 
 const DWORD_SIZE: usize = 4;
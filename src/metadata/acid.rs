@@ -0,0 +1,142 @@
+use std::fmt::Write;
+
+use crate::DWORD_SIZE;
+use crate::errors::{DJWavFixerError, Result};
+use crate::metadata::FixedFields;
+use crate::metadata::cues::note_name;
+
+pub(crate) const ACID_MAGIC: [u8; DWORD_SIZE] = *b"acid";
+
+const ACID_SIZE: usize = 24;
+const FLAG_ONE_SHOT: u32 = 0x01;
+const FLAG_ROOT_NOTE: u32 = 0x02;
+const FLAG_STRETCH: u32 = 0x04;
+const FLAG_DISK_BASED: u32 = 0x08;
+
+/// Tempo and beat information from an ACID `acid` chunk
+#[derive(Clone, Debug, PartialEq)]
+pub struct Acid {
+    flags: u32,
+    root_note: u16,
+    beats: u32,
+    meter_denominator: u16,
+    meter_numerator: u16,
+    tempo: f32,
+}
+
+impl Acid {
+    /// Whether the file plays once rather than looping
+    pub fn one_shot(&self) -> bool {
+        self.flags & FLAG_ONE_SHOT != 0
+    }
+
+    /// MIDI note the loop is in, `None` if it has no key
+    pub fn root_note(&self) -> Option<u16> {
+        (self.flags & FLAG_ROOT_NOTE != 0).then_some(self.root_note)
+    }
+
+    /// Whether the loop is stretched to the project tempo
+    pub fn stretch(&self) -> bool {
+        self.flags & FLAG_STRETCH != 0
+    }
+
+    /// Whether the file is streamed from disk instead of loaded into memory
+    pub fn disk_based(&self) -> bool {
+        self.flags & FLAG_DISK_BASED != 0
+    }
+
+    pub fn beats(&self) -> u32 {
+        self.beats
+    }
+
+    /// Time signature as numerator and denominator
+    pub fn meter(&self) -> (u16, u16) {
+        (self.meter_numerator, self.meter_denominator)
+    }
+
+    /// Tempo in beats per minute
+    pub fn tempo(&self) -> f32 {
+        self.tempo
+    }
+
+    pub(crate) fn write_information(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "  ACID:")?;
+        writeln!(
+            writer,
+            "    Type: {}",
+            if self.one_shot() { "One-Shot" } else { "Loop" }
+        )?;
+        writeln!(writer, "    Tempo: {:.2} BPM", self.tempo)?;
+        writeln!(
+            writer,
+            "    Beats: {} in {}/{}",
+            self.beats, self.meter_numerator, self.meter_denominator
+        )?;
+        if let Some(root_note) = self.root_note() {
+            writeln!(writer, "    Root Note: {}", note_name(root_note as u32))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<&[u8]> for Acid {
+    type Error = DJWavFixerError;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        if data.len() < ACID_SIZE {
+            return Err(DJWavFixerError::MetadataError(format!(
+                "'acid' chunk holds {} bytes, expected {}",
+                data.len(),
+                ACID_SIZE
+            )));
+        }
+
+        let mut fields = FixedFields::new(data);
+        let flags = fields.u32();
+        let root_note = fields.u16();
+        // Two fields with no known meaning
+        fields.bytes(6);
+        let beats = fields.u32();
+        let meter_denominator = fields.u16();
+        let meter_numerator = fields.u16();
+        let tempo = f32::from_bits(fields.u32());
+
+        Ok(Self {
+            flags,
+            root_note,
+            beats,
+            meter_denominator,
+            meter_numerator,
+            tempo,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_acid() {
+        let data = [
+            &(FLAG_ROOT_NOTE | FLAG_STRETCH).to_le_bytes()[..],
+            &57u16.to_le_bytes(),
+            &0x8000u16.to_le_bytes(),
+            &0f32.to_le_bytes(),
+            &8u32.to_le_bytes(),
+            &4u16.to_le_bytes(),
+            &4u16.to_le_bytes(),
+            &124.5f32.to_le_bytes(),
+        ]
+        .concat();
+
+        let acid = Acid::try_from(data.as_slice()).expect("Failed to parse acid");
+        assert!(!acid.one_shot());
+        assert!(acid.stretch());
+        assert_eq!(acid.root_note(), Some(57));
+        assert_eq!(acid.beats(), 8);
+        assert_eq!(acid.meter(), (4, 4));
+        assert_eq!(acid.tempo(), 124.5);
+    }
+}
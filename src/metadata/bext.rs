@@ -0,0 +1,263 @@
+use std::fmt::Write;
+
+use crate::errors::{DJWavFixerError, Result};
+#[cfg(test)]
+use crate::metadata::PutField;
+use crate::metadata::{FixedFields, decode_text};
+
+const DESCRIPTION_SIZE: usize = 256;
+const ORIGINATOR_SIZE: usize = 32;
+const ORIGINATOR_REFERENCE_SIZE: usize = 32;
+const DATE_SIZE: usize = 10;
+const TIME_SIZE: usize = 8;
+const UMID_SIZE: usize = 64;
+const RESERVED_SIZE: usize = 180;
+/// Offset of the time reference, after the text fields
+const TIME_REFERENCE_OFFSET: usize = 338;
+/// Size of everything before the coding history
+const FIXED_SIZE: usize = 602;
+/// Loudness fields hold this when they were not measured
+const LOUDNESS_UNSET: i16 = 0x7FFF;
+
+/// A Broadcast Wave Format `bext` chunk, as in EBU Tech 3285
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BroadcastExtension {
+    pub description: String,
+    pub originator: String,
+    pub originator_reference: String,
+    /// `yyyy-mm-dd`
+    pub origination_date: String,
+    /// `hh:mm:ss`
+    pub origination_time: String,
+    /// Samples since midnight at the first sample of the recording
+    pub time_reference: u64,
+    pub version: u16,
+    /// SMPTE UMID, 64 bytes
+    pub umid: Vec<u8>,
+    /// Integrated loudness in hundredths of LUFS, from version 2 on
+    pub loudness_value: Option<i16>,
+    /// In hundredths of LU
+    pub loudness_range: Option<i16>,
+    /// In hundredths of dBTP
+    pub max_true_peak_level: Option<i16>,
+    /// In hundredths of LUFS
+    pub max_momentary_loudness: Option<i16>,
+    /// In hundredths of LUFS
+    pub max_short_term_loudness: Option<i16>,
+    /// Lines describing each step the audio went through, as in EBU R98
+    pub coding_history: String,
+}
+
+impl BroadcastExtension {
+    #[cfg(test)]
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(FIXED_SIZE + self.coding_history.len());
+        data.put_text(&self.description, DESCRIPTION_SIZE);
+        data.put_text(&self.originator, ORIGINATOR_SIZE);
+        data.put_text(&self.originator_reference, ORIGINATOR_REFERENCE_SIZE);
+        data.put_text(&self.origination_date, DATE_SIZE);
+        data.put_text(&self.origination_time, TIME_SIZE);
+        data.extend_from_slice(&self.time_reference.to_le_bytes());
+        data.extend_from_slice(&self.version.to_le_bytes());
+        data.put_bytes(&self.umid, UMID_SIZE);
+        if self.version >= 2 {
+            for loudness in [
+                self.loudness_value,
+                self.loudness_range,
+                self.max_true_peak_level,
+                self.max_momentary_loudness,
+                self.max_short_term_loudness,
+            ] {
+                data.extend_from_slice(&loudness.unwrap_or(LOUDNESS_UNSET).to_le_bytes());
+            }
+        }
+        data.resize(FIXED_SIZE, 0);
+        data.extend_from_slice(self.coding_history.as_bytes());
+        data
+    }
+
+    pub(crate) fn write_information(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "  Broadcast Extension:")?;
+        for (name, value) in [
+            ("Description", &self.description),
+            ("Originator", &self.originator),
+            ("Originator Reference", &self.originator_reference),
+        ] {
+            if !value.is_empty() {
+                writeln!(writer, "    {}: {}", name, value)?;
+            }
+        }
+        if !self.origination_date.is_empty() {
+            writeln!(
+                writer,
+                "    Origination: {} {}",
+                self.origination_date, self.origination_time
+            )?;
+        }
+        writeln!(
+            writer,
+            "    Time Reference: {} samples",
+            self.time_reference
+        )?;
+        if let Some(loudness_value) = self.loudness_value {
+            writeln!(
+                writer,
+                "    Loudness: {:.2} LUFS",
+                loudness_value as f64 / 100.0
+            )?;
+        }
+        if let Some(max_true_peak_level) = self.max_true_peak_level {
+            writeln!(
+                writer,
+                "    Max True Peak: {:.2} dBTP",
+                max_true_peak_level as f64 / 100.0
+            )?;
+        }
+        for line in self.coding_history.lines().filter(|line| !line.is_empty()) {
+            writeln!(writer, "    Coding History: {}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Updates a `bext` body for audio resampled from `source_rate` to `target_rate` and appends
+/// `line` to its coding history. Every other byte is kept as it was, text in any encoding included
+pub(crate) fn update_bext(
+    data: &[u8],
+    source_rate: u32,
+    target_rate: u32,
+    line: &str,
+) -> Result<Vec<u8>> {
+    if data.len() < FIXED_SIZE {
+        return Err(DJWavFixerError::MetadataError(format!(
+            "'bext' chunk holds {} bytes, expected at least {}",
+            data.len(),
+            FIXED_SIZE
+        )));
+    }
+
+    let time_reference_field = TIME_REFERENCE_OFFSET..TIME_REFERENCE_OFFSET + 8;
+    let time_reference = u64::from_le_bytes(unsafe {
+        data[time_reference_field.clone()]
+            .try_into()
+            .unwrap_unchecked()
+    });
+    let time_reference =
+        (time_reference as u128 * target_rate as u128 / source_rate.max(1) as u128) as u64;
+
+    // Writers pad the coding history out with NULs, the new line goes after the text
+    let history_end = data[FIXED_SIZE..]
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .map_or(FIXED_SIZE, |index| FIXED_SIZE + index + 1);
+    let mut updated = Vec::with_capacity(history_end + line.len() + 4);
+    updated.extend_from_slice(&data[..history_end]);
+    updated[time_reference_field].copy_from_slice(&time_reference.to_le_bytes());
+    if history_end > FIXED_SIZE && !updated.ends_with(b"\r\n") {
+        updated.extend_from_slice(b"\r\n");
+    }
+    updated.extend_from_slice(line.as_bytes());
+    updated.extend_from_slice(b"\r\n");
+
+    Ok(updated)
+}
+
+impl TryFrom<&[u8]> for BroadcastExtension {
+    type Error = DJWavFixerError;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        if data.len() < FIXED_SIZE {
+            return Err(DJWavFixerError::MetadataError(format!(
+                "'bext' chunk holds {} bytes, expected at least {}",
+                data.len(),
+                FIXED_SIZE
+            )));
+        }
+
+        let mut fields = FixedFields::new(data);
+        let description = fields.text(DESCRIPTION_SIZE);
+        let originator = fields.text(ORIGINATOR_SIZE);
+        let originator_reference = fields.text(ORIGINATOR_REFERENCE_SIZE);
+        let origination_date = fields.text(DATE_SIZE);
+        let origination_time = fields.text(TIME_SIZE);
+        let time_reference = fields.u64();
+        let version = fields.u16();
+        let umid = fields.bytes(UMID_SIZE).to_vec();
+        // Version 1 and earlier kept these bytes reserved
+        let mut loudness =
+            || Some(fields.i16()).filter(|value| version >= 2 && *value != LOUDNESS_UNSET);
+        let loudness_value = loudness();
+        let loudness_range = loudness();
+        let max_true_peak_level = loudness();
+        let max_momentary_loudness = loudness();
+        let max_short_term_loudness = loudness();
+        fields.bytes(RESERVED_SIZE);
+
+        Ok(Self {
+            description,
+            originator,
+            originator_reference,
+            origination_date,
+            origination_time,
+            time_reference,
+            version,
+            umid,
+            loudness_value,
+            loudness_range,
+            max_true_peak_level,
+            max_momentary_loudness,
+            max_short_term_loudness,
+            coding_history: decode_text(fields.rest()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bext_round_trip() {
+        let bext = BroadcastExtension {
+            description: "Interview".to_string(),
+            originator: "Studio 2".to_string(),
+            origination_date: "2024-05-01".to_string(),
+            origination_time: "10:30:00".to_string(),
+            time_reference: 48000 * 3600,
+            version: 2,
+            umid: vec![7; UMID_SIZE],
+            loudness_value: Some(-2300),
+            max_true_peak_level: Some(-100),
+            coding_history: "A=PCM,F=48000,W=24,M=stereo\r\n".to_string(),
+            ..Default::default()
+        };
+
+        let data = bext.to_bytes();
+        assert_eq!(data.len(), FIXED_SIZE + bext.coding_history.len());
+        assert_eq!(BroadcastExtension::try_from(data.as_slice()), Ok(bext));
+    }
+
+    #[test]
+    fn test_update_bext_keeps_original_bytes() {
+        let mut data = BroadcastExtension {
+            time_reference: 96000,
+            version: 1,
+            ..Default::default()
+        }
+        .to_bytes();
+        // Latin-1 text filling its field, a reserved byte and history past an embedded NUL
+        data[..DESCRIPTION_SIZE].fill(0xE9);
+        data[FIXED_SIZE - 1] = 0x42;
+        data.extend_from_slice(b"A=PCM,F=96000\r\n\0T=old\0\0");
+
+        let updated = update_bext(&data, 96000, 44100, "A=PCM,F=44100,W=16,M=stereo")
+            .expect("Failed to update bext");
+
+        let mut expected = data[..data.len() - 2].to_vec();
+        expected[TIME_REFERENCE_OFFSET..TIME_REFERENCE_OFFSET + 8]
+            .copy_from_slice(&44100u64.to_le_bytes());
+        expected.extend_from_slice(b"\r\nA=PCM,F=44100,W=16,M=stereo\r\n");
+        assert_eq!(updated, expected);
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt::Write;
+
+use crate::DWORD_SIZE;
+use crate::errors::{DJWavFixerError, Result};
+use crate::metadata::{FixedFields, PutField, decode_text};
+
+const VERSION_SIZE: usize = 4;
+const TEXT_SIZE: usize = 64;
+const DATE_SIZE: usize = 10;
+const TIME_SIZE: usize = 8;
+const POST_TIMER_COUNT: usize = 8;
+const RESERVED_SIZE: usize = 276;
+const URL_SIZE: usize = 1024;
+/// Size of everything before the tag text
+const FIXED_SIZE: usize = 2048;
+
+/// A marker in a `cart` chunk, such as the end of the intro
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CartTimer {
+    /// What the timer marks, such as `INT1` or `SEC1`
+    pub usage: [u8; DWORD_SIZE],
+    /// Position in samples from the start of the audio
+    pub value: u32,
+}
+
+/// A radio traffic `cart` chunk, as in AES46
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Cart {
+    pub version: String,
+    pub title: String,
+    pub artist: String,
+    pub cut_id: String,
+    pub client_id: String,
+    pub category: String,
+    pub classification: String,
+    pub out_cue: String,
+    pub start_date: String,
+    pub start_time: String,
+    pub end_date: String,
+    pub end_time: String,
+    pub producer_app_id: String,
+    pub producer_app_version: String,
+    pub user_def: String,
+    /// Sample value of 0 dB full scale
+    pub level_reference: i32,
+    /// Timers in use, at most eight
+    pub post_timers: Vec<CartTimer>,
+    pub url: String,
+    pub tag_text: String,
+}
+
+impl Cart {
+    /// Rescales the post timers for audio resampled from `source_rate` to `target_rate`
+    pub(crate) fn resample(&mut self, source_rate: u32, target_rate: u32) {
+        for timer in &mut self.post_timers {
+            timer.value =
+                (timer.value as u64 * target_rate as u64 / source_rate.max(1) as u64) as u32;
+        }
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(FIXED_SIZE + self.tag_text.len());
+        data.put_text(&self.version, VERSION_SIZE);
+        for text in [
+            &self.title,
+            &self.artist,
+            &self.cut_id,
+            &self.client_id,
+            &self.category,
+            &self.classification,
+            &self.out_cue,
+        ] {
+            data.put_text(text, TEXT_SIZE);
+        }
+        data.put_text(&self.start_date, DATE_SIZE);
+        data.put_text(&self.start_time, TIME_SIZE);
+        data.put_text(&self.end_date, DATE_SIZE);
+        data.put_text(&self.end_time, TIME_SIZE);
+        data.put_text(&self.producer_app_id, TEXT_SIZE);
+        data.put_text(&self.producer_app_version, TEXT_SIZE);
+        data.put_text(&self.user_def, TEXT_SIZE);
+        data.extend_from_slice(&self.level_reference.to_le_bytes());
+        for index in 0..POST_TIMER_COUNT {
+            match self.post_timers.get(index) {
+                Some(timer) => {
+                    data.extend_from_slice(&timer.usage);
+                    data.extend_from_slice(&timer.value.to_le_bytes());
+                }
+                None => data.put_bytes(&[], 2 * DWORD_SIZE),
+            }
+        }
+        data.put_bytes(&[], RESERVED_SIZE);
+        data.put_text(&self.url, URL_SIZE);
+        data.extend_from_slice(self.tag_text.as_bytes());
+        data
+    }
+
+    pub(crate) fn write_information(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "  Cart:")?;
+        for (name, value) in [
+            ("Title", &self.title),
+            ("Artist", &self.artist),
+            ("Cut ID", &self.cut_id),
+            ("Client ID", &self.client_id),
+            ("Category", &self.category),
+            ("Classification", &self.classification),
+            ("Out Cue", &self.out_cue),
+            ("Producer", &self.producer_app_id),
+            ("URL", &self.url),
+        ] {
+            if !value.is_empty() {
+                writeln!(writer, "    {}: {}", name, value)?;
+            }
+        }
+        if !self.start_date.is_empty() {
+            writeln!(writer, "    Start: {} {}", self.start_date, self.start_time)?;
+        }
+        if !self.end_date.is_empty() {
+            writeln!(writer, "    End: {} {}", self.end_date, self.end_time)?;
+        }
+        for timer in &self.post_timers {
+            writeln!(
+                writer,
+                "    Timer {}: {} samples",
+                String::from_utf8_lossy(&timer.usage),
+                timer.value
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<&[u8]> for Cart {
+    type Error = DJWavFixerError;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        if data.len() < FIXED_SIZE {
+            return Err(DJWavFixerError::MetadataError(format!(
+                "'cart' chunk holds {} bytes, expected at least {}",
+                data.len(),
+                FIXED_SIZE
+            )));
+        }
+
+        let mut fields = FixedFields::new(data);
+        let version = fields.text(VERSION_SIZE);
+        let title = fields.text(TEXT_SIZE);
+        let artist = fields.text(TEXT_SIZE);
+        let cut_id = fields.text(TEXT_SIZE);
+        let client_id = fields.text(TEXT_SIZE);
+        let category = fields.text(TEXT_SIZE);
+        let classification = fields.text(TEXT_SIZE);
+        let out_cue = fields.text(TEXT_SIZE);
+        let start_date = fields.text(DATE_SIZE);
+        let start_time = fields.text(TIME_SIZE);
+        let end_date = fields.text(DATE_SIZE);
+        let end_time = fields.text(TIME_SIZE);
+        let producer_app_id = fields.text(TEXT_SIZE);
+        let producer_app_version = fields.text(TEXT_SIZE);
+        let user_def = fields.text(TEXT_SIZE);
+        let level_reference = fields.u32() as i32;
+        let post_timers = (0..POST_TIMER_COUNT)
+            .map(|_| CartTimer {
+                usage: unsafe { fields.bytes(DWORD_SIZE).try_into().unwrap_unchecked() },
+                value: fields.u32(),
+            })
+            // Unused timers have no usage
+            .filter(|timer| timer.usage.iter().any(|byte| *byte != 0))
+            .collect();
+        fields.bytes(RESERVED_SIZE);
+        let url = fields.text(URL_SIZE);
+
+        Ok(Self {
+            version,
+            title,
+            artist,
+            cut_id,
+            client_id,
+            category,
+            classification,
+            out_cue,
+            start_date,
+            start_time,
+            end_date,
+            end_time,
+            producer_app_id,
+            producer_app_version,
+            user_def,
+            level_reference,
+            post_timers,
+            url,
+            tag_text: decode_text(fields.rest()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cart_round_trip() {
+        let mut cart = Cart {
+            version: "0101".to_string(),
+            title: "Morning Jingle".to_string(),
+            cut_id: "J0042".to_string(),
+            start_date: "2024-01-01".to_string(),
+            start_time: "00:00:00".to_string(),
+            level_reference: 32768,
+            post_timers: vec![CartTimer {
+                usage: *b"INT1",
+                value: 96000,
+            }],
+            tag_text: "<tags/>".to_string(),
+            ..Default::default()
+        };
+
+        let data = cart.to_bytes();
+        assert_eq!(data.len(), FIXED_SIZE + cart.tag_text.len());
+        assert_eq!(Cart::try_from(data.as_slice()).as_ref(), Ok(&cart));
+
+        cart.resample(96000, 48000);
+        assert_eq!(cart.post_timers[0].value, 48000);
+    }
+}
@@ -0,0 +1,461 @@
+//! Markers and loops from `cue `, `smpl` and the labels in `LIST/adtl`
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::{Read, Seek};
+
+use crate::DWORD_SIZE;
+use crate::errors::{DJWavFixerError, Result};
+use crate::metadata::{FixedFields, decode_text};
+use crate::riff_parser::RiffSubchunk;
+
+pub(crate) const CUE_MAGIC: [u8; DWORD_SIZE] = *b"cue ";
+pub(crate) const SMPL_MAGIC: [u8; DWORD_SIZE] = *b"smpl";
+pub(crate) const PLST_MAGIC: [u8; DWORD_SIZE] = *b"plst";
+pub(crate) const ADTL_LIST_TYPE: [u8; DWORD_SIZE] = *b"adtl";
+const LTXT_MAGIC: [u8; DWORD_SIZE] = *b"ltxt";
+
+const CUE_POINT_SIZE: usize = 24;
+const SMPL_HEADER_SIZE: usize = 36;
+const SAMPLE_LOOP_SIZE: usize = 24;
+const PLAYLIST_SEGMENT_SIZE: usize = 12;
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Name of a MIDI note, such as `C4` for middle C
+pub(crate) fn note_name(note: u32) -> String {
+    format!(
+        "{}{}",
+        NOTE_NAMES[note as usize % NOTE_NAMES.len()],
+        note as i64 / 12 - 1
+    )
+}
+
+fn to_seconds(samples: u64, sample_rate: u32) -> f64 {
+    samples as f64 / sample_rate.max(1) as f64
+}
+
+/// Replaces the little-endian DWORD at `offset` with `update` of it, saturating at `u32::MAX`
+fn update_dword(data: &mut [u8], offset: usize, update: impl FnOnce(u64) -> u64) {
+    let field = &mut data[offset..offset + DWORD_SIZE];
+    let value = u32::from_le_bytes(unsafe { field.try_into().unwrap_unchecked() }) as u64;
+    field.copy_from_slice(&(update(value).min(u32::MAX as u64) as u32).to_le_bytes());
+}
+
+/// `value * numerator / denominator`, rounded to the nearest whole number
+fn rescaled(value: u64, numerator: u32, denominator: u32) -> u64 {
+    let denominator = denominator.max(1) as u64;
+    (value * numerator as u64 + denominator / 2) / denominator
+}
+
+fn too_short(id: [u8; DWORD_SIZE], length: usize, expected: usize) -> DJWavFixerError {
+    DJWavFixerError::MetadataError(format!(
+        "'{}' chunk holds {} bytes, expected at least {}",
+        String::from_utf8_lossy(&id),
+        length,
+        expected
+    ))
+}
+
+/// Rescales the sample positions in the body of a `cue `, `smpl`, `plst` or `LIST/adtl` subchunk
+/// for audio resampled from `source_rate` to `target_rate`, leaving everything else as it is
+pub(crate) fn resample_positions(
+    id: [u8; DWORD_SIZE],
+    data: &mut [u8],
+    source_rate: u32,
+    target_rate: u32,
+) -> Result<()> {
+    let rescale = |data: &mut [u8], offset| {
+        update_dword(data, offset, |samples| {
+            rescaled(samples, target_rate, source_rate)
+        })
+    };
+    match id {
+        CUE_MAGIC => {
+            if data.len() < DWORD_SIZE {
+                return Err(too_short(id, data.len(), DWORD_SIZE));
+            }
+            let count = FixedFields::new(data).u32() as usize;
+            for index in 0..count.min((data.len() - DWORD_SIZE) / CUE_POINT_SIZE) {
+                let cue_point = DWORD_SIZE + index * CUE_POINT_SIZE;
+                // Position in the play order and offset into the audio
+                rescale(data, cue_point + DWORD_SIZE);
+                rescale(data, cue_point + 5 * DWORD_SIZE);
+            }
+        }
+        SMPL_MAGIC => {
+            if data.len() < SMPL_HEADER_SIZE {
+                return Err(too_short(id, data.len(), SMPL_HEADER_SIZE));
+            }
+            // Nanoseconds a sample lasts, which shrinks as the sample rate grows
+            update_dword(data, 2 * DWORD_SIZE, |period| {
+                rescaled(period, source_rate, target_rate)
+            });
+            let count = FixedFields::new(&data[7 * DWORD_SIZE..]).u32() as usize;
+            for index in 0..count.min((data.len() - SMPL_HEADER_SIZE) / SAMPLE_LOOP_SIZE) {
+                let sample_loop = SMPL_HEADER_SIZE + index * SAMPLE_LOOP_SIZE;
+                rescale(data, sample_loop + 2 * DWORD_SIZE);
+                // The end is the last sample played, rescaling the one after it keeps the
+                // loop's length
+                update_dword(data, sample_loop + 3 * DWORD_SIZE, |end| {
+                    rescaled(end + 1, target_rate, source_rate).saturating_sub(1)
+                });
+            }
+        }
+        PLST_MAGIC => {
+            if data.len() < DWORD_SIZE {
+                return Err(too_short(id, data.len(), DWORD_SIZE));
+            }
+            let count = FixedFields::new(data).u32() as usize;
+            for index in 0..count.min((data.len() - DWORD_SIZE) / PLAYLIST_SEGMENT_SIZE) {
+                // Length of the segment in samples
+                rescale(
+                    data,
+                    DWORD_SIZE + index * PLAYLIST_SEGMENT_SIZE + DWORD_SIZE,
+                );
+            }
+        }
+        // Only `ltxt` holds a length in samples, `labl` and `note` are text
+        _ if data.starts_with(&ADTL_LIST_TYPE) => {
+            let mut position = DWORD_SIZE;
+            while position + 2 * DWORD_SIZE <= data.len() {
+                let mut header = FixedFields::new(&data[position..]);
+                let subchunk_id = header.bytes(DWORD_SIZE);
+                let size = header.u32() as usize;
+                let body = position + 2 * DWORD_SIZE;
+                if subchunk_id == LTXT_MAGIC && size >= 2 * DWORD_SIZE && body + size <= data.len()
+                {
+                    rescale(data, body + DWORD_SIZE);
+                }
+                position = body.saturating_add(size).saturating_add(size % 2);
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Text attached to cue points by `labl` and `note` subchunks, keyed by cue point ID
+#[derive(Debug, Default)]
+pub(crate) struct CueLabels {
+    labels: HashMap<u32, String>,
+    notes: HashMap<u32, String>,
+}
+
+impl CueLabels {
+    /// Reads the `labl` and `note` subchunks of a `LIST/adtl`
+    pub(crate) fn read<R: Read + Seek>(
+        &mut self,
+        list: &mut RiffSubchunk,
+        reader: &mut R,
+    ) -> Result<()> {
+        for subchunk in list.children_mut() {
+            let texts = match &subchunk.id() {
+                b"labl" => &mut self.labels,
+                b"note" => &mut self.notes,
+                _ => continue,
+            };
+
+            let data = subchunk.read_data(reader)?;
+            if data.len() < DWORD_SIZE {
+                continue;
+            }
+            let mut fields = FixedFields::new(data);
+            let cue_point_id = fields.u32();
+            texts
+                .entry(cue_point_id)
+                .or_insert_with(|| decode_text(fields.rest()));
+        }
+
+        Ok(())
+    }
+}
+
+/// A marker from the `cue ` chunk
+#[derive(Clone, Debug, PartialEq)]
+pub struct CuePoint {
+    id: u32,
+    position: u64,
+    sample_rate: u32,
+    label: Option<String>,
+    note: Option<String>,
+}
+
+impl CuePoint {
+    /// Identifies the cue point to loops and labels
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Position in samples from the start of the audio
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn position_seconds(&self) -> f64 {
+        to_seconds(self.position, self.sample_rate)
+    }
+
+    /// Name from the `labl` subchunk
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Comment from the `note` subchunk
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+}
+
+/// Reads the cue points of a `cue ` chunk, with their labels
+pub(crate) fn read_cue_points(
+    data: &[u8],
+    labels: &CueLabels,
+    sample_rate: u32,
+) -> Result<Vec<CuePoint>> {
+    if data.len() < DWORD_SIZE {
+        return Err(DJWavFixerError::MetadataError(
+            "'cue ' chunk is too short for its cue point count".to_string(),
+        ));
+    }
+
+    let mut fields = FixedFields::new(data);
+    let count = fields.u32() as usize;
+    // Trust the chunk size over a count that would run past it
+    let count = count.min(fields.rest().len() / CUE_POINT_SIZE);
+
+    Ok((0..count)
+        .map(|_| {
+            let id = fields.u32();
+            // Play order position, data chunk ID, chunk start and block start only matter for
+            // compressed or playlist audio
+            fields.bytes(4 * DWORD_SIZE);
+            let sample_offset = fields.u32();
+            CuePoint {
+                id,
+                position: sample_offset as u64,
+                sample_rate,
+                label: labels.labels.get(&id).cloned(),
+                note: labels.notes.get(&id).cloned(),
+            }
+        })
+        .collect())
+}
+
+/// How a sampler plays a loop
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LoopType {
+    Forward,
+    /// Forward then backward
+    Alternating,
+    Backward,
+    Other(u32),
+}
+
+impl From<u32> for LoopType {
+    fn from(loop_type: u32) -> Self {
+        match loop_type {
+            0 => LoopType::Forward,
+            1 => LoopType::Alternating,
+            2 => LoopType::Backward,
+            other => LoopType::Other(other),
+        }
+    }
+}
+
+/// A loop from the `smpl` chunk
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampleLoop {
+    cue_point_id: u32,
+    loop_type: LoopType,
+    start: u64,
+    end: u64,
+    play_count: u32,
+    sample_rate: u32,
+    label: Option<String>,
+}
+
+impl SampleLoop {
+    /// Cue point the loop is labelled through
+    pub fn cue_point_id(&self) -> u32 {
+        self.cue_point_id
+    }
+
+    pub fn loop_type(&self) -> LoopType {
+        self.loop_type
+    }
+
+    /// First sample of the loop
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Last sample of the loop, which is played
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    pub fn start_seconds(&self) -> f64 {
+        to_seconds(self.start, self.sample_rate)
+    }
+
+    pub fn end_seconds(&self) -> f64 {
+        to_seconds(self.end, self.sample_rate)
+    }
+
+    /// Times the loop plays, `None` if it loops forever
+    pub fn play_count(&self) -> Option<u32> {
+        (self.play_count != 0).then_some(self.play_count)
+    }
+
+    /// Name from the `labl` subchunk of the loop's cue point
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+}
+
+/// Sampler settings from the `smpl` chunk
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sampler {
+    root_note: u32,
+    pitch_fraction: u32,
+    loops: Vec<SampleLoop>,
+}
+
+impl Sampler {
+    /// MIDI note the audio plays back unchanged at, 60 is middle C
+    pub fn root_note(&self) -> u32 {
+        self.root_note
+    }
+
+    /// Name of the root note, such as `C4` for middle C
+    pub fn root_note_name(&self) -> String {
+        note_name(self.root_note)
+    }
+
+    /// How far above the root note the audio is pitched, in cents
+    pub fn pitch_fraction_cents(&self) -> f64 {
+        self.pitch_fraction as f64 * 100.0 / (u32::MAX as f64 + 1.0)
+    }
+
+    pub fn loops(&self) -> &[SampleLoop] {
+        &self.loops
+    }
+
+    pub(crate) fn parse(data: &[u8], labels: &CueLabels, sample_rate: u32) -> Result<Self> {
+        if data.len() < SMPL_HEADER_SIZE {
+            return Err(DJWavFixerError::MetadataError(format!(
+                "'smpl' chunk holds {} bytes, expected at least {}",
+                data.len(),
+                SMPL_HEADER_SIZE
+            )));
+        }
+
+        let mut fields = FixedFields::new(data);
+        // Manufacturer, product and sample period
+        fields.bytes(3 * DWORD_SIZE);
+        let root_note = fields.u32();
+        let pitch_fraction = fields.u32();
+        // SMPTE format and offset
+        fields.bytes(2 * DWORD_SIZE);
+        let loop_count = fields.u32() as usize;
+        // Sampler specific data follows the loops
+        let _sampler_data_size = fields.u32();
+        // Trust the chunk size over a count that would run past it
+        let loop_count = loop_count.min(fields.rest().len() / SAMPLE_LOOP_SIZE);
+
+        let loops = (0..loop_count)
+            .map(|_| {
+                let cue_point_id = fields.u32();
+                let loop_type = fields.u32().into();
+                let start = fields.u32() as u64;
+                let end = fields.u32() as u64;
+                let _fraction = fields.u32();
+                let play_count = fields.u32();
+                SampleLoop {
+                    cue_point_id,
+                    loop_type,
+                    start,
+                    end,
+                    play_count,
+                    sample_rate,
+                    label: labels.labels.get(&cue_point_id).cloned(),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            root_note,
+            pitch_fraction,
+            loops,
+        })
+    }
+
+    pub(crate) fn write_information(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "  Sampler:")?;
+        writeln!(writer, "    Root Note: {}", self.root_note_name())?;
+        for sample_loop in &self.loops {
+            write!(
+                writer,
+                "    Loop {}: {:.3}s to {:.3}s ({} to {} samples), {:?}",
+                sample_loop.cue_point_id,
+                sample_loop.start_seconds(),
+                sample_loop.end_seconds(),
+                sample_loop.start,
+                sample_loop.end,
+                sample_loop.loop_type
+            )?;
+            match sample_loop.play_count() {
+                Some(play_count) => write!(writer, ", plays {} times", play_count)?,
+                None => write!(writer, ", plays forever")?,
+            }
+            match sample_loop.label() {
+                Some(label) => writeln!(writer, ", \"{}\"", label)?,
+                None => writeln!(writer)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn write_cue_points(cue_points: &[CuePoint], mut writer: impl Write) -> Result<()> {
+    writeln!(writer, "  Cue Points:")?;
+    for cue_point in cue_points {
+        write!(
+            writer,
+            "    {}: {:.3}s ({} samples)",
+            cue_point.id,
+            cue_point.position_seconds(),
+            cue_point.position
+        )?;
+        if let Some(label) = cue_point.label() {
+            write!(writer, " \"{}\"", label)?;
+        }
+        match cue_point.note() {
+            Some(note) => writeln!(writer, ", {}", note)?,
+            None => writeln!(writer)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_root_note_name() {
+        let sampler = |root_note| Sampler {
+            root_note,
+            pitch_fraction: 0,
+            loops: vec![],
+        };
+        assert_eq!(sampler(60).root_note_name(), "C4");
+        assert_eq!(sampler(69).root_note_name(), "A4");
+        assert_eq!(sampler(0).root_note_name(), "C-1");
+    }
+}
@@ -0,0 +1,630 @@
+//! ID3v2.3 and ID3v2.4 tags, stored by DJ software in an `id3 ` subchunk
+
+use std::fmt::Write;
+
+use crate::DWORD_SIZE;
+use crate::errors::{DJWavFixerError, Result};
+
+const ID3_MAGIC: [u8; 3] = *b"ID3";
+const HEADER_SIZE: usize = 10;
+
+const FLAG_UNSYNCHRONISATION: u8 = 0x80;
+const FLAG_EXTENDED_HEADER: u8 = 0x40;
+
+// Frame format flags, which moved between versions
+const V3_FRAME_COMPRESSED: u8 = 0x80;
+const V3_FRAME_ENCRYPTED: u8 = 0x40;
+const V3_FRAME_GROUPED: u8 = 0x20;
+const V4_FRAME_GROUPED: u8 = 0x40;
+const V4_FRAME_COMPRESSED: u8 = 0x08;
+const V4_FRAME_ENCRYPTED: u8 = 0x04;
+const V4_FRAME_UNSYNCHRONISED: u8 = 0x02;
+const V4_FRAME_DATA_LENGTH: u8 = 0x01;
+
+/// A decoded ID3v2 frame, frames the parser does not know keep their raw body
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Id3Frame {
+    /// A `T***` text frame, ID3v2.4 allows several values in one frame
+    Text {
+        id: [u8; DWORD_SIZE],
+        values: Vec<String>,
+    },
+    /// `TXXX`, a text frame named by its description
+    UserText {
+        description: String,
+        value: String,
+    },
+    /// `COMM`
+    Comment {
+        language: String,
+        description: String,
+        text: String,
+    },
+    /// `APIC`, an attached picture such as cover art
+    Picture {
+        mime_type: String,
+        picture_type: u8,
+        description: String,
+        data: Vec<u8>,
+    },
+    /// `GEOB`, a general encapsulated object, used by Serato for its cue and beat grid data
+    Object {
+        mime_type: String,
+        filename: String,
+        description: String,
+        data: Vec<u8>,
+    },
+    Other {
+        id: [u8; DWORD_SIZE],
+        data: Vec<u8>,
+    },
+}
+
+impl Id3Frame {
+    pub fn id(&self) -> [u8; DWORD_SIZE] {
+        match self {
+            Id3Frame::Text { id, .. } | Id3Frame::Other { id, .. } => *id,
+            Id3Frame::UserText { .. } => *b"TXXX",
+            Id3Frame::Comment { .. } => *b"COMM",
+            Id3Frame::Picture { .. } => *b"APIC",
+            Id3Frame::Object { .. } => *b"GEOB",
+        }
+    }
+
+    fn parse(id: [u8; DWORD_SIZE], body: &[u8]) -> Result<Self> {
+        if !matches!(&id, b"TXXX" | b"COMM" | b"APIC" | b"GEOB") && id[0] != b'T' {
+            return Ok(Id3Frame::Other {
+                id,
+                data: body.to_vec(),
+            });
+        }
+
+        let (&encoding, mut rest) = body.split_first().ok_or_else(|| {
+            DJWavFixerError::MetadataError(format!(
+                "ID3 frame {} is empty",
+                String::from_utf8_lossy(&id)
+            ))
+        })?;
+
+        Ok(match &id {
+            b"TXXX" => {
+                let description = take_text(&mut rest, encoding)?;
+                Id3Frame::UserText {
+                    description,
+                    value: decode_text(rest, encoding)?,
+                }
+            }
+            b"COMM" => {
+                let language = String::from_utf8_lossy(take_bytes(&mut rest, 3)?).into_owned();
+                let description = take_text(&mut rest, encoding)?;
+                Id3Frame::Comment {
+                    language,
+                    description,
+                    text: decode_text(rest, encoding)?,
+                }
+            }
+            b"APIC" => {
+                let mime_type = take_text(&mut rest, 0)?;
+                let picture_type = take_bytes(&mut rest, 1)?[0];
+                let description = take_text(&mut rest, encoding)?;
+                Id3Frame::Picture {
+                    mime_type,
+                    picture_type,
+                    description,
+                    data: rest.to_vec(),
+                }
+            }
+            b"GEOB" => {
+                let mime_type = take_text(&mut rest, 0)?;
+                let filename = take_text(&mut rest, encoding)?;
+                let description = take_text(&mut rest, encoding)?;
+                Id3Frame::Object {
+                    mime_type,
+                    filename,
+                    description,
+                    data: rest.to_vec(),
+                }
+            }
+            _ => Id3Frame::Text {
+                id,
+                values: split_terminated(rest, encoding)
+                    .into_iter()
+                    .map(|value| decode_text(value, encoding))
+                    .collect::<Result<_>>()?,
+            },
+        })
+    }
+
+    fn write_information(&self, mut writer: impl Write) -> Result<()> {
+        match self {
+            Id3Frame::Text { id, values } => {
+                writeln!(
+                    writer,
+                    "    {}: {}",
+                    String::from_utf8_lossy(id),
+                    values.join(" / ")
+                )?;
+            }
+            Id3Frame::UserText { description, value } => {
+                writeln!(writer, "    TXXX ({}): {}", description, value)?;
+            }
+            Id3Frame::Comment { text, .. } => writeln!(writer, "    COMM: {}", text)?,
+            Id3Frame::Picture {
+                mime_type, data, ..
+            } => writeln!(writer, "    APIC: {}, {} bytes", mime_type, data.len())?,
+            Id3Frame::Object {
+                mime_type,
+                description,
+                data,
+                ..
+            } => writeln!(
+                writer,
+                "    GEOB ({}): {}, {} bytes",
+                description,
+                mime_type,
+                data.len()
+            )?,
+            Id3Frame::Other { id, data } => writeln!(
+                writer,
+                "    {}: {} bytes",
+                String::from_utf8_lossy(id),
+                data.len()
+            )?,
+        }
+
+        Ok(())
+    }
+}
+
+/// An ID3v2 tag and its frames, in tag order
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Id3Tag {
+    major_version: u8,
+    frames: Vec<Id3Frame>,
+}
+
+impl Id3Tag {
+    /// 3 for ID3v2.3, 4 for ID3v2.4
+    pub fn major_version(&self) -> u8 {
+        self.major_version
+    }
+
+    pub fn frames(&self) -> &[Id3Frame] {
+        &self.frames
+    }
+
+    /// First value of the text frame with `id`, such as `TIT2` or `TBPM`
+    pub fn text(&self, id: &[u8; DWORD_SIZE]) -> Option<&str> {
+        self.frames.iter().find_map(|frame| match frame {
+            Id3Frame::Text {
+                id: frame_id,
+                values,
+            } if frame_id == id => values.first().map(String::as_str),
+            _ => None,
+        })
+    }
+
+    /// Data of the `GEOB` frame with `description`
+    pub fn object(&self, description: &str) -> Option<&[u8]> {
+        self.frames.iter().find_map(|frame| match frame {
+            Id3Frame::Object {
+                description: object_description,
+                data,
+                ..
+            } if object_description == description => Some(data.as_slice()),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn write_information(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(
+            writer,
+            "  ID3v2.{} Tag: {} frames",
+            self.major_version,
+            self.frames.len()
+        )?;
+        for frame in &self.frames {
+            frame.write_information(&mut writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<&[u8]> for Id3Tag {
+    type Error = DJWavFixerError;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE || data[..3] != ID3_MAGIC {
+            return Err(DJWavFixerError::MetadataError(
+                "ID3 chunk does not start with an ID3v2 header".to_string(),
+            ));
+        }
+
+        let major_version = data[3];
+        if !matches!(major_version, 3 | 4) {
+            return Err(DJWavFixerError::MetadataError(format!(
+                "ID3v2.{} tags are not supported",
+                major_version
+            )));
+        }
+
+        let flags = data[5];
+        let tag_size = syncsafe(&data[6..10]) as usize;
+        let mut body = data
+            .get(HEADER_SIZE..HEADER_SIZE + tag_size)
+            .ok_or_else(|| {
+                DJWavFixerError::MetadataError(format!(
+                    "ID3 tag declares {} bytes, but the chunk holds {}",
+                    tag_size,
+                    data.len() - HEADER_SIZE
+                ))
+            })?
+            .to_vec();
+
+        // ID3v2.3 unsynchronises the whole tag, ID3v2.4 does it frame by frame
+        if major_version == 3 && flags & FLAG_UNSYNCHRONISATION != 0 {
+            body = resynchronise(&body);
+        }
+
+        let mut rest = body.as_slice();
+        if flags & FLAG_EXTENDED_HEADER != 0 {
+            let size = take_bytes(&mut rest, 4)?;
+            let remaining_size = match major_version {
+                3 => u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize,
+                _ => (syncsafe(size) as usize).saturating_sub(4),
+            };
+            take_bytes(&mut rest, remaining_size)?;
+        }
+
+        let mut frames = vec![];
+        // Padding starts where the next frame ID would be zeroes
+        while rest.len() >= HEADER_SIZE && rest[0] != 0 {
+            let header = take_bytes(&mut rest, HEADER_SIZE)?;
+            let id: [u8; DWORD_SIZE] = [header[0], header[1], header[2], header[3]];
+            let size = match major_version {
+                3 => u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+                _ => syncsafe(&header[4..8]),
+            } as usize;
+            let format_flags = header[9];
+            let frame_body = take_bytes(&mut rest, size)?;
+
+            let (compressed, encrypted) = match major_version {
+                3 => (
+                    format_flags & V3_FRAME_COMPRESSED != 0,
+                    format_flags & V3_FRAME_ENCRYPTED != 0,
+                ),
+                _ => (
+                    format_flags & V4_FRAME_COMPRESSED != 0,
+                    format_flags & V4_FRAME_ENCRYPTED != 0,
+                ),
+            };
+            if compressed || encrypted {
+                frames.push(Id3Frame::Other {
+                    id,
+                    data: frame_body.to_vec(),
+                });
+                continue;
+            }
+
+            // One malformed frame should not hide the rest of the tag
+            let frame = parse_frame_body(id, frame_body, major_version, format_flags)
+                .unwrap_or_else(|error| {
+                    log::warn!(
+                        "Keeping ID3 frame '{}' undecoded: {}",
+                        String::from_utf8_lossy(&id),
+                        error
+                    );
+                    Id3Frame::Other {
+                        id,
+                        data: frame_body.to_vec(),
+                    }
+                });
+            frames.push(frame);
+        }
+
+        Ok(Self {
+            major_version,
+            frames,
+        })
+    }
+}
+
+/// Strips the extra header fields `format_flags` announce from an uncompressed, unencrypted
+/// frame body and decodes it
+fn parse_frame_body(
+    id: [u8; DWORD_SIZE],
+    mut frame_body: &[u8],
+    major_version: u8,
+    format_flags: u8,
+) -> Result<Id3Frame> {
+    let grouped = match major_version {
+        3 => format_flags & V3_FRAME_GROUPED != 0,
+        _ => format_flags & V4_FRAME_GROUPED != 0,
+    };
+    if grouped {
+        take_bytes(&mut frame_body, 1)?;
+    }
+    if major_version == 4 && format_flags & V4_FRAME_DATA_LENGTH != 0 {
+        take_bytes(&mut frame_body, 4)?;
+    }
+
+    if major_version == 4 && format_flags & V4_FRAME_UNSYNCHRONISED != 0 {
+        Id3Frame::parse(id, &resynchronise(frame_body))
+    } else {
+        Id3Frame::parse(id, frame_body)
+    }
+}
+
+/// Decodes a 28-bit integer stored in the low 7 bits of 4 bytes
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |value, byte| (value << 7) | (*byte & 0x7F) as u32)
+}
+
+/// Undoes unsynchronisation, which puts a zero byte after every `0xFF`
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut previous = 0;
+    for &byte in data {
+        if !(previous == 0xFF && byte == 0) {
+            output.push(byte);
+        }
+        previous = byte;
+    }
+    output
+}
+
+fn take_bytes<'a>(data: &mut &'a [u8], length: usize) -> Result<&'a [u8]> {
+    if data.len() < length {
+        return Err(DJWavFixerError::MetadataError(format!(
+            "ID3 tag ends {} bytes early",
+            length - data.len()
+        )));
+    }
+
+    let (taken, rest) = data.split_at(length);
+    *data = rest;
+    Ok(taken)
+}
+
+/// Width of the terminator and of each code unit in `encoding`
+fn unit_size(encoding: u8) -> usize {
+    match encoding {
+        1 | 2 => 2,
+        _ => 1,
+    }
+}
+
+/// Splits `data` at each terminator of `encoding`, dropping a trailing empty value
+fn split_terminated(data: &[u8], encoding: u8) -> Vec<&[u8]> {
+    let unit_size = unit_size(encoding);
+    let mut values = vec![];
+    let mut start = 0;
+    let mut position = 0;
+    while position + unit_size <= data.len() {
+        if data[position..position + unit_size]
+            .iter()
+            .all(|byte| *byte == 0)
+        {
+            values.push(&data[start..position]);
+            start = position + unit_size;
+        }
+        position += unit_size;
+    }
+    if start < data.len() {
+        values.push(&data[start..]);
+    }
+    values
+}
+
+/// Takes text up to and including its terminator
+fn take_text(data: &mut &[u8], encoding: u8) -> Result<String> {
+    let unit_size = unit_size(encoding);
+    let length = data
+        .chunks_exact(unit_size)
+        .position(|unit| unit.iter().all(|byte| *byte == 0))
+        .map(|units| units * unit_size)
+        .ok_or_else(|| {
+            DJWavFixerError::MetadataError("ID3 text is missing its terminator".to_string())
+        })?;
+
+    let text = decode_text(take_bytes(data, length)?, encoding)?;
+    take_bytes(data, unit_size)?;
+    Ok(text)
+}
+
+fn decode_text(data: &[u8], encoding: u8) -> Result<String> {
+    let utf16 = |data: &[u8], little_endian: bool| {
+        let units = data
+            .chunks_exact(2)
+            .map(|unit| match little_endian {
+                true => u16::from_le_bytes([unit[0], unit[1]]),
+                false => u16::from_be_bytes([unit[0], unit[1]]),
+            })
+            .take_while(|unit| *unit != 0)
+            .collect::<Vec<_>>();
+        String::from_utf16_lossy(&units)
+    };
+
+    Ok(match encoding {
+        0 => data
+            .iter()
+            .take_while(|byte| **byte != 0)
+            .map(|byte| *byte as char)
+            .collect(),
+        1 => match data {
+            [0xFF, 0xFE, rest @ ..] => utf16(rest, true),
+            [0xFE, 0xFF, rest @ ..] => utf16(rest, false),
+            // No byte order mark, which some taggers get away with
+            _ => utf16(data, true),
+        },
+        2 => utf16(data, false),
+        3 => String::from_utf8_lossy(
+            &data[..data
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(data.len())],
+        )
+        .into_owned(),
+        _ => {
+            return Err(DJWavFixerError::MetadataError(format!(
+                "Unknown ID3 text encoding {}",
+                encoding
+            )));
+        }
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn to_syncsafe(value: usize) -> [u8; 4] {
+        [
+            (value >> 21) as u8 & 0x7F,
+            (value >> 14) as u8 & 0x7F,
+            (value >> 7) as u8 & 0x7F,
+            value as u8 & 0x7F,
+        ]
+    }
+
+    /// Assembles an ID3v2 tag from frame IDs, format flags and bodies
+    pub(crate) fn build_id3_tag(major_version: u8, frames: &[(&[u8; 4], u8, Vec<u8>)]) -> Vec<u8> {
+        let body = frames
+            .iter()
+            .flat_map(|(id, format_flags, body)| {
+                let size = match major_version {
+                    3 => (body.len() as u32).to_be_bytes(),
+                    _ => to_syncsafe(body.len()),
+                };
+                id.iter()
+                    .copied()
+                    .chain(size)
+                    .chain([0, *format_flags])
+                    .chain(body.iter().copied())
+            })
+            .collect::<Vec<_>>();
+
+        ID3_MAGIC
+            .into_iter()
+            .chain([major_version, 0, 0])
+            .chain(to_syncsafe(body.len()))
+            .chain(body)
+            .collect()
+    }
+
+    #[test]
+    fn test_id3v23_frames() {
+        let tag = build_id3_tag(
+            3,
+            &[
+                (b"TIT2", 0, b"\0Title".to_vec()),
+                (
+                    b"TXXX",
+                    0,
+                    b"\x01\xFF\xFEK\0e\0y\0\0\0\xFF\xFE8\0A\0".to_vec(),
+                ),
+                (b"COMM", 0, b"\0engDesc\0Comment text".to_vec()),
+                (b"APIC", 0, b"\0image/png\0\x03\0\x89PNG".to_vec()),
+                (
+                    b"GEOB",
+                    0,
+                    b"\0application/octet-stream\0\0Serato Markers2\0\x01\x01".to_vec(),
+                ),
+                (b"PRIV", 0, vec![1, 2, 3]),
+            ],
+        );
+
+        let tag = Id3Tag::try_from(tag.as_slice()).expect("Failed to parse ID3 tag");
+        assert_eq!(tag.major_version(), 3);
+        assert_eq!(tag.text(b"TIT2"), Some("Title"));
+        assert_eq!(
+            tag.frames()[1..],
+            [
+                Id3Frame::UserText {
+                    description: "Key".to_string(),
+                    value: "8A".to_string(),
+                },
+                Id3Frame::Comment {
+                    language: "eng".to_string(),
+                    description: "Desc".to_string(),
+                    text: "Comment text".to_string(),
+                },
+                Id3Frame::Picture {
+                    mime_type: "image/png".to_string(),
+                    picture_type: 3,
+                    description: String::new(),
+                    data: b"\x89PNG".to_vec(),
+                },
+                Id3Frame::Object {
+                    mime_type: "application/octet-stream".to_string(),
+                    filename: String::new(),
+                    description: "Serato Markers2".to_string(),
+                    data: vec![1, 1],
+                },
+                Id3Frame::Other {
+                    id: *b"PRIV",
+                    data: vec![1, 2, 3],
+                },
+            ]
+        );
+        assert_eq!(tag.object("Serato Markers2"), Some([1, 1].as_slice()));
+    }
+
+    #[test]
+    fn test_malformed_frames_are_kept_raw() {
+        let tag = build_id3_tag(
+            3,
+            &[
+                (b"COMM", 0, b"\0en".to_vec()),
+                (b"GEOB", 0, b"\0no terminator".to_vec()),
+                (b"TIT2", 0, b"\0Title".to_vec()),
+            ],
+        );
+
+        let tag = Id3Tag::try_from(tag.as_slice()).expect("Failed to parse ID3 tag");
+        assert_eq!(
+            tag.frames()[..2],
+            [
+                Id3Frame::Other {
+                    id: *b"COMM",
+                    data: b"\0en".to_vec()
+                },
+                Id3Frame::Other {
+                    id: *b"GEOB",
+                    data: b"\0no terminator".to_vec()
+                },
+            ]
+        );
+        assert_eq!(tag.text(b"TIT2"), Some("Title"));
+    }
+
+    #[test]
+    fn test_id3v24_multiple_values_and_unsynchronisation() {
+        let mut tag = build_id3_tag(
+            4,
+            &[
+                (b"TPE1", 0, "\x03Björk\0Guy".as_bytes().to_vec()),
+                // 0xFF 0x00 0xE0 unsynchronised back to 0xFF 0xE0
+                (
+                    b"GEOB",
+                    V4_FRAME_UNSYNCHRONISED,
+                    b"\0\0\0Data\0\xFF\0\xE0".to_vec(),
+                ),
+            ],
+        );
+        tag.extend_from_slice(&[0; 16]); // Padding
+
+        let tag = Id3Tag::try_from(tag.as_slice()).expect("Failed to parse ID3 tag");
+        assert_eq!(
+            tag.frames()[0],
+            Id3Frame::Text {
+                id: *b"TPE1",
+                values: vec!["Björk".to_string(), "Guy".to_string()],
+            }
+        );
+        assert_eq!(tag.object("Data"), Some([0xFF, 0xE0].as_slice()));
+    }
+}
@@ -0,0 +1,84 @@
+//! `LIST/INFO` tags, the plain text metadata most rippers write
+
+use std::io::{Read, Seek};
+
+use crate::DWORD_SIZE;
+use crate::errors::Result;
+use crate::metadata::decode_text;
+use crate::riff_parser::RiffSubchunk;
+
+/// Readable names of the INFO IDs from the RIFF specification, plus the common `ITRK`
+const INFO_NAMES: [([u8; DWORD_SIZE], &str); 23] = [
+    (*b"INAM", "Title"),
+    (*b"IART", "Artist"),
+    (*b"IPRD", "Album"),
+    (*b"IGNR", "Genre"),
+    (*b"ICMT", "Comment"),
+    (*b"ICRD", "Creation Date"),
+    (*b"ISFT", "Software"),
+    (*b"ITRK", "Track Number"),
+    (*b"IPRT", "Part"),
+    (*b"ICOP", "Copyright"),
+    (*b"IENG", "Engineer"),
+    (*b"ITCH", "Technician"),
+    (*b"IKEY", "Keywords"),
+    (*b"ISBJ", "Subject"),
+    (*b"ISRC", "Source"),
+    (*b"ISRF", "Source Form"),
+    (*b"ILGT", "Lightness"),
+    (*b"ILNG", "Language"),
+    (*b"IMED", "Medium"),
+    (*b"IARL", "Archival Location"),
+    (*b"ICMS", "Commissioned"),
+    (*b"ICRP", "Cropped"),
+    (*b"IDIM", "Dimensions"),
+];
+
+/// A single text tag from a `LIST/INFO` subchunk
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InfoTag {
+    id: [u8; DWORD_SIZE],
+    value: String,
+}
+
+impl InfoTag {
+    pub fn id(&self) -> [u8; DWORD_SIZE] {
+        self.id
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Readable name for well-known IDs, the ID itself otherwise
+    pub fn name(&self) -> String {
+        INFO_NAMES
+            .iter()
+            .find(|(id, _)| *id == self.id)
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_else(|| String::from_utf8_lossy(&self.id).into_owned())
+    }
+}
+
+/// Decodes the tags of a `LIST/INFO` subchunk, skipping empty ones
+pub(crate) fn read_info_tags<R: Read + Seek>(
+    list: &mut RiffSubchunk,
+    reader: &mut R,
+) -> Result<Vec<InfoTag>> {
+    let mut tags = vec![];
+    for child in list.children_mut() {
+        if child.list_type().is_some() {
+            continue;
+        }
+
+        let value = decode_text(child.read_data(reader)?);
+        if !value.is_empty() {
+            tags.push(InfoTag {
+                id: child.id(),
+                value,
+            });
+        }
+    }
+
+    Ok(tags)
+}
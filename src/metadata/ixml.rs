@@ -0,0 +1,229 @@
+use std::fmt::Write;
+
+use crate::DWORD_SIZE;
+use crate::errors::{DJWavFixerError, Result};
+use crate::metadata::xml::{XmlElement, parse_xml};
+
+pub(crate) const IXML_MAGIC: [u8; DWORD_SIZE] = *b"iXML";
+
+/// A track of a multitrack recording, from `TRACK_LIST`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IxmlTrack {
+    channel_index: Option<u32>,
+    interleave_index: Option<u32>,
+    name: String,
+    function: String,
+}
+
+impl IxmlTrack {
+    /// Input of the recorder the track came from, from 1
+    pub fn channel_index(&self) -> Option<u32> {
+        self.channel_index
+    }
+
+    /// Channel of the file holding the track, from 1
+    pub fn interleave_index(&self) -> Option<u32> {
+        self.interleave_index
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// What the track carries, such as `M-MID-SIDE` or `BOOM`
+    pub fn function(&self) -> &str {
+        &self.function
+    }
+}
+
+/// An iXML chunk as written by field and broadcast recorders, with its payload kept as-is
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Ixml {
+    raw: Vec<u8>,
+    root: XmlElement,
+}
+
+impl Ixml {
+    /// The chunk body byte for byte, the fix writes it back unchanged
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// The `BWFXML` root element
+    pub fn root(&self) -> &XmlElement {
+        &self.root
+    }
+
+    /// Text of the element at `path` below the root, such as `SPEED/TIMECODE_RATE`, `None` if
+    /// it is missing or empty
+    pub fn value(&self, path: &str) -> Option<&str> {
+        self.root
+            .find(path)
+            .map(XmlElement::text)
+            .filter(|text| !text.is_empty())
+    }
+
+    pub fn project(&self) -> Option<&str> {
+        self.value("PROJECT")
+    }
+
+    pub fn scene(&self) -> Option<&str> {
+        self.value("SCENE")
+    }
+
+    pub fn take(&self) -> Option<&str> {
+        self.value("TAKE")
+    }
+
+    pub fn tape(&self) -> Option<&str> {
+        self.value("TAPE")
+    }
+
+    pub fn note(&self) -> Option<&str> {
+        self.value("NOTE")
+    }
+
+    /// Frame rate of the timecode, such as `25/1` or `30000/1001`
+    pub fn timecode_rate(&self) -> Option<&str> {
+        self.value("SPEED/TIMECODE_RATE")
+    }
+
+    /// `DF` for drop frame timecode, `NDF` otherwise
+    pub fn timecode_flag(&self) -> Option<&str> {
+        self.value("SPEED/TIMECODE_FLAG")
+    }
+
+    /// Samples since midnight at the first sample of the recording
+    pub fn timestamp_samples(&self) -> Option<u64> {
+        let half = |name| self.value(name)?.parse::<u64>().ok();
+        let low = half("SPEED/TIMESTAMP_SAMPLES_SINCE_MIDNIGHT_LO")?;
+        let high = half("SPEED/TIMESTAMP_SAMPLES_SINCE_MIDNIGHT_HI").unwrap_or(0);
+        Some(high << 32 | low)
+    }
+
+    pub fn tracks(&self) -> Vec<IxmlTrack> {
+        let Some(track_list) = self.root.child("TRACK_LIST") else {
+            return vec![];
+        };
+
+        track_list
+            .children()
+            .iter()
+            .filter(|element| element.name() == "TRACK")
+            .map(|track| {
+                let text = |name| track.child(name).map(XmlElement::text).unwrap_or_default();
+                IxmlTrack {
+                    channel_index: text("CHANNEL_INDEX").parse().ok(),
+                    interleave_index: text("INTERLEAVE_INDEX").parse().ok(),
+                    name: text("NAME").to_string(),
+                    function: text("FUNCTION").to_string(),
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) fn write_information(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "  iXML:")?;
+        for (name, value) in [
+            ("Project", self.project()),
+            ("Scene", self.scene()),
+            ("Take", self.take()),
+            ("Tape", self.tape()),
+            ("Note", self.note()),
+        ] {
+            if let Some(value) = value {
+                writeln!(writer, "    {}: {}", name, value)?;
+            }
+        }
+        if let Some(timecode_rate) = self.timecode_rate() {
+            writeln!(
+                writer,
+                "    Timecode: {} fps {}",
+                timecode_rate,
+                self.timecode_flag().unwrap_or_default()
+            )?;
+        }
+        if let Some(timestamp_samples) = self.timestamp_samples() {
+            writeln!(writer, "    Timestamp: {} samples", timestamp_samples)?;
+        }
+        for track in self.tracks() {
+            write!(
+                writer,
+                "    Track {}: {}",
+                track.interleave_index.unwrap_or_default(),
+                track.name
+            )?;
+            match track.function.is_empty() {
+                true => writeln!(writer)?,
+                false => writeln!(writer, " ({})", track.function)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<&[u8]> for Ixml {
+    type Error = DJWavFixerError;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        // Recorders pad the payload out with NULs to leave room for later edits
+        let length = data
+            .iter()
+            .rposition(|byte| *byte != 0)
+            .map_or(0, |index| index + 1);
+        let text = String::from_utf8_lossy(&data[..length]);
+        let root = parse_xml(text.trim_start_matches('\u{FEFF}'))?;
+
+        Ok(Self {
+            raw: data.to_vec(),
+            root,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ixml() {
+        let data = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\r\n<BWFXML>\
+            <IXML_VERSION>2.10</IXML_VERSION><PROJECT>Live Set</PROJECT><SCENE>12A</SCENE>\
+            <TAKE>3</TAKE><NOTE>Crowd noise</NOTE>\
+            <SPEED><TIMECODE_RATE>25/1</TIMECODE_RATE><TIMECODE_FLAG>NDF</TIMECODE_FLAG>\
+            <TIMESTAMP_SAMPLES_SINCE_MIDNIGHT_HI>1</TIMESTAMP_SAMPLES_SINCE_MIDNIGHT_HI>\
+            <TIMESTAMP_SAMPLES_SINCE_MIDNIGHT_LO>16</TIMESTAMP_SAMPLES_SINCE_MIDNIGHT_LO></SPEED>\
+            <TRACK_LIST><TRACK_COUNT>2</TRACK_COUNT>\
+            <TRACK><CHANNEL_INDEX>1</CHANNEL_INDEX><INTERLEAVE_INDEX>1</INTERLEAVE_INDEX>\
+            <NAME>Boom</NAME><FUNCTION>BOOM</FUNCTION></TRACK>\
+            <TRACK><CHANNEL_INDEX>3</CHANNEL_INDEX><INTERLEAVE_INDEX>2</INTERLEAVE_INDEX>\
+            <NAME>Lav &amp; Vox</NAME></TRACK></TRACK_LIST></BWFXML>\0\0\0";
+
+        let ixml = Ixml::try_from(data.as_slice()).expect("Failed to parse iXML");
+        assert_eq!(ixml.raw(), data);
+        assert_eq!(ixml.project(), Some("Live Set"));
+        assert_eq!(ixml.scene(), Some("12A"));
+        assert_eq!(ixml.take(), Some("3"));
+        assert_eq!(ixml.tape(), None);
+        assert_eq!(ixml.timecode_rate(), Some("25/1"));
+        assert_eq!(ixml.timestamp_samples(), Some((1 << 32) + 16));
+        assert_eq!(
+            ixml.tracks(),
+            [
+                IxmlTrack {
+                    channel_index: Some(1),
+                    interleave_index: Some(1),
+                    name: "Boom".to_string(),
+                    function: "BOOM".to_string(),
+                },
+                IxmlTrack {
+                    channel_index: Some(3),
+                    interleave_index: Some(2),
+                    name: "Lav & Vox".to_string(),
+                    function: String::new(),
+                },
+            ]
+        );
+    }
+}
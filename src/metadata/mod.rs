@@ -0,0 +1,516 @@
+//! Metadata chunks carried alongside the audio
+
+use std::fmt::Write;
+use std::io::{Read, Seek};
+
+use crate::DWORD_SIZE;
+use crate::errors::Result;
+use crate::riff_parser::{RiffChunk, RiffFile};
+
+pub use acid::Acid;
+pub use bext::BroadcastExtension;
+pub(crate) use bext::update_bext;
+pub use cart::{Cart, CartTimer};
+pub(crate) use cues::{ADTL_LIST_TYPE, CUE_MAGIC, PLST_MAGIC, SMPL_MAGIC, resample_positions};
+pub use cues::{CuePoint, LoopType, SampleLoop, Sampler};
+pub use id3::{Id3Frame, Id3Tag};
+pub use info::InfoTag;
+pub use ixml::{Ixml, IxmlTrack};
+pub(crate) use serato::SERATO_GEOB_DESCRIPTIONS;
+pub use serato::{BeatGridMarker, SeratoBeatGrid, SeratoCue, SeratoLoop, SeratoMarkers};
+pub use xml::XmlElement;
+
+mod acid;
+mod bext;
+mod cart;
+mod cues;
+mod id3;
+mod info;
+mod ixml;
+mod serato;
+mod xml;
+
+#[cfg(test)]
+pub(crate) use id3::tests::build_id3_tag;
+#[cfg(test)]
+pub(crate) use serato::tests::build_markers2;
+
+pub(crate) const BEXT_MAGIC: [u8; DWORD_SIZE] = *b"bext";
+pub(crate) const CART_MAGIC: [u8; DWORD_SIZE] = *b"cart";
+
+const INFO_LIST_TYPE: [u8; DWORD_SIZE] = *b"INFO";
+/// Subchunk IDs DJ software stores ID3v2 tags under
+const ID3_IDS: [[u8; DWORD_SIZE]; 2] = [*b"id3 ", *b"ID3 "];
+
+/// Decodes text as tagging software writes it, up to the first NUL. UTF-16 needs a byte order
+/// mark, anything that is not valid UTF-8 is taken as Latin-1
+pub(crate) fn decode_text(data: &[u8]) -> String {
+    let utf16_units = match data {
+        [0xFF, 0xFE, rest @ ..] => Some(
+            rest.chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        [0xFE, 0xFF, rest @ ..] => Some(
+            rest.chunks_exact(2)
+                .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        _ => None,
+    };
+    if let Some(units) = utf16_units {
+        let length = units
+            .iter()
+            .position(|unit| *unit == 0)
+            .unwrap_or(units.len());
+        return String::from_utf16_lossy(&units[..length]);
+    }
+
+    let data = &data[..data
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(data.len())];
+    match std::str::from_utf8(data) {
+        Ok(text) => text.to_string(),
+        Err(_) => data.iter().map(|byte| *byte as char).collect(),
+    }
+}
+
+/// Reads the fixed-size fields of a chunk in order, the caller checks the chunk is long enough
+pub(crate) struct FixedFields<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> FixedFields<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub(crate) fn bytes(&mut self, size: usize) -> &'a [u8] {
+        let bytes = &self.data[self.position..self.position + size];
+        self.position += size;
+        bytes
+    }
+
+    /// Text padded out with NULs
+    pub(crate) fn text(&mut self, size: usize) -> String {
+        decode_text(self.bytes(size))
+    }
+
+    pub(crate) fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(unsafe { self.bytes(2).try_into().unwrap_unchecked() })
+    }
+
+    pub(crate) fn i16(&mut self) -> i16 {
+        self.u16() as i16
+    }
+
+    pub(crate) fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(unsafe { self.bytes(4).try_into().unwrap_unchecked() })
+    }
+
+    pub(crate) fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(unsafe { self.bytes(8).try_into().unwrap_unchecked() })
+    }
+
+    /// Everything after the fixed fields
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        &self.data[self.position..]
+    }
+}
+
+/// Appends values as fixed-size, NUL-padded fields
+pub(crate) trait PutField {
+    fn put_bytes(&mut self, value: &[u8], size: usize);
+    fn put_text(&mut self, value: &str, size: usize);
+}
+
+impl PutField for Vec<u8> {
+    fn put_bytes(&mut self, value: &[u8], size: usize) {
+        let length = value.len().min(size);
+        self.extend_from_slice(&value[..length]);
+        self.resize(self.len() + size - length, 0);
+    }
+
+    fn put_text(&mut self, value: &str, size: usize) {
+        self.put_bytes(value.as_bytes(), size);
+    }
+}
+
+/// Metadata read from a WAV file's chunks
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    info: Vec<InfoTag>,
+    id3: Option<Id3Tag>,
+    serato_markers: Option<SeratoMarkers>,
+    serato_beat_grid: Option<SeratoBeatGrid>,
+    bext: Option<BroadcastExtension>,
+    cart: Option<Cart>,
+    cue_points: Vec<CuePoint>,
+    sampler: Option<Sampler>,
+    acid: Option<Acid>,
+    ixml: Option<Ixml>,
+}
+
+/// Reads the ID3v2 tag embedded in `chunk`, `None` if it has none. The body is not cached in
+/// the subchunk, the tag holds everything worth keeping, cover art included
+pub(crate) fn read_id3_tag<R: Read + Seek>(
+    chunk: &RiffChunk,
+    reader: &mut R,
+) -> Option<Result<Id3Tag>> {
+    let subchunk = chunk
+        .subchunks()
+        .iter()
+        .find(|subchunk| ID3_IDS.contains(&subchunk.id()))?;
+    let mut data = Vec::with_capacity(subchunk.size() as usize);
+    Some(
+        subchunk
+            .copy_data(reader, &mut data)
+            .and_then(|()| Id3Tag::try_from(data.as_slice())),
+    )
+}
+
+impl Metadata {
+    /// Reads every metadata chunk it knows, chunks that cannot be read are left out. Positions
+    /// in samples are converted to seconds at `sample_rate`
+    pub(crate) fn read<R: Read + Seek>(riff_file: &mut RiffFile<R>, sample_rate: u32) -> Self {
+        let mut metadata = Self::default();
+        let Some((reader, chunk)) = riff_file.get_riff_chunk_and_reader() else {
+            return metadata;
+        };
+
+        for list in chunk
+            .subchunks_mut()
+            .iter_mut()
+            .filter(|subchunk| subchunk.list_type() == Some(INFO_LIST_TYPE))
+        {
+            match info::read_info_tags(list, reader) {
+                Ok(tags) => metadata.info.extend(tags),
+                Err(error) => log::warn!("Could not read 'LIST/INFO' tags: {}", error),
+            }
+        }
+
+        match read_id3_tag(chunk, reader) {
+            Some(Ok(tag)) => metadata.id3 = Some(tag),
+            Some(Err(error)) => log::warn!("Could not read ID3 tag: {}", error),
+            None => {}
+        }
+
+        if let Some(data) = metadata
+            .id3
+            .as_ref()
+            .and_then(|tag| tag.object(serato::MARKERS2_DESCRIPTION))
+        {
+            match SeratoMarkers::parse(data) {
+                Ok(markers) => metadata.serato_markers = Some(markers),
+                Err(error) => log::warn!("Could not read Serato markers: {}", error),
+            }
+        }
+        if let Some(data) = metadata
+            .id3
+            .as_ref()
+            .and_then(|tag| tag.object(serato::BEAT_GRID_DESCRIPTION))
+        {
+            match SeratoBeatGrid::parse(data) {
+                Ok(beat_grid) => metadata.serato_beat_grid = Some(beat_grid),
+                Err(error) => log::warn!("Could not read Serato beat grid: {}", error),
+            }
+        }
+
+        if let Some(subchunk) = chunk.get_subchunk_mut(&BEXT_MAGIC) {
+            match subchunk
+                .read_data(reader)
+                .and_then(BroadcastExtension::try_from)
+            {
+                Ok(bext) => metadata.bext = Some(bext),
+                Err(error) => log::warn!("Could not read 'bext' chunk: {}", error),
+            }
+        }
+
+        if let Some(subchunk) = chunk.get_subchunk_mut(&CART_MAGIC) {
+            match subchunk.read_data(reader).and_then(Cart::try_from) {
+                Ok(cart) => metadata.cart = Some(cart),
+                Err(error) => log::warn!("Could not read 'cart' chunk: {}", error),
+            }
+        }
+
+        let mut labels = cues::CueLabels::default();
+        for list in chunk
+            .subchunks_mut()
+            .iter_mut()
+            .filter(|subchunk| subchunk.list_type() == Some(ADTL_LIST_TYPE))
+        {
+            if let Err(error) = labels.read(list, reader) {
+                log::warn!("Could not read 'LIST/adtl' labels: {}", error);
+            }
+        }
+
+        if let Some(subchunk) = chunk.get_subchunk_mut(&CUE_MAGIC) {
+            match subchunk
+                .read_data(reader)
+                .and_then(|data| cues::read_cue_points(data, &labels, sample_rate))
+            {
+                Ok(cue_points) => metadata.cue_points = cue_points,
+                Err(error) => log::warn!("Could not read 'cue ' chunk: {}", error),
+            }
+        }
+
+        if let Some(subchunk) = chunk.get_subchunk_mut(&SMPL_MAGIC) {
+            match subchunk
+                .read_data(reader)
+                .and_then(|data| Sampler::parse(data, &labels, sample_rate))
+            {
+                Ok(sampler) => metadata.sampler = Some(sampler),
+                Err(error) => log::warn!("Could not read 'smpl' chunk: {}", error),
+            }
+        }
+
+        if let Some(subchunk) = chunk.get_subchunk_mut(&acid::ACID_MAGIC) {
+            match subchunk.read_data(reader).and_then(Acid::try_from) {
+                Ok(acid) => metadata.acid = Some(acid),
+                Err(error) => log::warn!("Could not read 'acid' chunk: {}", error),
+            }
+        }
+
+        if let Some(subchunk) = chunk.get_subchunk_mut(&ixml::IXML_MAGIC) {
+            match subchunk.read_data(reader).and_then(Ixml::try_from) {
+                Ok(ixml) => metadata.ixml = Some(ixml),
+                Err(error) => log::warn!("Could not read 'iXML' chunk: {}", error),
+            }
+        }
+
+        metadata
+    }
+
+    /// Tags from `LIST/INFO` subchunks, in file order
+    pub fn info(&self) -> &[InfoTag] {
+        &self.info
+    }
+
+    /// Value of the first INFO tag with `id`, such as `INAM` or `IART`
+    pub fn info_value(&self, id: &[u8; DWORD_SIZE]) -> Option<&str> {
+        self.info
+            .iter()
+            .find(|tag| tag.id() == *id)
+            .map(InfoTag::value)
+    }
+
+    /// The ID3v2 tag from the first `id3 ` or `ID3 ` subchunk
+    pub fn id3(&self) -> Option<&Id3Tag> {
+        self.id3.as_ref()
+    }
+
+    /// Hot cues, loops and track colour from the `Serato Markers2` ID3 frame
+    pub fn serato_markers(&self) -> Option<&SeratoMarkers> {
+        self.serato_markers.as_ref()
+    }
+
+    /// Beat grid from the `Serato BeatGrid` ID3 frame
+    pub fn serato_beat_grid(&self) -> Option<&SeratoBeatGrid> {
+        self.serato_beat_grid.as_ref()
+    }
+
+    /// The Broadcast Wave `bext` chunk
+    pub fn bext(&self) -> Option<&BroadcastExtension> {
+        self.bext.as_ref()
+    }
+
+    /// The radio traffic `cart` chunk
+    pub fn cart(&self) -> Option<&Cart> {
+        self.cart.as_ref()
+    }
+
+    /// Markers from the `cue ` chunk, labelled from `LIST/adtl`
+    pub fn cue_points(&self) -> &[CuePoint] {
+        &self.cue_points
+    }
+
+    /// Root note and loops from the `smpl` chunk
+    pub fn sampler(&self) -> Option<&Sampler> {
+        self.sampler.as_ref()
+    }
+
+    /// Tempo, beats and loop settings from the ACID `acid` chunk
+    pub fn acid(&self) -> Option<&Acid> {
+        self.acid.as_ref()
+    }
+
+    /// Scene, take, tracks and timecode from a recorder's `iXML` chunk
+    pub fn ixml(&self) -> Option<&Ixml> {
+        self.ixml.as_ref()
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.info_value(b"INAM")
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.info_value(b"IART")
+    }
+
+    pub fn genre(&self) -> Option<&str> {
+        self.info_value(b"IGNR")
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.info_value(b"ICMT")
+    }
+
+    pub(crate) fn write_information(&self, mut writer: impl Write) -> Result<()> {
+        if !self.info.is_empty() {
+            writeln!(writer, "  Info Tags:")?;
+            for tag in &self.info {
+                writeln!(writer, "    {}: {}", tag.name(), tag.value())?;
+            }
+        }
+        if let Some(id3) = &self.id3 {
+            id3.write_information(&mut writer)?;
+        }
+        if let Some(serato_markers) = &self.serato_markers {
+            serato_markers.write_information(&mut writer)?;
+        }
+        if let Some(serato_beat_grid) = &self.serato_beat_grid {
+            serato_beat_grid.write_information(&mut writer)?;
+        }
+        if let Some(bext) = &self.bext {
+            bext.write_information(&mut writer)?;
+        }
+        if let Some(cart) = &self.cart {
+            cart.write_information(&mut writer)?;
+        }
+        if !self.cue_points.is_empty() {
+            cues::write_cue_points(&self.cue_points, &mut writer)?;
+        }
+        if let Some(sampler) = &self.sampler {
+            sampler.write_information(&mut writer)?;
+        }
+        if let Some(acid) = &self.acid {
+            acid.write_information(&mut writer)?;
+        }
+        if let Some(ixml) = &self.ixml {
+            ixml.write_information(&mut writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::ParseMode;
+    use crate::riff_parser::tests::{build_list, build_riff_wave};
+    use crate::riff_parser::{DATA_MAGIC, LIST_MAGIC};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_decode_text() {
+        assert_eq!(decode_text(b"Title\0\0"), "Title");
+        assert_eq!(decode_text("Björk\0".as_bytes()), "Björk");
+        assert_eq!(decode_text(b"Bj\xF6rk"), "Björk");
+        assert_eq!(decode_text(b"\xFF\xFEB\0j\0\xF6\0r\0k\0\0\0"), "Björk");
+    }
+
+    #[test]
+    fn test_read_info_tags() {
+        let info = build_list(
+            INFO_LIST_TYPE,
+            &[
+                (*b"INAM", b"Track\0".to_vec()),
+                (*b"IART", b"Artist\0\0".to_vec()),
+                (*b"ICMT", b"\0".to_vec()),
+                (*b"IXYZ", b"Custom".to_vec()),
+            ],
+        );
+        let file = build_riff_wave(&[(DATA_MAGIC, vec![0; 2]), (LIST_MAGIC, info)]);
+        let data_size = file.len() as u64 - 8;
+        let mut riff_file = RiffFile::try_new(Cursor::new(file), data_size, ParseMode::Strict)
+            .expect("Failed to parse RIFF");
+
+        let metadata = Metadata::read(&mut riff_file, 44100);
+        assert_eq!(metadata.title(), Some("Track"));
+        assert_eq!(metadata.artist(), Some("Artist"));
+        assert_eq!(metadata.comment(), None);
+        assert_eq!(
+            metadata
+                .info()
+                .iter()
+                .map(InfoTag::name)
+                .collect::<Vec<_>>(),
+            ["Title", "Artist", "IXYZ"]
+        );
+    }
+
+    #[test]
+    fn test_read_id3_chunk() {
+        let tag = build_id3_tag(4, &[(b"TIT2", 0, b"\x03Track".to_vec())]);
+        let file = build_riff_wave(&[(DATA_MAGIC, vec![0; 2]), (*b"id3 ", tag)]);
+        let data_size = file.len() as u64 - 8;
+        let mut riff_file = RiffFile::try_new(Cursor::new(file), data_size, ParseMode::Strict)
+            .expect("Failed to parse RIFF");
+
+        let metadata = Metadata::read(&mut riff_file, 44100);
+        let id3 = metadata.id3().expect("ID3 tag not read");
+        assert_eq!(id3.major_version(), 4);
+        assert_eq!(id3.text(b"TIT2"), Some("Track"));
+        // Only the parsed tag is kept, the chunk body is not held a second time
+        let chunk = riff_file.get_riff_chunk().unwrap();
+        assert!(!chunk.get_subchunk(b"id3 ").unwrap().is_cached());
+    }
+
+    #[test]
+    fn test_read_cues_and_loops() {
+        let cue_point = |id: u32, sample_offset: u32| {
+            [id, 0, u32::from_le_bytes(DATA_MAGIC), 0, 0, sample_offset]
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect::<Vec<_>>()
+        };
+        let cue = [
+            2u32.to_le_bytes().to_vec(),
+            cue_point(1, 22050),
+            cue_point(2, 88200),
+        ]
+        .concat();
+        let smpl = [0, 0, 22675, 60, 0, 0, 0, 1, 0, 2, 0, 88200, 176399, 0, 4]
+            .iter()
+            .flat_map(|value: &u32| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        let adtl = build_list(
+            ADTL_LIST_TYPE,
+            &[
+                (*b"labl", b"\x01\0\0\0Drop\0".to_vec()),
+                (*b"note", b"\x01\0\0\0Big one\0".to_vec()),
+                (*b"labl", b"\x02\0\0\0Loop\0".to_vec()),
+            ],
+        );
+        let file = build_riff_wave(&[
+            (DATA_MAGIC, vec![0; 2]),
+            (CUE_MAGIC, cue),
+            (LIST_MAGIC, adtl),
+            (SMPL_MAGIC, smpl),
+        ]);
+        let data_size = file.len() as u64 - 8;
+        let mut riff_file = RiffFile::try_new(Cursor::new(file), data_size, ParseMode::Strict)
+            .expect("Failed to parse RIFF");
+
+        let metadata = Metadata::read(&mut riff_file, 44100);
+        let cue_points = metadata.cue_points();
+        assert_eq!(cue_points.len(), 2);
+        assert_eq!(cue_points[0].position(), 22050);
+        assert_eq!(cue_points[0].position_seconds(), 0.5);
+        assert_eq!(cue_points[0].label(), Some("Drop"));
+        assert_eq!(cue_points[0].note(), Some("Big one"));
+        assert_eq!(cue_points[1].label(), Some("Loop"));
+
+        let sampler = metadata.sampler().expect("Sampler not read");
+        assert_eq!(sampler.root_note_name(), "C4");
+        let sample_loop = &sampler.loops()[0];
+        assert_eq!(sample_loop.loop_type(), LoopType::Forward);
+        assert_eq!(sample_loop.start_seconds(), 2.0);
+        assert_eq!(sample_loop.end(), 176399);
+        assert_eq!(sample_loop.play_count(), Some(4));
+        assert_eq!(sample_loop.label(), Some("Loop"));
+    }
+}
@@ -0,0 +1,479 @@
+//! Hot cues, loops and beat grids Serato keeps in ID3 `GEOB` frames
+
+use std::fmt::Write;
+
+use crate::errors::{DJWavFixerError, Result};
+
+/// Description of the `GEOB` frame holding cues, loops and the track colour
+pub(crate) const MARKERS2_DESCRIPTION: &str = "Serato Markers2";
+/// Description of the `GEOB` frame holding the beat grid
+pub(crate) const BEAT_GRID_DESCRIPTION: &str = "Serato BeatGrid";
+/// Frames a fix must carry over byte for byte, Serato reads nothing else from them
+pub(crate) const SERATO_GEOB_DESCRIPTIONS: [&str; 2] =
+    [MARKERS2_DESCRIPTION, BEAT_GRID_DESCRIPTION];
+
+const MARKERS2_VERSION: [u8; 2] = [0x01, 0x01];
+const BEAT_GRID_VERSION: [u8; 2] = [0x01, 0x00];
+
+fn serato_error(message: impl Into<String>) -> DJWavFixerError {
+    DJWavFixerError::MetadataError(message.into())
+}
+
+/// Decodes base64 as Serato writes it, across line breaks and without trailing padding
+fn decode_base64(text: &[u8]) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for &character in text {
+        let value = match character {
+            b'A'..=b'Z' => character - b'A',
+            b'a'..=b'z' => character - b'a' + 26,
+            b'0'..=b'9' => character - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'\n' | b'\r' => continue,
+            // Padding, or the NULs the frame is padded out with
+            b'=' | 0 => break,
+            _ => {
+                return Err(serato_error(format!(
+                    "Invalid base64 character {:#04X}",
+                    character
+                )));
+            }
+        };
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            data.push((bits >> bit_count) as u8);
+        }
+    }
+    // A lone character left over holds less than a byte, Serato writes one now and then
+    Ok(data)
+}
+
+/// Splits off a NUL-terminated string
+fn take_string<'a>(data: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let length = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or_else(|| serato_error("Serato string is missing its terminator"))?;
+    let string = &data[..length];
+    *data = &data[length + 1..];
+    Ok(string)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(unsafe { data[offset..offset + 4].try_into().unwrap_unchecked() })
+}
+
+/// Decodes a NUL-terminated UTF-8 name
+fn read_name(data: &[u8]) -> String {
+    let length = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..length]).into_owned()
+}
+
+fn format_color(color: [u8; 3]) -> String {
+    format!("#{:02X}{:02X}{:02X}", color[0], color[1], color[2])
+}
+
+/// A hot cue set in Serato
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SeratoCue {
+    index: u8,
+    position_ms: u32,
+    color: [u8; 3],
+    name: String,
+}
+
+impl SeratoCue {
+    /// Slot of the cue on the controller, from 0
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Position in milliseconds from the start of the audio
+    pub fn position_ms(&self) -> u32 {
+        self.position_ms
+    }
+
+    pub fn position_seconds(&self) -> f64 {
+        self.position_ms as f64 / 1000.0
+    }
+
+    /// Red, green and blue
+    pub fn color(&self) -> [u8; 3] {
+        self.color
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// A saved loop set in Serato
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SeratoLoop {
+    index: u8,
+    start_ms: u32,
+    end_ms: u32,
+    locked: bool,
+    name: String,
+}
+
+impl SeratoLoop {
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    pub fn start_ms(&self) -> u32 {
+        self.start_ms
+    }
+
+    pub fn end_ms(&self) -> u32 {
+        self.end_ms
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Contents of a `Serato Markers2` frame
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SeratoMarkers {
+    cues: Vec<SeratoCue>,
+    loops: Vec<SeratoLoop>,
+    track_color: Option<[u8; 3]>,
+    bpm_locked: Option<bool>,
+}
+
+impl SeratoMarkers {
+    /// Hot cues in the order Serato stored them
+    pub fn cues(&self) -> &[SeratoCue] {
+        &self.cues
+    }
+
+    pub fn loops(&self) -> &[SeratoLoop] {
+        &self.loops
+    }
+
+    /// Colour of the track in the Serato library
+    pub fn track_color(&self) -> Option<[u8; 3]> {
+        self.track_color
+    }
+
+    pub fn bpm_locked(&self) -> Option<bool> {
+        self.bpm_locked
+    }
+
+    /// Parses the data of a `GEOB` frame, a version followed by base64 encoded entries
+    pub(crate) fn parse(data: &[u8]) -> Result<Self> {
+        let text = data
+            .strip_prefix(&MARKERS2_VERSION)
+            .ok_or_else(|| serato_error("Unknown Serato Markers2 version"))?;
+        let payload = decode_base64(text)?;
+        let mut entries = payload
+            .strip_prefix(&MARKERS2_VERSION)
+            .ok_or_else(|| serato_error("Unknown Serato Markers2 payload version"))?;
+
+        let mut markers = Self::default();
+        while !entries.is_empty() {
+            let entry_type = take_string(&mut entries)?;
+            // The entries end with an empty type, the rest is padding
+            if entry_type.is_empty() || entries.len() < 4 {
+                break;
+            }
+            let length = read_u32(entries, 0) as usize;
+            let body = entries.get(4..4 + length).ok_or_else(|| {
+                serato_error(format!(
+                    "Serato {} entry runs past the end of the frame",
+                    String::from_utf8_lossy(entry_type)
+                ))
+            })?;
+            entries = &entries[4 + length..];
+
+            match entry_type {
+                b"CUE" if body.len() >= 12 => markers.cues.push(SeratoCue {
+                    index: body[1],
+                    position_ms: read_u32(body, 2),
+                    color: [body[7], body[8], body[9]],
+                    name: read_name(&body[12..]),
+                }),
+                b"LOOP" if body.len() >= 20 => markers.loops.push(SeratoLoop {
+                    index: body[1],
+                    start_ms: read_u32(body, 2),
+                    end_ms: read_u32(body, 6),
+                    locked: body[19] != 0,
+                    name: read_name(&body[20..]),
+                }),
+                b"COLOR" if body.len() >= 4 => {
+                    markers.track_color = Some([body[1], body[2], body[3]]);
+                }
+                b"BPMLOCK" if !body.is_empty() => markers.bpm_locked = Some(body[0] != 0),
+                // `FLIP` and entries from newer versions
+                _ => {}
+            }
+        }
+
+        Ok(markers)
+    }
+
+    pub(crate) fn write_information(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "  Serato Markers:")?;
+        if let Some(track_color) = self.track_color {
+            writeln!(writer, "    Track Colour: {}", format_color(track_color))?;
+        }
+        for cue in &self.cues {
+            write!(
+                writer,
+                "    Hot Cue {}: {:.3}s {}",
+                cue.index + 1,
+                cue.position_seconds(),
+                format_color(cue.color)
+            )?;
+            match cue.name.is_empty() {
+                true => writeln!(writer)?,
+                false => writeln!(writer, " \"{}\"", cue.name)?,
+            }
+        }
+        for serato_loop in &self.loops {
+            write!(
+                writer,
+                "    Loop {}: {:.3}s to {:.3}s",
+                serato_loop.index + 1,
+                serato_loop.start_ms as f64 / 1000.0,
+                serato_loop.end_ms as f64 / 1000.0
+            )?;
+            match serato_loop.name.is_empty() {
+                true => writeln!(writer)?,
+                false => writeln!(writer, " \"{}\"", serato_loop.name)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A marker in a Serato beat grid
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BeatGridMarker {
+    position_seconds: f32,
+    beats_to_next: Option<u32>,
+    bpm: Option<f32>,
+}
+
+impl BeatGridMarker {
+    pub fn position_seconds(&self) -> f32 {
+        self.position_seconds
+    }
+
+    /// Beats up to the next marker, `None` for the last marker
+    pub fn beats_to_next(&self) -> Option<u32> {
+        self.beats_to_next
+    }
+
+    /// Tempo from this marker on, only the last marker has one
+    pub fn bpm(&self) -> Option<f32> {
+        self.bpm
+    }
+}
+
+/// Contents of a `Serato BeatGrid` frame, which is stored as-is rather than in base64
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SeratoBeatGrid {
+    markers: Vec<BeatGridMarker>,
+}
+
+impl SeratoBeatGrid {
+    pub fn markers(&self) -> &[BeatGridMarker] {
+        &self.markers
+    }
+
+    /// Tempo of the last marker
+    pub fn bpm(&self) -> Option<f32> {
+        self.markers.last().and_then(BeatGridMarker::bpm)
+    }
+
+    pub(crate) fn parse(data: &[u8]) -> Result<Self> {
+        let markers_data = data
+            .strip_prefix(&BEAT_GRID_VERSION)
+            .filter(|rest| rest.len() >= 4)
+            .ok_or_else(|| serato_error("Unknown Serato BeatGrid version"))?;
+        let count = read_u32(markers_data, 0) as usize;
+        let markers_data = &markers_data[4..];
+        // Compared in whole markers, a count from the file times 8 can overflow
+        if count > markers_data.len() / 8 {
+            return Err(serato_error(format!(
+                "Serato BeatGrid declares {} markers, but holds {}",
+                count,
+                markers_data.len() / 8
+            )));
+        }
+
+        let markers = markers_data
+            .chunks_exact(8)
+            .take(count)
+            .enumerate()
+            .map(|(index, marker)| {
+                let position_seconds = f32::from_bits(read_u32(marker, 0));
+                let value = read_u32(marker, 4);
+                // Every marker but the last counts beats to the next, the last gives the tempo
+                match index + 1 == count {
+                    true => BeatGridMarker {
+                        position_seconds,
+                        beats_to_next: None,
+                        bpm: Some(f32::from_bits(value)),
+                    },
+                    false => BeatGridMarker {
+                        position_seconds,
+                        beats_to_next: Some(value),
+                        bpm: None,
+                    },
+                }
+            })
+            .collect();
+
+        Ok(Self { markers })
+    }
+
+    pub(crate) fn write_information(&self, mut writer: impl Write) -> Result<()> {
+        write!(writer, "  Serato Beat Grid: {} markers", self.markers.len())?;
+        match self.bpm() {
+            Some(bpm) => writeln!(writer, ", {:.2} BPM", bpm)?,
+            None => writeln!(writer)?,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const BASE64_ALPHABET: &[u8] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    /// Encodes as Serato does, lines of 72 characters without padding
+    fn encode_base64(data: &[u8]) -> Vec<u8> {
+        let mut text = vec![];
+        for group in data.chunks(3) {
+            let bits = group.iter().enumerate().fold(0u32, |bits, (index, byte)| {
+                bits | (*byte as u32) << (16 - 8 * index)
+            });
+            for index in 0..=group.len() {
+                text.push(BASE64_ALPHABET[(bits >> (18 - 6 * index)) as usize & 0x3F]);
+            }
+        }
+        text.chunks(72).collect::<Vec<_>>().join(&b'\n')
+    }
+
+    fn entry(entry_type: &str, body: &[u8]) -> Vec<u8> {
+        [
+            entry_type.as_bytes(),
+            &[0],
+            &(body.len() as u32).to_be_bytes(),
+            body,
+        ]
+        .concat()
+    }
+
+    /// Data of a `Serato Markers2` frame with a red cue named `Drop` at 1.5 seconds and a loop
+    pub(crate) fn build_markers2() -> Vec<u8> {
+        let payload = [
+            MARKERS2_VERSION.to_vec(),
+            entry("COLOR", &[0, 0xFF, 0x99, 0xFF]),
+            entry(
+                "CUE",
+                &[
+                    &[0, 0],
+                    &1500u32.to_be_bytes()[..],
+                    &[0, 0xCC, 0, 0, 0, 0],
+                    b"Drop\0",
+                ]
+                .concat(),
+            ),
+            entry(
+                "LOOP",
+                &[
+                    &[0, 1][..],
+                    &2000u32.to_be_bytes(),
+                    &4000u32.to_be_bytes(),
+                    &[0xFF; 4],
+                    &[0, 0x27, 0xAA, 0xE1, 0, 1],
+                    b"\0",
+                ]
+                .concat(),
+            ),
+            entry("BPMLOCK", &[0]),
+            vec![0],
+        ]
+        .concat();
+        [&MARKERS2_VERSION[..], &encode_base64(&payload), &[0; 4]].concat()
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode_base64(b"TWFu").unwrap(), b"Man");
+        assert_eq!(decode_base64(b"TWE\n=").unwrap(), b"Ma");
+        assert_eq!(decode_base64(b"TQ\0\0").unwrap(), b"M");
+        assert!(decode_base64(b"T*").is_err());
+    }
+
+    #[test]
+    fn test_parse_markers2() {
+        let markers = SeratoMarkers::parse(&build_markers2()).expect("Failed to parse markers");
+        assert_eq!(
+            markers.cues(),
+            [SeratoCue {
+                index: 0,
+                position_ms: 1500,
+                color: [0xCC, 0, 0],
+                name: "Drop".to_string(),
+            }]
+        );
+        assert_eq!(
+            markers.loops(),
+            [SeratoLoop {
+                index: 1,
+                start_ms: 2000,
+                end_ms: 4000,
+                locked: true,
+                name: String::new(),
+            }]
+        );
+        assert_eq!(markers.track_color(), Some([0xFF, 0x99, 0xFF]));
+        assert_eq!(markers.bpm_locked(), Some(false));
+    }
+
+    #[test]
+    fn test_parse_beat_grid() {
+        let data = [
+            &BEAT_GRID_VERSION[..],
+            &2u32.to_be_bytes(),
+            &0.1f32.to_bits().to_be_bytes(),
+            &16u32.to_be_bytes(),
+            &8.1f32.to_bits().to_be_bytes(),
+            &120f32.to_bits().to_be_bytes(),
+            &[0],
+        ]
+        .concat();
+
+        let beat_grid = SeratoBeatGrid::parse(&data).expect("Failed to parse beat grid");
+        assert_eq!(beat_grid.markers().len(), 2);
+        assert_eq!(beat_grid.markers()[0].beats_to_next(), Some(16));
+        assert_eq!(beat_grid.bpm(), Some(120.0));
+
+        let mut oversized = data.clone();
+        oversized[2..6].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(SeratoBeatGrid::parse(&oversized).is_err());
+    }
+}
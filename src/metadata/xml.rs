@@ -0,0 +1,244 @@
+//! A small XML reader for the element trees metadata chunks embed, attributes are skipped
+
+use crate::errors::{DJWavFixerError, Result};
+
+/// Deepest element nesting read, anything deeper is rejected before it can overflow the stack
+const MAX_ELEMENT_DEPTH: usize = 64;
+
+fn xml_error(message: impl Into<String>) -> DJWavFixerError {
+    DJWavFixerError::MetadataError(message.into())
+}
+
+/// An element with its text and child elements
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct XmlElement {
+    name: String,
+    text: String,
+    children: Vec<XmlElement>,
+}
+
+impl XmlElement {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Text directly inside the element, without surrounding whitespace
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn children(&self) -> &[XmlElement] {
+        &self.children
+    }
+
+    /// The first child named `name`
+    pub fn child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// The first descendant along `path`, child names separated by `/`
+    pub fn find(&self, path: &str) -> Option<&XmlElement> {
+        path.split('/')
+            .try_fold(self, |element, name| element.child(name))
+    }
+}
+
+/// Replaces the predefined entities and character references
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let character = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            reference => reference
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| reference.strip_prefix('#').map(str::parse))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match character {
+            Some(character) => {
+                decoded.push(character);
+                rest = &rest[end + 1..];
+            }
+            // Not an entity, keep the ampersand as written
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+struct XmlParser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> XmlParser<'a> {
+    /// Moves past `terminator`, returning what came before it
+    fn take_until(&mut self, terminator: &str) -> Result<&'a str> {
+        let end = self
+            .rest
+            .find(terminator)
+            .ok_or_else(|| xml_error(format!("XML ends before '{}'", terminator)))?;
+        let taken = &self.rest[..end];
+        self.rest = &self.rest[end + terminator.len()..];
+        Ok(taken)
+    }
+
+    /// Skips whitespace, the XML declaration, processing instructions, comments and doctypes
+    fn skip_prolog(&mut self) -> Result<()> {
+        loop {
+            self.rest = self.rest.trim_start();
+            if self.rest.starts_with("<?") {
+                self.take_until("?>")?;
+            } else if self.rest.starts_with("<!--") {
+                self.take_until("-->")?;
+            } else if self.rest.starts_with("<!") {
+                self.take_until(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads a start tag, returning the element name and whether the tag closes itself
+    fn start_tag(&mut self) -> Result<(String, bool)> {
+        self.rest = self
+            .rest
+            .strip_prefix('<')
+            .ok_or_else(|| xml_error("Expected an XML element"))?;
+
+        let mut quote = None;
+        let end = self
+            .rest
+            .char_indices()
+            .find(|&(_, character)| match (quote, character) {
+                (None, '"' | '\'') => {
+                    quote = Some(character);
+                    false
+                }
+                (Some(open), _) if open == character => {
+                    quote = None;
+                    false
+                }
+                (None, '>') => true,
+                _ => false,
+            })
+            .map(|(index, _)| index)
+            .ok_or_else(|| xml_error("XML ends inside a tag"))?;
+
+        let tag = &self.rest[..end];
+        self.rest = &self.rest[end + 1..];
+        let self_closing = tag.ends_with('/');
+        let name = tag
+            .trim_end_matches('/')
+            .split(|character: char| character.is_whitespace())
+            .next()
+            .unwrap_or_default();
+        if name.is_empty() {
+            return Err(xml_error("XML element has no name"));
+        }
+
+        Ok((name.to_string(), self_closing))
+    }
+
+    /// Reads an element and everything in it, `depth` counts the elements it is nested in
+    fn element(&mut self, depth: usize) -> Result<XmlElement> {
+        if depth >= MAX_ELEMENT_DEPTH {
+            return Err(xml_error(format!(
+                "XML elements are nested more than {} deep",
+                MAX_ELEMENT_DEPTH
+            )));
+        }
+        let (name, self_closing) = self.start_tag()?;
+        let mut element = XmlElement {
+            name,
+            ..Default::default()
+        };
+        if self_closing {
+            return Ok(element);
+        }
+
+        loop {
+            if let Some(rest) = self.rest.strip_prefix("</") {
+                self.rest = rest;
+                let end_name = self.take_until(">")?.trim();
+                if end_name != element.name {
+                    return Err(xml_error(format!(
+                        "XML element '{}' is closed by '{}'",
+                        element.name, end_name
+                    )));
+                }
+                element.text = element.text.trim().to_string();
+                return Ok(element);
+            } else if let Some(rest) = self.rest.strip_prefix("<![CDATA[") {
+                self.rest = rest;
+                element.text.push_str(self.take_until("]]>")?);
+            } else if self.rest.starts_with("<!--") {
+                self.take_until("-->")?;
+            } else if self.rest.starts_with("<?") {
+                self.take_until("?>")?;
+            } else if self.rest.starts_with('<') {
+                element.children.push(self.element(depth + 1)?);
+            } else if self.rest.is_empty() {
+                return Err(xml_error(format!(
+                    "XML ends before '{}' is closed",
+                    element.name
+                )));
+            } else {
+                let end = self.rest.find('<').unwrap_or(self.rest.len());
+                element.text.push_str(&decode_entities(&self.rest[..end]));
+                self.rest = &self.rest[end..];
+            }
+        }
+    }
+}
+
+/// Parses the root element of an XML document
+pub(crate) fn parse_xml(text: &str) -> Result<XmlElement> {
+    let mut parser = XmlParser { rest: text };
+    parser.skip_prolog()?;
+    parser.element(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_xml() {
+        let root = parse_xml(
+            "<?xml version=\"1.0\"?>\n<!-- recorder -->\n<ROOT a=\"x>y\">\n  <A>Tom &amp; Jerry&#33;</A>\n  \
+             <B><C><![CDATA[<raw>]]></C><D/></B>\n</ROOT>",
+        )
+        .expect("Failed to parse XML");
+
+        assert_eq!(root.name(), "ROOT");
+        assert_eq!(root.child("A").map(XmlElement::text), Some("Tom & Jerry!"));
+        assert_eq!(root.find("B/C").map(XmlElement::text), Some("<raw>"));
+        assert_eq!(root.find("B/D").map(XmlElement::text), Some(""));
+        assert!(parse_xml("<A><B></A>").is_err());
+    }
+
+    #[test]
+    fn test_deep_nesting_is_rejected() {
+        let nested = |depth| format!("{}{}", "<A>".repeat(depth), "</A>".repeat(depth));
+        assert!(parse_xml(&nested(MAX_ELEMENT_DEPTH)).is_ok());
+        assert!(parse_xml(&nested(MAX_ELEMENT_DEPTH + 1)).is_err());
+        assert!(parse_xml(&nested(100_000)).is_err());
+    }
+}
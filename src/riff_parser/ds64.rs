@@ -0,0 +1,114 @@
+use crate::DWORD_SIZE;
+use crate::errors::{DJWavFixerError, Result};
+use crate::riff_parser::DATA_MAGIC;
+
+/// Size of the fixed part of a `ds64` subchunk, before its table
+const DS64_FIXED_SIZE: usize = 28;
+
+/// Size of one `ds64` table entry, a chunk ID and its 64-bit size
+const DS64_TABLE_ENTRY_SIZE: usize = 12;
+
+/// The `ds64` subchunk of RF64/BW64 files, holding the sizes that do not fit in 32 bits
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Ds64 {
+    pub(crate) riff_size: u64,
+    pub(crate) data_size: u64,
+    pub(crate) sample_count: u64,
+    /// 64-bit sizes of subchunks other than `data`
+    pub(crate) table: Vec<([u8; DWORD_SIZE], u64)>,
+}
+
+impl Ds64 {
+    /// The 64-bit size of the subchunk with `id`, for subchunks whose 32-bit size is `0xFFFFFFFF`
+    pub(crate) fn size_of(&self, id: &[u8; DWORD_SIZE]) -> Option<u64> {
+        if *id == DATA_MAGIC {
+            return Some(self.data_size);
+        }
+
+        self.table
+            .iter()
+            .find(|(table_id, _)| table_id == id)
+            .map(|(_, size)| *size)
+    }
+}
+
+impl TryFrom<&[u8]> for Ds64 {
+    type Error = DJWavFixerError;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        if data.len() < DS64_FIXED_SIZE {
+            return Err(DJWavFixerError::RiffHeaderError(format!(
+                "'ds64' subchunk is too short: expected at least {} bytes, got {}",
+                DS64_FIXED_SIZE,
+                data.len()
+            )));
+        }
+
+        let (riff_size, data_size, sample_count, table_length) = unsafe {
+            (
+                u64::from_le_bytes(data[0..8].try_into().unwrap_unchecked()),
+                u64::from_le_bytes(data[8..16].try_into().unwrap_unchecked()),
+                u64::from_le_bytes(data[16..24].try_into().unwrap_unchecked()),
+                u32::from_le_bytes(data[24..28].try_into().unwrap_unchecked()) as usize,
+            )
+        };
+
+        let table_data = &data[DS64_FIXED_SIZE..];
+        if table_length
+            .checked_mul(DS64_TABLE_ENTRY_SIZE)
+            .is_none_or(|table_size| table_data.len() < table_size)
+        {
+            return Err(DJWavFixerError::RiffHeaderError(format!(
+                "'ds64' table declares {} entries but holds {} bytes of them",
+                table_length,
+                table_data.len()
+            )));
+        }
+
+        let table = table_data
+            .chunks_exact(DS64_TABLE_ENTRY_SIZE)
+            .take(table_length)
+            .map(|entry| unsafe {
+                (
+                    entry[0..4].try_into().unwrap_unchecked(),
+                    u64::from_le_bytes(entry[4..12].try_into().unwrap_unchecked()),
+                )
+            })
+            .collect();
+
+        Ok(Self {
+            riff_size,
+            data_size,
+            sample_count,
+            table,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ds64_table_sizes() {
+        let data = [1u64 << 33, 1 << 32, 1000]
+            .into_iter()
+            .flat_map(u64::to_le_bytes)
+            .chain(1u32.to_le_bytes())
+            .chain(*b"LIST")
+            .chain((5u64 << 32).to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let ds64 = Ds64::try_from(data.as_slice()).expect("Failed to parse ds64");
+        assert_eq!(ds64.riff_size, 1 << 33);
+        assert_eq!(ds64.size_of(&DATA_MAGIC), Some(1 << 32));
+        assert_eq!(ds64.size_of(b"LIST"), Some(5 << 32));
+        assert_eq!(ds64.size_of(b"bext"), None);
+
+        assert!(Ds64::try_from(&data[..DS64_FIXED_SIZE + 4]).is_err());
+
+        let mut oversized = data.clone();
+        oversized[24..28].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Ds64::try_from(oversized.as_slice()).is_err());
+    }
+}
@@ -1,15 +1,318 @@
 use crate::DWORD_SIZE;
 
+pub(crate) use ds64::Ds64;
 pub(crate) use riff_chunk::RiffChunk;
 pub(crate) use riff_file::RiffFile;
+pub(crate) use riff_subchunk::RiffSubchunk;
+pub(crate) use riff_writer::RiffWriter;
 
+mod ds64;
 mod riff_chunk;
 mod riff_file;
 mod riff_subchunk;
+mod riff_writer;
 
 pub(crate) const RIFF_MAGIC: [u8; DWORD_SIZE] = *b"RIFF";
+pub(crate) const RF64_MAGIC: [u8; DWORD_SIZE] = *b"RF64";
+pub(crate) const BW64_MAGIC: [u8; DWORD_SIZE] = *b"BW64";
+pub(crate) const DS64_MAGIC: [u8; DWORD_SIZE] = *b"ds64";
 pub(crate) const FMT_MAGIC: [u8; DWORD_SIZE] = *b"fmt ";
 pub(crate) const WAVE_MAGIC: [u8; DWORD_SIZE] = *b"WAVE";
-#[allow(dead_code)]
 pub(crate) const DATA_MAGIC: [u8; DWORD_SIZE] = *b"data";
+pub(crate) const FACT_MAGIC: [u8; DWORD_SIZE] = *b"fact";
+pub(crate) const LIST_MAGIC: [u8; DWORD_SIZE] = *b"LIST";
 pub(crate) const RIFF_CHUNK_HEADER_SIZE: usize = 8; // 4 bytes for type + 4 bytes for size
+/// Stored in 32-bit size fields of RF64/BW64 files whose real size is in `ds64`
+pub(crate) const RF64_SIZE_PLACEHOLDER: u32 = u32::MAX;
+/// Sizes recorders write before the recording ends, and leave behind if it never does
+pub(crate) const UNFINALIZED_SIZES: [u32; 2] = [0, u32::MAX];
+
+/// Size of a chunk body once padded to an even length, as RIFF requires
+pub(crate) fn padded_size(size: u64) -> u64 {
+    size + size % 2
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::riff_subchunk::MAX_LIST_DEPTH;
+    use super::*;
+    use crate::diagnostics::{HeaderField, ParseMode, Severity};
+    use crate::errors::Result;
+    use std::io::Cursor;
+
+    /// Assembles an in-memory `RIFF`/`WAVE` file from the given subchunks, padding odd-sized ones
+    pub(crate) fn build_riff_wave(subchunks: &[([u8; DWORD_SIZE], Vec<u8>)]) -> Vec<u8> {
+        build_riff_wave_with_padding(subchunks, true)
+    }
+
+    /// Like `build_riff_wave`, but leaves out pad bytes when `pad` is false
+    pub(crate) fn build_riff_wave_with_padding(
+        subchunks: &[([u8; DWORD_SIZE], Vec<u8>)],
+        pad: bool,
+    ) -> Vec<u8> {
+        let body = subchunks
+            .iter()
+            .flat_map(|(id, data)| {
+                let pad_length = if pad { data.len() % 2 } else { 0 };
+                id.iter()
+                    .copied()
+                    .chain((data.len() as u32).to_le_bytes())
+                    .chain(data.iter().copied())
+                    .chain(std::iter::repeat_n(0, pad_length))
+            })
+            .collect::<Vec<_>>();
+
+        RIFF_MAGIC
+            .into_iter()
+            .chain(((body.len() + WAVE_MAGIC.len()) as u32).to_le_bytes())
+            .chain(WAVE_MAGIC)
+            .chain(body)
+            .collect()
+    }
+
+    /// Assembles an in-memory `RF64`/`WAVE` file, with the `RIFF` and `data` sizes in `ds64`
+    pub(crate) fn build_rf64_wave(subchunks: &[([u8; DWORD_SIZE], Vec<u8>)]) -> Vec<u8> {
+        let data_size = subchunks
+            .iter()
+            .find(|(id, _)| *id == DATA_MAGIC)
+            .map_or(0, |(_, data)| data.len() as u64);
+        let ds64_size = 28;
+        let riff_size = WAVE_MAGIC.len() as u64
+            + (RIFF_CHUNK_HEADER_SIZE + ds64_size) as u64
+            + subchunks
+                .iter()
+                .map(|(_, data)| RIFF_CHUNK_HEADER_SIZE as u64 + padded_size(data.len() as u64))
+                .sum::<u64>();
+
+        let ds64_data = [riff_size, data_size, 0]
+            .into_iter()
+            .flat_map(u64::to_le_bytes)
+            .chain(0u32.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut file = build_riff_wave(
+            &[(DS64_MAGIC, ds64_data)]
+                .into_iter()
+                .chain(subchunks.iter().cloned())
+                .collect::<Vec<_>>(),
+        );
+
+        file[..DWORD_SIZE].copy_from_slice(&RF64_MAGIC);
+        file[DWORD_SIZE..2 * DWORD_SIZE].copy_from_slice(&RF64_SIZE_PLACEHOLDER.to_le_bytes());
+        let mut position =
+            RIFF_CHUNK_HEADER_SIZE + WAVE_MAGIC.len() + RIFF_CHUNK_HEADER_SIZE + ds64_size;
+        for (id, data) in subchunks {
+            if *id == DATA_MAGIC {
+                file[position + DWORD_SIZE..position + RIFF_CHUNK_HEADER_SIZE]
+                    .copy_from_slice(&RF64_SIZE_PLACEHOLDER.to_le_bytes());
+            }
+            position += RIFF_CHUNK_HEADER_SIZE + padded_size(data.len() as u64) as usize;
+        }
+        file
+    }
+
+    fn parse(file: Vec<u8>, mode: ParseMode) -> Result<RiffFile<Cursor<Vec<u8>>>> {
+        let data_size = file.len() as u64 - RIFF_CHUNK_HEADER_SIZE as u64;
+        RiffFile::try_new(Cursor::new(file), data_size, mode)
+    }
+
+    #[test]
+    fn test_odd_sized_subchunks_are_padded() {
+        let file = build_riff_wave(&[
+            (*b"LIST", b"INFOISFTabc".to_vec()),
+            (DATA_MAGIC, vec![1, 2, 3]),
+        ]);
+        assert_eq!(file.len(), 12 + 8 + 12 + 8 + 4);
+
+        let riff_file = parse(file, ParseMode::Strict).expect("Failed to parse RIFF");
+        let chunk = riff_file.get_riff_chunk().unwrap();
+        assert_eq!(chunk.get_subchunk(&DATA_MAGIC).unwrap().size(), 3);
+        assert!(!chunk.needs_rewrite());
+    }
+
+    #[test]
+    fn test_missing_pad_bytes_are_detected() {
+        let subchunks = [
+            (*b"LIST", b"INFOISFTabc".to_vec()),
+            (DATA_MAGIC, vec![1, 2, 3]),
+        ];
+
+        let riff_file = parse(
+            build_riff_wave_with_padding(&subchunks, false),
+            ParseMode::Strict,
+        )
+        .expect("Failed to parse RIFF");
+        let chunk = riff_file.get_riff_chunk().unwrap();
+        assert_eq!(
+            chunk
+                .subchunks_missing_pad()
+                .map(|subchunk| subchunk.id())
+                .collect::<Vec<_>>(),
+            [*b"LIST", DATA_MAGIC]
+        );
+        assert!(chunk.needs_rewrite());
+    }
+
+    #[test]
+    fn test_writer_pads_odd_sized_subchunks() {
+        let mut writer = RiffWriter::try_new(Cursor::new(vec![]), WAVE_MAGIC).unwrap();
+        writer.write_subchunk(*b"LIST", b"INFOISFTabc").unwrap();
+        writer.write_subchunk(DATA_MAGIC, &[1, 2, 3]).unwrap();
+
+        assert_eq!(
+            writer.finish().unwrap().into_inner(),
+            build_riff_wave(&[
+                (*b"LIST", b"INFOISFTabc".to_vec()),
+                (DATA_MAGIC, vec![1, 2, 3]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_lenient_parse_clamps_truncated_subchunk() {
+        let mut file = build_riff_wave(&[(DATA_MAGIC, vec![0; 8])]);
+        // Claim 16 bytes of audio in both the RIFF and data sizes, as if writing was cut short
+        file[4..8].copy_from_slice(&(4 + 8 + 16u32).to_le_bytes());
+        file[16..20].copy_from_slice(&16u32.to_le_bytes());
+
+        assert!(parse(file.clone(), ParseMode::Strict).is_err());
+
+        let riff_file = parse(file, ParseMode::Lenient).expect("Failed to parse RIFF");
+        let chunk = riff_file.get_riff_chunk().unwrap();
+        assert_eq!(chunk.get_subchunk(&DATA_MAGIC).unwrap().size(), 8);
+
+        let diagnostics = riff_file
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.severity(),
+                    diagnostic.offset(),
+                    diagnostic.field(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            diagnostics,
+            [
+                (Severity::Error, 16, HeaderField::SubchunkSize(DATA_MAGIC)),
+                (Severity::Error, 4, HeaderField::RiffSize),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_data_is_not_unfinalized() {
+        let file = build_riff_wave(&[(DATA_MAGIC, vec![]), (*b"LIST", b"INFO".to_vec())]);
+
+        let riff_file = parse(file, ParseMode::Strict).expect("Failed to parse RIFF");
+        let chunk = riff_file.get_riff_chunk().unwrap();
+        assert_eq!(chunk.get_subchunk(&DATA_MAGIC).unwrap().size(), 0);
+        assert!(!chunk.needs_rewrite());
+    }
+
+    #[test]
+    fn test_repeated_subchunk_ids_are_kept_in_order() {
+        let file = build_riff_wave(&[
+            (*b"LIST", b"INFOISFT".to_vec()),
+            (*b"JUNK", vec![0; 4]),
+            (DATA_MAGIC, vec![1, 2]),
+            (*b"LIST", b"adtl".to_vec()),
+            (*b"JUNK", vec![0; 2]),
+        ]);
+
+        let riff_file = parse(file, ParseMode::Strict).expect("Failed to parse RIFF");
+        let chunk = riff_file.get_riff_chunk().unwrap();
+        assert_eq!(
+            chunk
+                .subchunks()
+                .iter()
+                .map(|subchunk| subchunk.id())
+                .collect::<Vec<_>>(),
+            [*b"LIST", *b"JUNK", DATA_MAGIC, *b"LIST", *b"JUNK"]
+        );
+        assert_eq!(
+            chunk
+                .get_subchunks(b"JUNK")
+                .map(|subchunk| subchunk.size())
+                .collect::<Vec<_>>(),
+            [4, 2]
+        );
+        assert_eq!(chunk.get_subchunk(b"LIST").unwrap().size(), 8);
+    }
+
+    /// Assembles the body of a `LIST` subchunk, padding odd-sized subchunks inside it
+    pub(crate) fn build_list(
+        list_type: [u8; DWORD_SIZE],
+        subchunks: &[([u8; DWORD_SIZE], Vec<u8>)],
+    ) -> Vec<u8> {
+        let mut body = list_type.to_vec();
+        for (id, data) in subchunks {
+            body.extend_from_slice(id);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            body.resize(body.len() + data.len() % 2, 0);
+        }
+        body
+    }
+
+    #[test]
+    fn test_nested_lists_are_parsed_into_a_tree() {
+        let info = build_list(*b"INFO", &[(*b"INAM", b"Name\0".to_vec())]);
+        let adtl = build_list(
+            *b"adtl",
+            &[
+                (*b"labl", b"\x01\0\0\0Cue\0".to_vec()),
+                (LIST_MAGIC, info),
+                (*b"note", b"\x01\0\0\0Note\0".to_vec()),
+            ],
+        );
+        let file = build_riff_wave(&[(LIST_MAGIC, adtl), (DATA_MAGIC, vec![0; 2])]);
+
+        let mut riff_file = parse(file, ParseMode::Strict).expect("Failed to parse RIFF");
+        let (reader, chunk) = riff_file.get_riff_chunk_and_reader().unwrap();
+        assert!(chunk.get_list(b"INFO").is_none());
+
+        let adtl = chunk.get_list_mut(b"adtl").expect("Missing LIST/adtl");
+        let children = adtl
+            .children()
+            .iter()
+            .map(|child| (child.id(), child.list_type()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            children,
+            [
+                (*b"labl", None),
+                (LIST_MAGIC, Some(*b"INFO")),
+                (*b"note", None),
+            ]
+        );
+
+        let name = &mut adtl.children_mut()[1].children_mut()[0];
+        assert_eq!(name.read_data(reader).unwrap(), b"Name\0");
+        let note = &mut adtl.children_mut()[2];
+        assert_eq!(note.read_data(reader).unwrap(), b"\x01\0\0\0Note\0");
+    }
+
+    #[test]
+    fn test_deeply_nested_lists_are_kept_opaque() {
+        let mut list = build_list(*b"INFO", &[(*b"INAM", b"Name\0".to_vec())]);
+        for _ in 0..100 {
+            list = build_list(*b"deep", &[(LIST_MAGIC, list)]);
+        }
+        let file = build_riff_wave(&[(LIST_MAGIC, list), (DATA_MAGIC, vec![0; 2])]);
+
+        let riff_file = parse(file, ParseMode::Strict).expect("Failed to parse RIFF");
+        let mut list = riff_file
+            .get_riff_chunk()
+            .unwrap()
+            .get_subchunk(&LIST_MAGIC)
+            .unwrap();
+        let mut depth = 1;
+        while let [child] = list.children() {
+            list = child;
+            depth += 1;
+        }
+        assert_eq!(depth, MAX_LIST_DEPTH + 1);
+        assert_eq!(list.list_type(), None);
+    }
+}
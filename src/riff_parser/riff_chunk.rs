@@ -1,39 +1,42 @@
-use indexmap::IndexMap;
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, SeekFrom};
 
+use crate::diagnostics::{Diagnostics, HeaderField};
 use crate::errors::{DJWavFixerError, Result};
-use crate::riff_parser::DWORD_SIZE;
 use crate::riff_parser::riff_subchunk::RiffSubchunk;
+use crate::riff_parser::{
+    BW64_MAGIC, DS64_MAGIC, DWORD_SIZE, Ds64, RF64_MAGIC, RF64_SIZE_PLACEHOLDER, RIFF_MAGIC,
+    UNFINALIZED_SIZES,
+};
 
 #[derive(Debug)]
 pub(crate) struct RiffChunk {
     position: u64,
     id: [u8; DWORD_SIZE],
-    size: u32,
+    size: u64,
     format: [u8; DWORD_SIZE],
-    subchunks: IndexMap<[u8; DWORD_SIZE], RiffSubchunk>,
+    /// In file order, IDs may repeat
+    subchunks: Vec<RiffSubchunk>,
+    /// Left with placeholder sizes by a recording that was cut off
+    unfinalized: bool,
 }
 
 impl RiffChunk {
     fn scan_subchunks<R: Read + Seek>(
         reader: &mut R,
-    ) -> Result<IndexMap<[u8; DWORD_SIZE], RiffSubchunk>> {
-        let mut subchunks = IndexMap::new();
-        while let Some(subchunk) = RiffSubchunk::scan_next(reader)? {
-            let subchunk_id = subchunk.id();
-            if subchunks.contains_key(&subchunk_id) {
-                return Err(DJWavFixerError::RiffHeaderError(format!(
-                    "Duplicate subchunk id found: {:?}{}",
-                    subchunk_id,
-                    String::from_utf8_lossy(&subchunk_id)
-                )));
-            }
-            subchunks.insert(subchunk_id, subchunk);
+        mut subchunks: Vec<RiffSubchunk>,
+        ds64: Option<&Ds64>,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Vec<RiffSubchunk>> {
+        while let Some(subchunk) = RiffSubchunk::scan_next(reader, ds64, diagnostics)? {
+            subchunks.push(subchunk);
         }
         Ok(subchunks)
     }
 
-    pub(crate) fn scan_next<R: Read + Seek>(reader: &mut R) -> Result<Option<Self>> {
+    pub(crate) fn scan_next<R: Read + Seek>(
+        reader: &mut R,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Option<Self>> {
         let position = reader.stream_position()?;
 
         let mut id = [0; DWORD_SIZE];
@@ -47,24 +50,70 @@ impl RiffChunk {
 
         let mut size_buffer = [0; DWORD_SIZE];
         reader.read_exact(&mut size_buffer)?;
-        let size = u32::from_le_bytes(size_buffer);
+        let declared_size = u32::from_le_bytes(size_buffer);
+        let mut size = declared_size as u64;
 
         let mut format = [0; DWORD_SIZE];
         reader.read_exact(&mut format)?;
 
-        let subchunks = Self::scan_subchunks(reader)?;
+        let mut subchunks = vec![];
+        let ds64 = if id == RF64_MAGIC || id == BW64_MAGIC {
+            // RF64 keeps its 64-bit sizes in a `ds64` subchunk, which must come first
+            let mut ds64_subchunk = RiffSubchunk::scan_next(reader, None, diagnostics)?
+                .filter(|subchunk| subchunk.id() == DS64_MAGIC)
+                .ok_or_else(|| {
+                    DJWavFixerError::RiffHeaderError(format!(
+                        "{} chunk does not start with a 'ds64' subchunk",
+                        String::from_utf8_lossy(&id)
+                    ))
+                })?;
+
+            let subchunks_position = reader.stream_position()?;
+            let ds64 = Ds64::try_from(ds64_subchunk.read_data(reader)?)?;
+            reader.seek(SeekFrom::Start(subchunks_position))?;
+
+            if size == RF64_SIZE_PLACEHOLDER as u64 {
+                size = ds64.riff_size;
+            }
+            subchunks.push(ds64_subchunk);
+            Some(ds64)
+        } else {
+            None
+        };
+
+        let subchunks = Self::scan_subchunks(reader, subchunks, ds64.as_ref(), diagnostics)?;
 
         let last_subchunk_end = subchunks
             .last()
-            .map(|(_, subchunk)| subchunk.position() + subchunk.size() as u64)
+            .map(|subchunk| subchunk.position() + subchunk.stored_size())
             .unwrap_or(position + id.len() as u64 + DWORD_SIZE as u64 + format.len() as u64);
 
-        if position + size as u64 != last_subchunk_end {
-            return Err(DJWavFixerError::RiffHeaderError(format!(
-                "Chunk size mismatch: expected {} from chunk, but last subchunk ends at {}",
-                position + size as u64,
-                last_subchunk_end
-            )));
+        let mut unfinalized = subchunks.iter().any(|subchunk| subchunk.unfinalized());
+        if id == RIFF_MAGIC
+            && UNFINALIZED_SIZES.contains(&declared_size)
+            && position + size != last_subchunk_end
+        {
+            diagnostics.warning(
+                position + DWORD_SIZE as u64,
+                HeaderField::RiffSize,
+                format!(
+                    "RIFF size is {:#X}, the recording was never finalized",
+                    declared_size
+                ),
+            );
+            size = last_subchunk_end - position;
+            unfinalized = true;
+        } else if position + size != last_subchunk_end {
+            diagnostics.error(
+                position + DWORD_SIZE as u64,
+                HeaderField::RiffSize,
+                format!(
+                    "Chunk size mismatch: expected {} from chunk, but last subchunk ends at {}",
+                    position + size,
+                    last_subchunk_end
+                ),
+            )?;
+            size = last_subchunk_end - position;
         }
 
         Ok(Some(Self {
@@ -73,23 +122,58 @@ impl RiffChunk {
             size,
             format,
             subchunks,
+            unfinalized,
         }))
     }
 
-    #[allow(unused)]
+    /// The first subchunk with `id`
     pub(crate) fn get_subchunk(&self, id: &[u8; DWORD_SIZE]) -> Option<&RiffSubchunk> {
-        self.subchunks.get(id)
+        self.subchunks.iter().find(|subchunk| subchunk.id() == *id)
     }
 
     pub(crate) fn get_subchunk_mut(&mut self, id: &[u8; DWORD_SIZE]) -> Option<&mut RiffSubchunk> {
-        self.subchunks.get_mut(id)
+        self.subchunks
+            .iter_mut()
+            .find(|subchunk| subchunk.id() == *id)
+    }
+
+    /// Every subchunk with `id`, in file order
+    #[allow(unused)]
+    pub(crate) fn get_subchunks<'a>(
+        &'a self,
+        id: &'a [u8; DWORD_SIZE],
+    ) -> impl Iterator<Item = &'a RiffSubchunk> {
+        self.subchunks
+            .iter()
+            .filter(move |subchunk| subchunk.id() == *id)
+    }
+
+    /// The first `LIST` subchunk with form type `list_type`
+    #[allow(unused)]
+    pub(crate) fn get_list(&self, list_type: &[u8; DWORD_SIZE]) -> Option<&RiffSubchunk> {
+        self.subchunks
+            .iter()
+            .find(|subchunk| subchunk.list_type() == Some(*list_type))
     }
 
     #[allow(unused)]
-    pub(crate) fn subchunks(&self) -> &IndexMap<[u8; DWORD_SIZE], RiffSubchunk> {
+    pub(crate) fn get_list_mut(
+        &mut self,
+        list_type: &[u8; DWORD_SIZE],
+    ) -> Option<&mut RiffSubchunk> {
+        self.subchunks
+            .iter_mut()
+            .find(|subchunk| subchunk.list_type() == Some(*list_type))
+    }
+
+    pub(crate) fn subchunks(&self) -> &[RiffSubchunk] {
         &self.subchunks
     }
 
+    pub(crate) fn subchunks_mut(&mut self) -> &mut [RiffSubchunk] {
+        &mut self.subchunks
+    }
+
     pub(crate) fn position(&self) -> u64 {
         self.position
     }
@@ -98,11 +182,28 @@ impl RiffChunk {
         self.id
     }
 
-    pub(crate) fn size(&self) -> u32 {
+    pub(crate) fn size(&self) -> u64 {
         self.size
     }
 
     pub(crate) fn format(&self) -> [u8; DWORD_SIZE] {
         self.format
     }
+
+    /// Whether this is an RF64 or BW64 chunk, whose sizes may go beyond 32 bits
+    pub(crate) fn is_rf64(&self) -> bool {
+        self.id == RF64_MAGIC || self.id == BW64_MAGIC
+    }
+
+    /// Subchunks with an odd size and no pad byte after them
+    pub(crate) fn subchunks_missing_pad(&self) -> impl Iterator<Item = &RiffSubchunk> {
+        self.subchunks
+            .iter()
+            .filter(|subchunk| subchunk.pad_missing())
+    }
+
+    /// Whether the chunk itself needs rewriting, whatever its samples are
+    pub(crate) fn needs_rewrite(&self) -> bool {
+        self.is_rf64() || self.unfinalized || self.subchunks_missing_pad().next().is_some()
+    }
 }
@@ -1,14 +1,17 @@
-use indexmap::IndexMap;
 use std::fmt::{Debug, Formatter};
 use std::io::{Read, Seek};
 
+use crate::DWORD_SIZE;
+use crate::diagnostics::{Diagnostic, Diagnostics, HeaderField, ParseMode};
 use crate::errors::Result;
-use crate::riff_parser::RiffChunk;
-use crate::{DJWavFixerError, DWORD_SIZE};
+use crate::riff_parser::{BW64_MAGIC, RF64_MAGIC, RIFF_MAGIC, RiffChunk};
 
 pub(crate) struct RiffFile<R> {
     file: R,
-    chunks: IndexMap<[u8; DWORD_SIZE], RiffChunk>,
+    /// In file order, IDs may repeat
+    chunks: Vec<RiffChunk>,
+    /// Problems found while parsing, including those of the `WAVE` headers inside
+    diagnostics: Vec<Diagnostic>,
 }
 
 // Must implement Debug manually because of the generic type R
@@ -23,16 +26,43 @@ impl<R> RiffFile<R> {
         &mut self,
         id: &[u8; DWORD_SIZE],
     ) -> Option<(&mut R, &mut RiffChunk)> {
-        self.chunks.get_mut(id).map(|chunk| (&mut self.file, chunk))
+        self.chunks
+            .iter_mut()
+            .find(|chunk| chunk.id() == *id)
+            .map(|chunk| (&mut self.file, chunk))
     }
 
-    #[allow(unused)]
+    /// The `RIFF`, `RF64` or `BW64` chunk holding the file
+    fn riff_chunk_id(&self) -> Option<[u8; DWORD_SIZE]> {
+        [RIFF_MAGIC, RF64_MAGIC, BW64_MAGIC]
+            .into_iter()
+            .find(|id| self.get_chunk(id).is_some())
+    }
+
+    pub(crate) fn get_riff_chunk_and_reader(&mut self) -> Option<(&mut R, &mut RiffChunk)> {
+        let id = self.riff_chunk_id()?;
+        self.get_chunk_and_reader(&id)
+    }
+
+    pub(crate) fn get_riff_chunk(&self) -> Option<&RiffChunk> {
+        self.get_chunk(&self.riff_chunk_id()?)
+    }
+
+    pub(crate) fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub(crate) fn add_diagnostics(&mut self, diagnostics: Diagnostics) {
+        self.diagnostics.extend(diagnostics.into_vec());
+    }
+
+    /// The first chunk with `id`
     pub(crate) fn get_chunk(&self, id: &[u8; DWORD_SIZE]) -> Option<&RiffChunk> {
-        self.chunks.get(id)
+        self.chunks.iter().find(|chunk| chunk.id() == *id)
     }
 
     #[allow(unused)]
-    pub(crate) fn chunks(&self) -> &IndexMap<[u8; DWORD_SIZE], RiffChunk> {
+    pub(crate) fn chunks(&self) -> &[RiffChunk] {
         &self.chunks
     }
 
@@ -43,37 +73,38 @@ impl<R> RiffFile<R> {
 }
 
 impl<R: Read + Seek> RiffFile<R> {
-    fn scan_chunks(reader: &mut R) -> Result<IndexMap<[u8; DWORD_SIZE], RiffChunk>> {
-        let mut chunks = IndexMap::new();
-        while let Some(chunk) = RiffChunk::scan_next(reader)? {
-            let chunk_id = chunk.id();
-            if chunks.contains_key(&chunk_id) {
-                return Err(DJWavFixerError::RiffHeaderError(format!(
-                    "Duplicate chunk id found: {:?}{}",
-                    chunk_id,
-                    String::from_utf8_lossy(&chunk_id)
-                )));
-            }
-            chunks.insert(chunk_id, chunk);
+    fn scan_chunks(reader: &mut R, diagnostics: &mut Diagnostics) -> Result<Vec<RiffChunk>> {
+        let mut chunks = vec![];
+        while let Some(chunk) = RiffChunk::scan_next(reader, diagnostics)? {
+            chunks.push(chunk);
         }
         Ok(chunks)
     }
 
-    pub fn try_new(mut file: R, data_size: u64) -> Result<Self> {
-        let chunks = Self::scan_chunks(&mut file)?;
+    pub fn try_new(mut file: R, data_size: u64, mode: ParseMode) -> Result<Self> {
+        let mut diagnostics = Diagnostics::new(mode);
+        let chunks = Self::scan_chunks(&mut file, &mut diagnostics)?;
 
         let last_chunk_end = chunks
             .last()
-            .map(|(_, chunk)| chunk.position() + chunk.size() as u64)
+            .map(|chunk| chunk.position() + chunk.size())
             .unwrap_or(0);
 
         if last_chunk_end != data_size {
-            return Err(DJWavFixerError::RiffHeaderError(format!(
-                "Data size mismatch: expected {} from file, but last chunk ends at {}",
-                data_size, last_chunk_end
-            )));
+            diagnostics.error(
+                DWORD_SIZE as u64,
+                HeaderField::RiffSize,
+                format!(
+                    "Data size mismatch: expected {} from file, but last chunk ends at {}",
+                    data_size, last_chunk_end
+                ),
+            )?;
         }
 
-        Ok(Self { file, chunks })
+        Ok(Self {
+            file,
+            chunks,
+            diagnostics: diagnostics.into_vec(),
+        })
     }
 }
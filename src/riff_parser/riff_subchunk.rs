@@ -1,19 +1,49 @@
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 
 use crate::DWORD_SIZE;
-use crate::errors::Result;
-use crate::riff_parser::RIFF_CHUNK_HEADER_SIZE;
+use crate::diagnostics::{Diagnostics, HeaderField};
+use crate::errors::{DJWavFixerError, Result};
+use crate::riff_parser::{
+    DATA_MAGIC, Ds64, LIST_MAGIC, RF64_SIZE_PLACEHOLDER, RIFF_CHUNK_HEADER_SIZE, UNFINALIZED_SIZES,
+    padded_size,
+};
+
+/// Lists nested deeper than this are kept as opaque subchunks, a crafted file could otherwise
+/// nest them until the stack overflows
+pub(crate) const MAX_LIST_DEPTH: usize = 16;
 
 #[derive(Debug)]
 pub(crate) struct RiffSubchunk {
     position: u64,
     id: [u8; DWORD_SIZE],
-    size: u32,
+    size: u64,
+    /// Odd-sized subchunk whose writer left out the pad byte after it
+    pad_missing: bool,
+    /// `data` subchunk left with a placeholder size by a recording that was cut off
+    unfinalized: bool,
+    /// Form type of a `LIST` subchunk, such as `INFO` or `adtl`
+    list_type: Option<[u8; DWORD_SIZE]>,
+    /// Subchunks nested in a `LIST`, in file order
+    children: Vec<RiffSubchunk>,
     data: Option<Vec<u8>>,
 }
 
+/// Whether `id` looks like a chunk ID, printable ASCII that does not start with a space
+fn is_chunk_id(id: &[u8]) -> bool {
+    id[0] != b' '
+        && id
+            .iter()
+            .all(|byte| byte.is_ascii_graphic() || *byte == b' ')
+}
+
 impl RiffSubchunk {
-    pub(crate) fn scan_next<R: Read + Seek>(reader: &mut R) -> Result<Option<Self>> {
+    /// Reads the next subchunk header and skips its body, `ds64` gives the sizes of RF64 subchunks
+    /// that are too large for 32 bits
+    pub(crate) fn scan_next<R: Read + Seek>(
+        reader: &mut R,
+        ds64: Option<&Ds64>,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Option<Self>> {
         let position = reader.stream_position()?;
 
         let mut id = [0; 4];
@@ -27,19 +57,184 @@ impl RiffSubchunk {
 
         let mut size_buffer = [0; 4];
         reader.read_exact(&mut size_buffer)?;
-        let size = u32::from_le_bytes(size_buffer);
+        let declared_size = u32::from_le_bytes(size_buffer);
+        let mut size = match (declared_size, ds64) {
+            (RF64_SIZE_PLACEHOLDER, Some(ds64)) => ds64.size_of(&id).ok_or_else(|| {
+                DJWavFixerError::RiffHeaderError(format!(
+                    "Subchunk {} has no size in 'ds64'",
+                    String::from_utf8_lossy(&id)
+                ))
+            })?,
+            (size, _) => size as u64,
+        };
+
+        let body_start = reader.stream_position()?;
+        let remaining = reader.seek(SeekFrom::End(0))? - body_start;
+        reader.seek(SeekFrom::Start(body_start))?;
+
+        let unfinalized = id == DATA_MAGIC
+            && ds64.is_none()
+            && UNFINALIZED_SIZES.contains(&declared_size)
+            && size != remaining
+            && (declared_size != 0 || !Self::is_chunk_id_next(reader)?);
+        if unfinalized {
+            diagnostics.warning(
+                position + DWORD_SIZE as u64,
+                HeaderField::SubchunkSize(id),
+                format!(
+                    "Subchunk {} declares {:#X} bytes, the recording was never finalized, using \
+                     the {} bytes up to the end of the file",
+                    String::from_utf8_lossy(&id),
+                    declared_size,
+                    remaining
+                ),
+            );
+            size = remaining;
+        } else if diagnostics.is_lenient() && size > remaining {
+            diagnostics.error(
+                position + DWORD_SIZE as u64,
+                HeaderField::SubchunkSize(id),
+                format!(
+                    "Subchunk {} is truncated: declares {} bytes, but {} remain",
+                    String::from_utf8_lossy(&id),
+                    size,
+                    remaining
+                ),
+            )?;
+            size = remaining;
+        }
+
+        let (list_type, children) = if id == LIST_MAGIC && size >= DWORD_SIZE as u64 {
+            let (list_type, children) =
+                Self::scan_list(reader, body_start, body_start + size.min(remaining), 1)?;
+            (Some(list_type), children)
+        } else {
+            (None, vec![])
+        };
 
         // Seek forward to the end of the subchunk
-        reader.seek(SeekFrom::Current(size as i64))?;
+        reader.seek(SeekFrom::Start(body_start + size))?;
+
+        let pad_missing = size % 2 == 1 && Self::is_pad_missing(reader)?;
+        if pad_missing {
+            diagnostics.warning(
+                position + RIFF_CHUNK_HEADER_SIZE as u64 + size,
+                HeaderField::PadByte(id),
+                format!(
+                    "Subchunk {} has an odd size but no pad byte after it",
+                    String::from_utf8_lossy(&id)
+                ),
+            );
+        } else {
+            reader.seek(SeekFrom::Current((padded_size(size) - size) as i64))?;
+        }
 
         Ok(Some(Self {
             position,
             id,
             size,
+            pad_missing,
+            unfinalized,
+            list_type,
+            children,
             data: None,
         }))
     }
 
+    /// Reads the form type of a `LIST` body spanning `start..end` and the subchunks nested in
+    /// it. Nested subchunks are cut short at the end of their list, which is only logged, as the
+    /// list is copied as a whole anyway. `depth` counts the lists this one is nested in, itself
+    /// included
+    fn scan_list<R: Read + Seek>(
+        reader: &mut R,
+        start: u64,
+        end: u64,
+        depth: usize,
+    ) -> Result<([u8; DWORD_SIZE], Vec<Self>)> {
+        reader.seek(SeekFrom::Start(start))?;
+        let mut list_type = [0; DWORD_SIZE];
+        reader.read_exact(&mut list_type)?;
+
+        let mut children = vec![];
+        let mut position = start + DWORD_SIZE as u64;
+        while position + RIFF_CHUNK_HEADER_SIZE as u64 <= end {
+            reader.seek(SeekFrom::Start(position))?;
+            let mut id = [0; DWORD_SIZE];
+            reader.read_exact(&mut id)?;
+            let mut size_buffer = [0; DWORD_SIZE];
+            reader.read_exact(&mut size_buffer)?;
+            let mut size = u32::from_le_bytes(size_buffer) as u64;
+
+            let body_start = position + RIFF_CHUNK_HEADER_SIZE as u64;
+            if body_start + size > end {
+                log::debug!(
+                    "Subchunk {} runs {} bytes past the end of its list",
+                    String::from_utf8_lossy(&id),
+                    body_start + size - end
+                );
+                size = end - body_start;
+            }
+
+            let is_list = id == LIST_MAGIC && size >= DWORD_SIZE as u64;
+            if is_list && depth >= MAX_LIST_DEPTH {
+                log::debug!(
+                    "Not looking into a list nested more than {} deep",
+                    MAX_LIST_DEPTH
+                );
+            }
+            let (child_list_type, grandchildren) = if is_list && depth < MAX_LIST_DEPTH {
+                let (list_type, children) =
+                    Self::scan_list(reader, body_start, body_start + size, depth + 1)?;
+                (Some(list_type), children)
+            } else {
+                (None, vec![])
+            };
+
+            children.push(Self {
+                position,
+                id,
+                size,
+                pad_missing: false,
+                unfinalized: false,
+                list_type: child_list_type,
+                children: grandchildren,
+                data: None,
+            });
+            position = body_start + padded_size(size);
+        }
+
+        Ok((list_type, children))
+    }
+
+    /// Whether another subchunk starts at the reader's position, which is left where it was
+    fn is_chunk_id_next<R: Read + Seek>(reader: &mut R) -> Result<bool> {
+        let start = reader.stream_position()?;
+        let mut next = [0; DWORD_SIZE];
+        let found = reader.read_exact(&mut next).is_ok() && is_chunk_id(&next);
+        reader.seek(SeekFrom::Start(start))?;
+        Ok(found)
+    }
+
+    /// Looks at the bytes after an odd-sized subchunk body to tell whether the next subchunk
+    /// starts after a pad byte, as it should, or right away
+    fn is_pad_missing<R: Read + Seek>(reader: &mut R) -> Result<bool> {
+        let body_end = reader.stream_position()?;
+        let mut next = Vec::with_capacity(1 + DWORD_SIZE);
+        reader
+            .by_ref()
+            .take(1 + DWORD_SIZE as u64)
+            .read_to_end(&mut next)?;
+        reader.seek(SeekFrom::Start(body_end))?;
+
+        Ok(match next.len() {
+            // The file ends where the pad byte should be
+            0 => true,
+            length if length > DWORD_SIZE && is_chunk_id(&next[1..]) => false,
+            length if length >= DWORD_SIZE => is_chunk_id(&next[..DWORD_SIZE]),
+            _ => false,
+        })
+    }
+
     pub(crate) fn position(&self) -> u64 {
         self.position
     }
@@ -48,10 +243,48 @@ impl RiffSubchunk {
         self.id
     }
 
-    pub(crate) fn size(&self) -> u32 {
+    pub(crate) fn size(&self) -> u64 {
         self.size
     }
 
+    /// Size the subchunk body takes in the file, including the pad byte if there is one
+    pub(crate) fn stored_size(&self) -> u64 {
+        if self.pad_missing {
+            self.size
+        } else {
+            padded_size(self.size)
+        }
+    }
+
+    pub(crate) fn pad_missing(&self) -> bool {
+        self.pad_missing
+    }
+
+    pub(crate) fn unfinalized(&self) -> bool {
+        self.unfinalized
+    }
+
+    /// Form type of a `LIST` subchunk, `None` for other subchunks
+    pub(crate) fn list_type(&self) -> Option<[u8; DWORD_SIZE]> {
+        self.list_type
+    }
+
+    /// Subchunks nested in a `LIST`, empty for other subchunks
+    #[allow(unused)]
+    pub(crate) fn children(&self) -> &[RiffSubchunk] {
+        &self.children
+    }
+
+    pub(crate) fn children_mut(&mut self) -> &mut [RiffSubchunk] {
+        &mut self.children
+    }
+
+    /// Whether the body is held in memory by `read_data`
+    #[cfg(test)]
+    pub(crate) fn is_cached(&self) -> bool {
+        self.data.is_some()
+    }
+
     pub(crate) fn read_data<R: Read + Seek>(&mut self, reader: &mut R) -> Result<&[u8]> {
         if self.data.is_none() {
             reader.seek(SeekFrom::Start(
@@ -65,4 +298,51 @@ impl RiffSubchunk {
 
         Ok(self.data.as_deref().unwrap())
     }
+
+    /// Positions the reader at the start of the subchunk body and limits it to the body's size
+    pub(crate) fn data_reader<'r, R: Read + Seek>(
+        &self,
+        reader: &'r mut R,
+    ) -> Result<io::Take<&'r mut R>> {
+        reader.seek(SeekFrom::Start(
+            self.position + RIFF_CHUNK_HEADER_SIZE as u64,
+        ))?;
+
+        Ok(reader.take(self.size))
+    }
+
+    /// Copies the subchunk body to the writer without buffering all of it in memory
+    pub(crate) fn copy_data<R: Read + Seek, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<()> {
+        self.copy_data_prefix(reader, writer, self.size)
+    }
+
+    /// Copies the first `length` bytes of the subchunk body to the writer
+    pub(crate) fn copy_data_prefix<R: Read + Seek, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        length: u64,
+    ) -> Result<()> {
+        let length = length.min(self.size);
+        if let Some(data) = self.data.as_deref() {
+            writer.write_all(&data[..length as usize])?;
+            return Ok(());
+        }
+
+        let copied = io::copy(&mut self.data_reader(reader)?.take(length), writer)?;
+        if copied != length {
+            return Err(DJWavFixerError::RiffHeaderError(format!(
+                "Subchunk {} is truncated: expected {} bytes, found {}",
+                String::from_utf8_lossy(&self.id),
+                length,
+                copied
+            )));
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,105 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use crate::DWORD_SIZE;
+use crate::errors::{DJWavFixerError, Result};
+use crate::riff_parser::{RIFF_CHUNK_HEADER_SIZE, RIFF_MAGIC};
+
+/// Streams a single RIFF chunk and its subchunks into a writer, back-patching sizes once known
+pub(crate) struct RiffWriter<W> {
+    writer: W,
+    riff_position: u64,
+    open_subchunk_position: Option<u64>,
+}
+
+impl<W: Write + Seek> RiffWriter<W> {
+    pub(crate) fn try_new(mut writer: W, format: [u8; DWORD_SIZE]) -> Result<Self> {
+        let riff_position = writer.stream_position()?;
+
+        writer.write_all(&RIFF_MAGIC)?;
+        writer.write_all(&0u32.to_le_bytes())?; // Patched in `finish`
+        writer.write_all(&format)?;
+
+        Ok(Self {
+            writer,
+            riff_position,
+            open_subchunk_position: None,
+        })
+    }
+
+    fn patch_size(&mut self, size_position: u64, size: u64) -> Result<()> {
+        let size = u32::try_from(size).map_err(|_| {
+            DJWavFixerError::RiffHeaderError(format!(
+                "Chunk size {} does not fit in a RIFF size field",
+                size
+            ))
+        })?;
+
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(size_position))?;
+        self.writer.write_all(&size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+
+        Ok(())
+    }
+
+    pub(crate) fn begin_subchunk(&mut self, id: [u8; DWORD_SIZE]) -> Result<()> {
+        if self.open_subchunk_position.is_some() {
+            return Err(DJWavFixerError::GeneralError(
+                "Cannot begin a subchunk while another one is still open".to_string(),
+            ));
+        }
+
+        self.open_subchunk_position = Some(self.writer.stream_position()?);
+        self.writer.write_all(&id)?;
+        self.writer.write_all(&0u32.to_le_bytes())?; // Patched in `end_subchunk`
+
+        Ok(())
+    }
+
+    pub(crate) fn end_subchunk(&mut self) -> Result<()> {
+        let position = self.open_subchunk_position.take().ok_or_else(|| {
+            DJWavFixerError::GeneralError("No subchunk is currently open".to_string())
+        })?;
+
+        let end = self.writer.stream_position()?;
+        let size_position = position + DWORD_SIZE as u64;
+        let size = end - size_position - DWORD_SIZE as u64;
+        self.patch_size(size_position, size)?;
+
+        // Odd-sized subchunks are followed by a pad byte that their size leaves out
+        if size % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn write_subchunk(&mut self, id: [u8; DWORD_SIZE], data: &[u8]) -> Result<()> {
+        self.begin_subchunk(id)?;
+        self.writer.write_all(data)?;
+        self.end_subchunk()
+    }
+
+    /// Size the RIFF chunk would declare if it were finished now
+    pub(crate) fn riff_size(&mut self) -> Result<u64> {
+        Ok(self.writer.stream_position()? - self.riff_position - RIFF_CHUNK_HEADER_SIZE as u64)
+    }
+
+    /// Gives direct access to the underlying writer, used for streaming subchunk bodies
+    pub(crate) fn writer(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub(crate) fn finish(mut self) -> Result<W> {
+        if self.open_subchunk_position.is_some() {
+            self.end_subchunk()?;
+        }
+
+        let end = self.writer.stream_position()?;
+        let size_position = self.riff_position + DWORD_SIZE as u64;
+        self.patch_size(size_position, end - size_position - DWORD_SIZE as u64)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
@@ -1,18 +1,19 @@
 use std::fmt::{Debug, Formatter, Write};
-use std::path::PathBuf;
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
 
+use crate::DJWavFixerError;
+use crate::diagnostics::Diagnostic;
+use crate::metadata::{Id3Frame, Metadata};
 use crate::riff_parser::RiffFile;
+use crate::wav_fixer::{self, FixOptions, FixReport, OutputTreeAction};
 
-pub(crate) use wav_format::{WaveFormatExtensible, WaveFormatType};
-
-#[cfg(test)]
-pub(crate) use wav_format::WaveAudioChannels;
+pub(crate) use wav_format::{AdpcmFormat, WaveAudioChannels, WaveFormatExtensible, WaveFormatType};
 
 mod wav_format;
 
 pub(crate) enum WavFileLoadStatus<R> {
     Success {
-        #[allow(unused)]
         riff_file: RiffFile<R>,
         wave_format_info: WaveFormatExtensible,
     },
@@ -54,6 +55,8 @@ impl<R> Debug for WavFileLoadStatus<R> {
 pub struct WavFile<R> {
     pub(crate) path: PathBuf,
     pub(crate) load_status: WavFileLoadStatus<R>,
+    pub(crate) metadata: Metadata,
+    pub(crate) fix_report: Option<FixReport>,
 }
 
 impl<R> WavFile<R> {
@@ -61,61 +64,122 @@ impl<R> WavFile<R> {
         &self.path
     }
 
-    pub fn needs_fixing(&self) -> Option<bool> {
+    /// Tags and other metadata read from the file's chunks
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Frames of the file's embedded ID3v2 tag, empty if it has none
+    pub fn id3_frames(&self) -> &[Id3Frame] {
+        self.metadata
+            .id3()
+            .map(|tag| tag.frames())
+            .unwrap_or_default()
+    }
+
+    /// The outcome of the last fix written for this file, if any
+    pub fn fix_report(&self) -> Option<&FixReport> {
+        self.fix_report.as_ref()
+    }
+
+    /// Problems found in the headers while loading, errors only end up here when parsing leniently
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self.load_status {
+            WavFileLoadStatus::Success { ref riff_file, .. }
+            | WavFileLoadStatus::WavFileInvalid { ref riff_file, .. } => riff_file.diagnostics(),
+            WavFileLoadStatus::RiffFileInvalid { .. } => &[],
+        }
+    }
+
+    /// RF64 and BW64 files are rewritten as RIFF, few players read them, and missing pad bytes
+    /// and inconsistent header fields are repaired
+    fn needs_rewrite(riff_file: &RiffFile<R>) -> bool {
+        !riff_file.diagnostics().is_empty()
+            || riff_file
+                .get_riff_chunk()
+                .is_some_and(|chunk| chunk.needs_rewrite())
+    }
+
+    fn write_diagnostics(&self, mut writer: impl Write) -> crate::Result<()> {
+        if !self.diagnostics().is_empty() {
+            writeln!(writer, "  Diagnostics:")?;
+            for diagnostic in self.diagnostics() {
+                writeln!(writer, "    {}", diagnostic)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether players may have trouble with this file, given the targets in `options`
+    pub fn needs_fixing(&self, options: &FixOptions) -> Option<bool> {
         match self.load_status {
             WavFileLoadStatus::Success {
+                ref riff_file,
                 ref wave_format_info,
-                ..
             } => Some(
-                !wave_format_info.is_sample_bits_supported_by_players()
-                    || !wave_format_info.is_integer_pcm(),
+                Self::needs_rewrite(riff_file)
+                    || !wave_format_info.is_sample_bits_supported_by_players()
+                    || !wave_format_info.is_integer_pcm()
+                    || !wave_format_info
+                        .is_sample_rate_supported_by_players(options.target_sample_rate)
+                    || !wave_format_info.are_channels_supported_by_players(),
             ),
             _ => None,
         }
     }
 
-    pub fn can_fix(&self) -> Option<bool> {
+    pub fn can_fix(&self, options: &FixOptions) -> Option<bool> {
         match self.load_status {
             WavFileLoadStatus::Success {
+                ref riff_file,
                 ref wave_format_info,
-                ..
-            } => {
-                if wave_format_info.is_sample_bits_supported_by_players() {
-                    Some(
-                        self.path.is_file()
-                            && wave_format_info.format_tag == WaveFormatType::WaveFormatExtensible
-                            && wave_format_info.bits_per_sample
-                                == wave_format_info
-                                    .valid_bits_per_sample
-                                    .unwrap_or(wave_format_info.bits_per_sample),
-                    )
-                } else {
-                    Some(false)
-                }
-            }
+            } => Some(
+                self.path.is_file()
+                    && (Self::needs_rewrite(riff_file)
+                        || wav_fixer::plan_fix(wave_format_info, options).is_some()),
+            ),
             _ => None,
         }
     }
 
-    pub fn write_information(&self, mut writer: impl Write) -> crate::Result<()> {
+    pub fn write_information(
+        &self,
+        mut writer: impl Write,
+        options: &FixOptions,
+    ) -> crate::Result<()> {
         writeln!(writer, "  Path: {}", self.path.display())?;
         match self.load_status {
             WavFileLoadStatus::Success {
+                ref riff_file,
                 ref wave_format_info,
-                ..
             } => {
+                if let Some(chunk) = riff_file.get_riff_chunk()
+                    && chunk.is_rf64()
+                {
+                    writeln!(
+                        writer,
+                        "  Container: {}",
+                        String::from_utf8_lossy(&chunk.id())
+                    )?;
+                }
                 wave_format_info.write_information(&mut writer)?;
-                if let Some(needs_fixing) = self.needs_fixing() {
+                self.metadata.write_information(&mut writer)?;
+                self.write_diagnostics(&mut writer)?;
+                if let Some(needs_fixing) = self.needs_fixing(options) {
                     writeln!(writer, "  Needs Fixing: {}", needs_fixing)?;
-                    if needs_fixing {
-                        if let Some(can_fix) = self.can_fix() {
-                            writeln!(writer, "  Can Fix: {}", can_fix)?;
-                        }
+                    if needs_fixing && let Some(can_fix) = self.can_fix(options) {
+                        writeln!(writer, "  Can Fix: {}", can_fix)?;
                     }
                 }
+                if let Some(ref fix_report) = self.fix_report {
+                    writeln!(writer, "  Fixed: true")?;
+                    fix_report.write_information(&mut writer)?;
+                }
             }
             WavFileLoadStatus::WavFileInvalid { ref error, .. } => {
                 writeln!(writer, "  WAV file invalid: {}", error)?;
+                self.write_diagnostics(&mut writer)?;
             }
             WavFileLoadStatus::RiffFileInvalid { ref error } => {
                 writeln!(writer, "  Could not load RIFF structure: {}", error)?;
@@ -125,3 +189,95 @@ impl<R> WavFile<R> {
         Ok(())
     }
 }
+
+impl<R: Read + Seek> WavFile<R> {
+    fn loaded_parts(&mut self) -> crate::Result<(&mut RiffFile<R>, &WaveFormatExtensible)> {
+        match self.load_status {
+            WavFileLoadStatus::Success {
+                ref mut riff_file,
+                ref wave_format_info,
+            } => Ok((riff_file, wave_format_info)),
+            WavFileLoadStatus::WavFileInvalid { ref error, .. }
+            | WavFileLoadStatus::RiffFileInvalid { ref error } => Err(DJWavFixerError::FixError(
+                format!("{} could not be loaded: {}", self.path.display(), error),
+            )),
+        }
+    }
+
+    /// Writes a fixed copy of this file into `writer`, returning the writer once done. Serato
+    /// frames are read back from `writer` to check they came through intact
+    pub fn write_fixed<W: io::Read + io::Write + Seek>(
+        &mut self,
+        writer: W,
+        options: &FixOptions,
+    ) -> crate::Result<W> {
+        let (riff_file, wave_format_info) = self.loaded_parts()?;
+        let mut writer = Some(writer);
+        let (mut parts, mut report) = wav_fixer::write_fixed_wav(
+            riff_file,
+            wave_format_info,
+            |_| {
+                writer.take().ok_or_else(|| {
+                    DJWavFixerError::FixError(
+                        "Fixed file is too large for a single RIFF file".to_string(),
+                    )
+                })
+            },
+            options,
+            wav_fixer::MAX_RIFF_SIZE,
+        )?;
+        let mut writer = parts.remove(0);
+        writer.flush()?;
+        wav_fixer::verify_serato_frames(riff_file, &mut writer, &mut report)?;
+        self.fix_report = Some(report);
+        Ok(writer)
+    }
+
+    /// Replaces the file on disk with its fixed version
+    pub fn fix_in_place(&mut self, options: &FixOptions) -> crate::Result<()> {
+        let path = self.path.clone();
+        self.fix_to_path(&path, options)
+    }
+
+    /// Writes the fixed version of this file to `path`, leaving the original untouched
+    pub fn fix_to_path(&mut self, path: &Path, options: &FixOptions) -> crate::Result<()> {
+        let (riff_file, wave_format_info) = self.loaded_parts()?;
+        self.fix_report = Some(wav_fixer::write_fixed_file(
+            riff_file,
+            wave_format_info,
+            path,
+            options,
+        )?);
+        Ok(())
+    }
+
+    /// Writes this file into `output_root`, mirroring its location relative to `source_root`.
+    /// Fixable files are written fixed, files that need no fixing are handled per
+    /// `options.unfixed_file_action`
+    pub fn write_to_output_tree(
+        &mut self,
+        source_root: &Path,
+        output_root: &Path,
+        options: &FixOptions,
+    ) -> crate::Result<OutputTreeAction> {
+        let output_path = wav_fixer::mirrored_output_path(source_root, &self.path, output_root)?;
+
+        if self.needs_fixing(options) == Some(false) {
+            return wav_fixer::place_unfixed_file(
+                &self.path,
+                &output_path,
+                options.unfixed_file_action,
+            );
+        }
+
+        if wav_fixer::is_same_file(&self.path, &output_path)? {
+            return Err(DJWavFixerError::GeneralError(format!(
+                "Output path `{}` is the same as the source file",
+                output_path.display()
+            )));
+        }
+
+        self.fix_to_path(&output_path, options)?;
+        Ok(OutputTreeAction::Fixed)
+    }
+}
@@ -1,6 +1,8 @@
 use std::fmt::Write;
 use std::fmt::{Display, Formatter};
+use std::num::NonZeroU32;
 
+use crate::diagnostics::{Diagnostics, HeaderField, ParseMode};
 use crate::errors::{DJWavFixerError, Result};
 
 #[repr(u16)]
@@ -77,6 +79,190 @@ impl Display for WaveFormatType {
     }
 }
 
+/// The tail shared by every `KSDATAFORMAT_SUBTYPE_*` GUID that wraps a plain format tag
+const KSDATAFORMAT_SUBTYPE_BASE: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// The SubFormat GUID of a `WAVE_FORMAT_EXTENSIBLE` header, which says how samples are encoded
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum SubFormat {
+    Pcm,
+    IeeeFloat,
+    ALaw,
+    MuLaw,
+    Adpcm,
+    Unknown([u8; 16]),
+}
+
+impl SubFormat {
+    /// The plain format tag that describes the same sample encoding, if there is one
+    pub(crate) fn format_type(&self) -> Option<WaveFormatType> {
+        match self {
+            SubFormat::Pcm => Some(WaveFormatType::IntegerPCM),
+            SubFormat::IeeeFloat => Some(WaveFormatType::FloatPCM),
+            SubFormat::ALaw => Some(WaveFormatType::ALaw),
+            SubFormat::MuLaw => Some(WaveFormatType::ULaw),
+            SubFormat::Adpcm => Some(WaveFormatType::MicrosoftADPCM),
+            SubFormat::Unknown(_) => None,
+        }
+    }
+}
+
+impl From<[u8; 16]> for SubFormat {
+    fn from(guid: [u8; 16]) -> Self {
+        if guid[2..] != KSDATAFORMAT_SUBTYPE_BASE {
+            return SubFormat::Unknown(guid);
+        }
+
+        match u16::from_le_bytes([guid[0], guid[1]]) {
+            1 => SubFormat::Pcm,
+            2 => SubFormat::Adpcm,
+            3 => SubFormat::IeeeFloat,
+            6 => SubFormat::ALaw,
+            7 => SubFormat::MuLaw,
+            _ => SubFormat::Unknown(guid),
+        }
+    }
+}
+
+impl Display for SubFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubFormat::Pcm => write!(f, "PCM"),
+            SubFormat::IeeeFloat => write!(f, "IEEE Float"),
+            SubFormat::ALaw => write!(f, "A-Law"),
+            SubFormat::MuLaw => write!(f, "U-Law"),
+            SubFormat::Adpcm => write!(f, "ADPCM"),
+            SubFormat::Unknown(guid) => {
+                // Registry format, the first three fields are little-endian
+                write!(
+                    f,
+                    "Unknown {{{:08X}-{:04X}-{:04X}-",
+                    u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+                    u16::from_le_bytes([guid[4], guid[5]]),
+                    u16::from_le_bytes([guid[6], guid[7]])
+                )?;
+                for (index, byte) in guid[8..].iter().enumerate() {
+                    if index == 2 {
+                        write!(f, "-")?;
+                    }
+                    write!(f, "{:02X}", byte)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// The extra `fmt ` fields that ADPCM decoders need, stored after cbSize
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum AdpcmFormat {
+    Ima {
+        samples_per_block: u16,
+    },
+    Microsoft {
+        samples_per_block: u16,
+        /// Predictor coefficient pairs, blocks select one by index
+        coefficients: Vec<(i16, i16)>,
+    },
+}
+
+impl AdpcmFormat {
+    pub(crate) fn samples_per_block(&self) -> u16 {
+        match self {
+            AdpcmFormat::Ima { samples_per_block }
+            | AdpcmFormat::Microsoft {
+                samples_per_block, ..
+            } => *samples_per_block,
+        }
+    }
+
+    /// Parses the bytes following cbSize, checking them against the block layout
+    fn try_parse(
+        format_tag: WaveFormatType,
+        channels: u16,
+        block_align: u16,
+        extra_data: &[u8],
+    ) -> Result<Self> {
+        if extra_data.len() < 2 {
+            return Err(DJWavFixerError::WaveFormatError(format!(
+                "{} header is missing samples per block",
+                format_tag
+            )));
+        }
+        let samples_per_block = u16::from_le_bytes([extra_data[0], extra_data[1]]);
+
+        // Each channel has a block header, the rest of the block holds 4-bit samples
+        let (adpcm_format, header_size, samples_in_header) = match format_tag {
+            WaveFormatType::ImaAdpcm => (AdpcmFormat::Ima { samples_per_block }, 4, 1),
+            WaveFormatType::MicrosoftADPCM => {
+                if extra_data.len() < 4 {
+                    return Err(DJWavFixerError::WaveFormatError(
+                        "Microsoft ADPCM header is missing the coefficient count".to_string(),
+                    ));
+                }
+
+                let coefficient_count = u16::from_le_bytes([extra_data[2], extra_data[3]]) as usize;
+                let coefficient_data = &extra_data[4..];
+                if coefficient_count == 0 || coefficient_data.len() < coefficient_count * 4 {
+                    return Err(DJWavFixerError::WaveFormatError(format!(
+                        "Microsoft ADPCM header declares {} coefficients but holds {} bytes of them",
+                        coefficient_count,
+                        coefficient_data.len()
+                    )));
+                }
+
+                let coefficients = coefficient_data
+                    .chunks_exact(4)
+                    .take(coefficient_count)
+                    .map(|pair| {
+                        (
+                            i16::from_le_bytes([pair[0], pair[1]]),
+                            i16::from_le_bytes([pair[2], pair[3]]),
+                        )
+                    })
+                    .collect();
+
+                (
+                    AdpcmFormat::Microsoft {
+                        samples_per_block,
+                        coefficients,
+                    },
+                    7,
+                    2,
+                )
+            }
+            _ => {
+                return Err(DJWavFixerError::WaveFormatError(format!(
+                    "{} is not an ADPCM format",
+                    format_tag
+                )));
+            }
+        };
+
+        let channels = channels as usize;
+        let header_bytes = header_size * channels;
+        if channels == 0 || (block_align as usize) <= header_bytes {
+            return Err(DJWavFixerError::WaveFormatError(format!(
+                "Block align {} is too small for {} {} channel(s)",
+                block_align, format_tag, channels
+            )));
+        }
+
+        let calculated_samples_per_block =
+            (block_align as usize - header_bytes) * 2 / channels + samples_in_header;
+        if samples_per_block as usize != calculated_samples_per_block {
+            return Err(DJWavFixerError::WaveFormatError(format!(
+                "Samples per block mismatch: expected {}, got {}",
+                calculated_samples_per_block, samples_per_block
+            )));
+        }
+
+        Ok(adpcm_format)
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Debug, PartialEq)]
 pub struct WaveFormatExtensible {
@@ -93,6 +279,87 @@ pub struct WaveFormatExtensible {
 }
 
 impl WaveFormatExtensible {
+    /// Builds a plain 16-byte `WAVE_FORMAT_PCM` header, deriving block align and byte rate
+    pub(crate) fn integer_pcm(
+        channels: WaveAudioChannels,
+        sample_rate: u32,
+        bits_per_sample: u16,
+    ) -> Self {
+        let block_align = channels.as_u16() * bits_per_sample.div_ceil(8);
+        Self {
+            format_tag: WaveFormatType::IntegerPCM,
+            channels,
+            sample_rate,
+            avg_bytes_per_second: sample_rate * block_align as u32,
+            block_align,
+            bits_per_sample,
+            cb_size: 0,
+            valid_bits_per_sample: None,
+            channel_mask: 0,
+            subformat_data: vec![],
+        }
+    }
+
+    /// Serializes the header back into the layout expected inside a `fmt ` subchunk
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(18 + self.cb_size as usize);
+        data.extend_from_slice(&(self.format_tag as u16).to_le_bytes());
+        data.extend_from_slice(&self.channels.as_u16().to_le_bytes());
+        data.extend_from_slice(&self.sample_rate.to_le_bytes());
+        data.extend_from_slice(&self.avg_bytes_per_second.to_le_bytes());
+        data.extend_from_slice(&self.block_align.to_le_bytes());
+        data.extend_from_slice(&self.bits_per_sample.to_le_bytes());
+
+        // Plain PCM headers are written without cbSize, as most players expect exactly 16 bytes
+        if self.format_tag == WaveFormatType::IntegerPCM {
+            return data;
+        }
+
+        data.extend_from_slice(&self.cb_size.to_le_bytes());
+        if self.format_tag == WaveFormatType::WaveFormatExtensible {
+            data.extend_from_slice(
+                &self
+                    .valid_bits_per_sample
+                    .unwrap_or(self.bits_per_sample)
+                    .to_le_bytes(),
+            );
+            data.extend_from_slice(&self.channel_mask.to_le_bytes());
+        }
+        data.extend_from_slice(&self.subformat_data);
+
+        data
+    }
+
+    /// The ADPCM block layout, for the ADPCM formats
+    pub(crate) fn adpcm_format(&self) -> Option<AdpcmFormat> {
+        AdpcmFormat::try_parse(
+            self.format_tag,
+            self.channels.as_u16(),
+            self.block_align,
+            &self.subformat_data,
+        )
+        .ok()
+    }
+
+    /// The SubFormat GUID, for `WAVE_FORMAT_EXTENSIBLE` headers
+    pub(crate) fn sub_format(&self) -> Option<SubFormat> {
+        if self.format_tag != WaveFormatType::WaveFormatExtensible {
+            return None;
+        }
+
+        let guid: [u8; 16] = self.subformat_data.get(..16)?.try_into().ok()?;
+        Some(SubFormat::from(guid))
+    }
+
+    /// The format tag that describes how samples are actually encoded, looking through
+    /// `WAVE_FORMAT_EXTENSIBLE` to its SubFormat. `None` for unknown SubFormats
+    pub(crate) fn sample_format(&self) -> Option<WaveFormatType> {
+        match self.format_tag {
+            WaveFormatType::WaveFormatExtensible => self.sub_format()?.format_type(),
+            format_tag => Some(format_tag),
+        }
+    }
+
     pub(crate) fn is_integer_pcm(&self) -> bool {
         self.format_tag == WaveFormatType::IntegerPCM
     }
@@ -101,7 +368,15 @@ impl WaveFormatExtensible {
         self.bits_per_sample == 16 || self.bits_per_sample == 24
     }
 
-    #[allow(unused)]
+    /// Whether the sample rate is at most `target_sample_rate`, any rate is fine without a target
+    pub(crate) fn is_sample_rate_supported_by_players(
+        &self,
+        target_sample_rate: Option<NonZeroU32>,
+    ) -> bool {
+        target_sample_rate
+            .is_none_or(|target_sample_rate| self.sample_rate <= target_sample_rate.get())
+    }
+
     pub(crate) fn are_channels_supported_by_players(&self) -> bool {
         matches!(
             self.channels,
@@ -130,6 +405,18 @@ impl WaveFormatExtensible {
             writeln!(writer, "  Channel Mask: {:#X}", self.channel_mask)?;
         }
 
+        if let Some(sub_format) = self.sub_format() {
+            writeln!(writer, "  Subformat: {}", sub_format)?;
+        }
+
+        if let Some(adpcm_format) = self.adpcm_format() {
+            writeln!(
+                writer,
+                "  Samples Per Block: {}",
+                adpcm_format.samples_per_block()
+            )?;
+        }
+
         if !self.subformat_data.is_empty() {
             writeln!(
                 writer,
@@ -146,6 +433,14 @@ impl TryFrom<&[u8]> for WaveFormatExtensible {
     type Error = DJWavFixerError;
 
     fn try_from(data: &[u8]) -> Result<Self> {
+        Self::parse(data, 0, &mut Diagnostics::new(ParseMode::Strict))
+    }
+}
+
+impl WaveFormatExtensible {
+    /// Parses the body of a `fmt ` subchunk found at byte `offset`, inconsistent fields are
+    /// corrected when `diagnostics` is lenient
+    pub(crate) fn parse(data: &[u8], offset: u64, diagnostics: &mut Diagnostics) -> Result<Self> {
         if data.len() < 16 {
             return Err(DJWavFixerError::WaveFormatError(format!(
                 "Data is too short to contain WaveFormatExtensible (data size is {})",
@@ -157,10 +452,10 @@ impl TryFrom<&[u8]> for WaveFormatExtensible {
             format_tag,
             channels,
             sample_rate,
-            avg_bytes_per_second,
-            block_align,
+            mut avg_bytes_per_second,
+            mut block_align,
             bits_per_sample,
-            cb_size,
+            mut cb_size,
         ) = unsafe {
             (
                 WaveFormatType::try_from(u16::from_le_bytes(
@@ -182,24 +477,38 @@ impl TryFrom<&[u8]> for WaveFormatExtensible {
             )
         };
 
+        // Nothing can be decoded without a channel, and block sizes derived from it would be 0
+        if channels.as_u16() == 0 {
+            return Err(DJWavFixerError::WaveFormatError(
+                "Header declares no channels".to_string(),
+            ));
+        }
+
         let (valid_bits_per_sample, channel_mask, subformat_data) = match format_tag {
             WaveFormatType::IntegerPCM | WaveFormatType::FloatPCM => {
                 if cb_size != 0 {
-                    return Err(DJWavFixerError::WaveFormatError(
+                    diagnostics.error(
+                        offset + 16,
+                        HeaderField::CbSize,
                         "cbSize should be 0 for Integer/Float PCM".to_string(),
-                    ));
+                    )?;
+                    cb_size = 0;
                 }
                 (None, 0, vec![])
             }
-            WaveFormatType::MicrosoftADPCM => {
-                return Err(DJWavFixerError::WaveFormatError(
-                    "Microsoft ADPCM format is not supported".to_string(),
-                ));
-            }
-            WaveFormatType::ImaAdpcm => {
-                return Err(DJWavFixerError::WaveFormatError(
-                    "IMA_ADPCM format is not supported".to_string(),
-                ));
+            WaveFormatType::MicrosoftADPCM | WaveFormatType::ImaAdpcm => {
+                if data.len() < 18 + cb_size as usize {
+                    return Err(DJWavFixerError::WaveFormatError(format!(
+                        "Data is too short for the {} header",
+                        format_tag
+                    )));
+                }
+
+                // The extra bytes are kept as-is, `adpcm_format` gives the parsed view of them
+                let extra_data = data[18..18 + cb_size as usize].to_vec();
+                AdpcmFormat::try_parse(format_tag, channels.as_u16(), block_align, &extra_data)?;
+
+                (None, 0, extra_data)
             }
             WaveFormatType::WaveFormatExtensible => {
                 if cb_size < 22 {
@@ -231,22 +540,51 @@ impl TryFrom<&[u8]> for WaveFormatExtensible {
             }
         };
 
+        if matches!(
+            format_tag,
+            WaveFormatType::MicrosoftADPCM | WaveFormatType::ImaAdpcm
+        ) {
+            // Block align and byte rate describe compressed blocks, which `AdpcmFormat` checked
+            return Ok(Self {
+                format_tag,
+                channels,
+                sample_rate,
+                avg_bytes_per_second,
+                block_align,
+                bits_per_sample,
+                cb_size,
+                valid_bits_per_sample,
+                channel_mask,
+                subformat_data,
+            });
+        }
+
         let bits_per_sample_storage =
             valid_bits_per_sample.unwrap_or(bits_per_sample).div_ceil(8) * 8; // Round up to nearest byte
         let calculated_block_align = channels.as_u16() * (bits_per_sample_storage / 8);
         if block_align != calculated_block_align {
-            return Err(DJWavFixerError::WaveFormatError(format!(
-                "Block align mismatch: expected {}, got {}",
-                calculated_block_align, block_align
-            )));
+            diagnostics.error(
+                offset + 12,
+                HeaderField::BlockAlign,
+                format!(
+                    "Block align mismatch: expected {}, got {}",
+                    calculated_block_align, block_align
+                ),
+            )?;
+            block_align = calculated_block_align;
         }
 
         let calculated_avg_bytes_per_second = sample_rate * block_align as u32;
         if avg_bytes_per_second != calculated_avg_bytes_per_second {
-            return Err(DJWavFixerError::WaveFormatError(format!(
-                "Average bytes per second mismatch: expected {}, got {}",
-                calculated_avg_bytes_per_second, avg_bytes_per_second
-            )));
+            diagnostics.error(
+                offset + 8,
+                HeaderField::AvgBytesPerSecond,
+                format!(
+                    "Average bytes per second mismatch: expected {}, got {}",
+                    calculated_avg_bytes_per_second, avg_bytes_per_second
+                ),
+            )?;
+            avg_bytes_per_second = calculated_avg_bytes_per_second;
         }
 
         Ok(Self {
@@ -263,3 +601,133 @@ impl TryFrom<&[u8]> for WaveFormatExtensible {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STANDARD_COEFFICIENTS: [(i16, i16); 7] = [
+        (256, 0),
+        (512, -256),
+        (0, 0),
+        (192, 64),
+        (240, 0),
+        (460, -208),
+        (392, -232),
+    ];
+
+    fn microsoft_adpcm_fmt(samples_per_block: u16) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&(WaveFormatType::MicrosoftADPCM as u16).to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&22050u32.to_le_bytes());
+        data.extend_from_slice(&11155u32.to_le_bytes());
+        data.extend_from_slice(&256u16.to_le_bytes());
+        data.extend_from_slice(&4u16.to_le_bytes());
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.extend_from_slice(&samples_per_block.to_le_bytes());
+        data.extend_from_slice(&(STANDARD_COEFFICIENTS.len() as u16).to_le_bytes());
+        for (coefficient1, coefficient2) in STANDARD_COEFFICIENTS {
+            data.extend_from_slice(&coefficient1.to_le_bytes());
+            data.extend_from_slice(&coefficient2.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_zero_channels_are_rejected() {
+        let mut data = WaveFormatExtensible::integer_pcm(1.into(), 44100, 16).to_bytes();
+        // With no channels, block align and byte rate of 0 look consistent
+        data[2..4].copy_from_slice(&0u16.to_le_bytes());
+        data[8..12].copy_from_slice(&0u32.to_le_bytes());
+        data[12..14].copy_from_slice(&0u16.to_le_bytes());
+
+        assert!(matches!(
+            WaveFormatExtensible::try_from(data.as_slice()),
+            Err(DJWavFixerError::WaveFormatError(_))
+        ));
+    }
+
+    #[test]
+    fn test_microsoft_adpcm_header() {
+        let data = microsoft_adpcm_fmt(500);
+        let wave_format_info =
+            WaveFormatExtensible::try_from(data.as_slice()).expect("Failed to parse fmt");
+
+        assert_eq!(
+            wave_format_info.adpcm_format(),
+            Some(AdpcmFormat::Microsoft {
+                samples_per_block: 500,
+                coefficients: STANDARD_COEFFICIENTS.to_vec(),
+            })
+        );
+        assert_eq!(wave_format_info.to_bytes(), data);
+    }
+
+    #[test]
+    fn test_sub_format_guid() {
+        let mut guid = [0; 16];
+        guid[2..].copy_from_slice(&KSDATAFORMAT_SUBTYPE_BASE);
+
+        guid[0] = 3;
+        assert_eq!(SubFormat::from(guid), SubFormat::IeeeFloat);
+        guid[0] = 7;
+        assert_eq!(SubFormat::from(guid), SubFormat::MuLaw);
+
+        // Dolby AC-3 SPDIF is a registered extensible subtype with no plain format tag equivalent
+        guid[0] = 0x92;
+        assert_eq!(SubFormat::from(guid), SubFormat::Unknown(guid));
+        assert_eq!(SubFormat::from(guid).format_type(), None);
+
+        guid[15] = 0;
+        assert_eq!(
+            SubFormat::from(guid).to_string(),
+            "Unknown {00000092-0000-0010-8000-00AA00389B00}"
+        );
+    }
+
+    #[test]
+    fn test_adpcm_samples_per_block_mismatch() {
+        let data = microsoft_adpcm_fmt(505);
+        assert!(matches!(
+            WaveFormatExtensible::try_from(data.as_slice()),
+            Err(DJWavFixerError::WaveFormatError(_))
+        ));
+    }
+
+    #[test]
+    fn test_lenient_parse_corrects_inconsistent_fields() {
+        let mut data = WaveFormatExtensible::integer_pcm(2.into(), 44100, 16).to_bytes();
+        data[8..12].copy_from_slice(&44100u32.to_le_bytes());
+        data[12..14].copy_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data[16..18].copy_from_slice(&4u16.to_le_bytes());
+
+        assert_eq!(
+            WaveFormatExtensible::try_from(data.as_slice()),
+            Err(DJWavFixerError::WaveFormatError(
+                "cbSize should be 0 for Integer/Float PCM".to_string()
+            ))
+        );
+
+        let mut diagnostics = Diagnostics::new(ParseMode::Lenient);
+        let wave_format_info = WaveFormatExtensible::parse(&data, 20, &mut diagnostics)
+            .expect("Failed to parse fmt leniently");
+        assert_eq!(
+            wave_format_info,
+            WaveFormatExtensible::integer_pcm(2.into(), 44100, 16)
+        );
+        assert_eq!(
+            diagnostics
+                .into_vec()
+                .iter()
+                .map(|diagnostic| (diagnostic.offset(), diagnostic.field()))
+                .collect::<Vec<_>>(),
+            [
+                (36, HeaderField::CbSize),
+                (32, HeaderField::BlockAlign),
+                (28, HeaderField::AvgBytesPerSecond),
+            ]
+        );
+    }
+}
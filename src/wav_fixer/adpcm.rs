@@ -0,0 +1,257 @@
+//! IMA and Microsoft ADPCM block decoding to 16-bit linear PCM
+
+use crate::wav_file::AdpcmFormat;
+
+const IMA_STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+const IMA_INDEX_TABLE: [i32; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+
+/// Scales the Microsoft ADPCM step size by the magnitude of the last nibble, in 1/256ths
+const MICROSOFT_ADAPTATION_TABLE: [i32; 16] = [
+    230, 230, 230, 230, 307, 409, 512, 614, 768, 614, 512, 409, 307, 230, 230, 230,
+];
+
+/// Smallest step size a Microsoft ADPCM decoder lets the delta shrink to
+const MICROSOFT_MINIMUM_DELTA: i32 = 16;
+/// Largest step size, as in ffmpeg, so scaling it by the adaptation table cannot overflow
+const MICROSOFT_MAXIMUM_DELTA: i32 = i32::MAX / 768;
+
+fn read_i16(data: &[u8], offset: usize) -> i32 {
+    i16::from_le_bytes([data[offset], data[offset + 1]]) as i32
+}
+
+fn clamp_to_i16(sample: i32) -> i32 {
+    sample.clamp(i16::MIN as i32, i16::MAX as i32)
+}
+
+struct ImaChannelState {
+    predictor: i32,
+    step_index: i32,
+}
+
+impl ImaChannelState {
+    fn decode_nibble(&mut self, nibble: u8) -> i32 {
+        let step = IMA_STEP_TABLE[self.step_index as usize];
+
+        let mut difference = step >> 3;
+        if nibble & 1 != 0 {
+            difference += step >> 2;
+        }
+        if nibble & 2 != 0 {
+            difference += step >> 1;
+        }
+        if nibble & 4 != 0 {
+            difference += step;
+        }
+        if nibble & 8 != 0 {
+            difference = -difference;
+        }
+
+        self.predictor = clamp_to_i16(self.predictor + difference);
+        self.step_index = (self.step_index + IMA_INDEX_TABLE[nibble as usize]).clamp(0, 88);
+        self.predictor
+    }
+}
+
+/// Decodes an IMA block, where each channel's nibbles come in interleaved groups of 4 bytes
+fn decode_ima_block(block: &[u8], channels: usize, output: &mut Vec<i32>) {
+    let header_size = 4 * channels;
+    if block.len() < header_size {
+        return;
+    }
+
+    // Each channel header holds the first sample and the step index to continue from
+    let mut states = (0..channels)
+        .map(|channel| ImaChannelState {
+            predictor: read_i16(block, 4 * channel),
+            step_index: (block[4 * channel + 2] as i32).clamp(0, 88),
+        })
+        .collect::<Vec<_>>();
+    output.extend(states.iter().map(|state| state.predictor));
+
+    let mut frames = [0i32; 8];
+    let mut decoded = vec![[0i32; 8]; channels];
+    for group in block[header_size..].chunks_exact(4 * channels) {
+        for (channel, bytes) in group.chunks_exact(4).enumerate() {
+            for (index, byte) in bytes.iter().enumerate() {
+                // Low nibble comes first
+                frames[2 * index] = states[channel].decode_nibble(byte & 0x0F);
+                frames[2 * index + 1] = states[channel].decode_nibble(byte >> 4);
+            }
+            decoded[channel] = frames;
+        }
+
+        for frame in 0..8 {
+            output.extend(decoded.iter().map(|samples| samples[frame]));
+        }
+    }
+}
+
+struct MicrosoftChannelState {
+    coefficients: (i32, i32),
+    delta: i32,
+    sample1: i32,
+    sample2: i32,
+}
+
+impl MicrosoftChannelState {
+    fn decode_nibble(&mut self, nibble: u8) -> i32 {
+        let (coefficient1, coefficient2) = self.coefficients;
+        // Hostile coefficients and deltas overflow 32 bits, the sample is clamped anyway
+        let predicted = (self.sample1 as i64 * coefficient1 as i64
+            + self.sample2 as i64 * coefficient2 as i64)
+            >> 8;
+        // Nibbles are signed 4-bit values
+        let signed_nibble = ((nibble as i8) << 4 >> 4) as i64;
+        let sample = (predicted + signed_nibble * self.delta as i64)
+            .clamp(i16::MIN as i64, i16::MAX as i64) as i32;
+
+        self.delta = ((MICROSOFT_ADAPTATION_TABLE[nibble as usize] * self.delta) >> 8)
+            .clamp(MICROSOFT_MINIMUM_DELTA, MICROSOFT_MAXIMUM_DELTA);
+        self.sample2 = self.sample1;
+        self.sample1 = sample;
+        sample
+    }
+}
+
+/// Decodes a Microsoft ADPCM block, whose header fields are each interleaved by channel
+fn decode_microsoft_block(
+    block: &[u8],
+    channels: usize,
+    coefficients: &[(i16, i16)],
+    output: &mut Vec<i32>,
+) {
+    let header_size = 7 * channels;
+    if block.len() < header_size {
+        return;
+    }
+
+    let mut states = (0..channels)
+        .map(|channel| {
+            let (coefficient1, coefficient2) = coefficients
+                .get(block[channel] as usize)
+                .copied()
+                .unwrap_or_else(|| {
+                    log::warn!(
+                        "ADPCM block uses predictor {} of {}, falling back to the first",
+                        block[channel],
+                        coefficients.len()
+                    );
+                    coefficients[0]
+                });
+
+            MicrosoftChannelState {
+                coefficients: (coefficient1 as i32, coefficient2 as i32),
+                delta: read_i16(block, channels + 2 * channel),
+                sample1: read_i16(block, 3 * channels + 2 * channel),
+                sample2: read_i16(block, 5 * channels + 2 * channel),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // The header holds the first two samples, oldest last
+    output.extend(states.iter().map(|state| state.sample2));
+    output.extend(states.iter().map(|state| state.sample1));
+
+    // High nibble comes first, nibbles cycle through the channels
+    let nibbles = block[header_size..]
+        .iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0F]);
+    for (index, nibble) in nibbles.enumerate() {
+        output.push(states[index % channels].decode_nibble(nibble));
+    }
+}
+
+/// Decodes one ADPCM block into interleaved 16-bit samples, a short final block decodes as far as
+/// its data goes
+pub(crate) fn decode_block(
+    adpcm_format: &AdpcmFormat,
+    channels: usize,
+    block: &[u8],
+    output: &mut Vec<i32>,
+) {
+    match adpcm_format {
+        AdpcmFormat::Ima { .. } => decode_ima_block(block, channels, output),
+        AdpcmFormat::Microsoft { coefficients, .. } => {
+            decode_microsoft_block(block, channels, coefficients, output)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ima_mono_block() {
+        let adpcm_format = AdpcmFormat::Ima {
+            samples_per_block: 9,
+        };
+        let block = [0, 0, 0, 0, 0x07, 0, 0, 0];
+
+        let mut output = vec![];
+        decode_block(&adpcm_format, 1, &block, &mut output);
+
+        assert_eq!(output, [0, 11, 13, 14, 15, 16, 17, 18, 19]);
+    }
+
+    #[test]
+    fn test_ima_stereo_block_is_interleaved() {
+        let adpcm_format = AdpcmFormat::Ima {
+            samples_per_block: 9,
+        };
+        let mut block = vec![0x10, 0, 0, 0, 0xF0, 0xFF, 0, 0];
+        block.extend_from_slice(&[0x07, 0, 0, 0]);
+        block.extend_from_slice(&[0x0F, 0, 0, 0]);
+
+        let mut output = vec![];
+        decode_block(&adpcm_format, 2, &block, &mut output);
+
+        assert_eq!(output.len(), 18);
+        assert_eq!(&output[..6], [16, -16, 27, -27, 29, -25]);
+    }
+
+    #[test]
+    fn test_microsoft_mono_block() {
+        let adpcm_format = AdpcmFormat::Microsoft {
+            samples_per_block: 4,
+            coefficients: vec![(256, 0)],
+        };
+        let mut block = vec![0];
+        block.extend_from_slice(&16i16.to_le_bytes());
+        block.extend_from_slice(&100i16.to_le_bytes());
+        block.extend_from_slice(&50i16.to_le_bytes());
+        block.push(0x1F);
+
+        let mut output = vec![];
+        decode_block(&adpcm_format, 1, &block, &mut output);
+
+        assert_eq!(output, [50, 100, 116, 100]);
+    }
+
+    #[test]
+    fn test_microsoft_delta_cannot_overflow() {
+        let adpcm_format = AdpcmFormat::Microsoft {
+            samples_per_block: 2002,
+            coefficients: vec![(256, 0)],
+        };
+        let mut block = vec![0];
+        block.extend_from_slice(&i16::MAX.to_le_bytes());
+        block.extend_from_slice(&0i16.to_le_bytes());
+        block.extend_from_slice(&0i16.to_le_bytes());
+        // Each 0x8 nibble is -8 steps and grows the delta by the largest factor
+        block.extend_from_slice(&[0x88; 1000]);
+
+        let mut output = vec![];
+        decode_block(&adpcm_format, 1, &block, &mut output);
+
+        assert_eq!(output.len(), 2002);
+        assert!(output[2..].iter().all(|sample| *sample == i16::MIN as i32));
+    }
+}
@@ -0,0 +1,167 @@
+//! Multichannel to stereo downmixing, following the speaker positions in `channel_mask`
+
+use std::f64::consts::FRAC_1_SQRT_2;
+
+/// How channels are placed when a multichannel file has no `channel_mask`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum UnknownChannelLayout {
+    /// Assume the default WAVE speaker order: front left, front right, centre, LFE, back left, ...
+    #[default]
+    SpeakerOrder,
+    /// Keep the first two channels as left and right, drop the rest
+    FirstPair,
+    /// Send odd channels left and even channels right, at equal level
+    Alternate,
+}
+
+/// Left and right downmix coefficients for each `channel_mask` bit, in bit order, after ITU-R
+/// BS.775 where it covers the speaker. LFE is left out, as the standard does
+const SPEAKER_COEFFICIENTS: [(f64, f64); 18] = [
+    // Front left, front right, front centre, LFE
+    (1.0, 0.0),
+    (0.0, 1.0),
+    (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+    (0.0, 0.0),
+    // Back left, back right
+    (FRAC_1_SQRT_2, 0.0),
+    (0.0, FRAC_1_SQRT_2),
+    // Front left of centre, front right of centre
+    (1.0, 0.0),
+    (0.0, 1.0),
+    // Back centre
+    (0.5, 0.5),
+    // Side left, side right
+    (FRAC_1_SQRT_2, 0.0),
+    (0.0, FRAC_1_SQRT_2),
+    // Top centre, top front left, top front centre, top front right
+    (0.5, 0.5),
+    (FRAC_1_SQRT_2, 0.0),
+    (0.5, 0.5),
+    (0.0, FRAC_1_SQRT_2),
+    // Top back left, top back centre, top back right
+    (0.5, 0.0),
+    (0.5 * FRAC_1_SQRT_2, 0.5 * FRAC_1_SQRT_2),
+    (0.0, 0.5),
+];
+
+/// Mixes interleaved frames of any channel count down to stereo
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Downmix {
+    /// Left and right coefficients for each source channel
+    coefficients: Vec<(f64, f64)>,
+}
+
+impl Downmix {
+    /// Works out the coefficients for `channels` channels placed as `channel_mask` says, using
+    /// `unknown_layout` when the mask is zero
+    pub(crate) fn new(
+        channels: usize,
+        channel_mask: u32,
+        unknown_layout: UnknownChannelLayout,
+    ) -> Self {
+        let coefficients = match (channel_mask, unknown_layout) {
+            (0, UnknownChannelLayout::FirstPair) => (0..channels)
+                .map(|channel| match channel {
+                    0 => (1.0, 0.0),
+                    1 => (0.0, 1.0),
+                    _ => (0.0, 0.0),
+                })
+                .collect(),
+            (0, UnknownChannelLayout::Alternate) => (0..channels)
+                .map(|channel| match channel % 2 {
+                    0 => (1.0, 0.0),
+                    _ => (0.0, 1.0),
+                })
+                .collect(),
+            (0, UnknownChannelLayout::SpeakerOrder) => {
+                Self::speaker_coefficients(channels, u32::MAX)
+            }
+            _ => Self::speaker_coefficients(channels, channel_mask),
+        };
+
+        Self { coefficients }
+    }
+
+    /// Channels take the set bits of `channel_mask` in order, channels beyond them are dropped
+    fn speaker_coefficients(channels: usize, channel_mask: u32) -> Vec<(f64, f64)> {
+        let mut speakers = (0..SPEAKER_COEFFICIENTS.len())
+            .filter(|bit| channel_mask & (1 << bit) != 0)
+            .map(|bit| SPEAKER_COEFFICIENTS[bit]);
+
+        let mut coefficients = (0..channels)
+            .map_while(|_| speakers.next())
+            .collect::<Vec<_>>();
+        if coefficients.len() < channels {
+            log::warn!(
+                "Channel mask {:#X} places {} of {} channels, dropping the rest",
+                channel_mask,
+                coefficients.len(),
+                channels
+            );
+        }
+
+        coefficients.resize(channels, (0.0, 0.0));
+        coefficients
+    }
+
+    /// Mixes whole interleaved frames from `input` and appends the stereo frames to `output`
+    pub(crate) fn process(&self, input: &[f64], output: &mut Vec<f64>) {
+        for frame in input.chunks_exact(self.coefficients.len()) {
+            let (left, right) = frame.iter().zip(&self.coefficients).fold(
+                (0.0, 0.0),
+                |(left, right), (sample, (left_coefficient, right_coefficient))| {
+                    (
+                        left + sample * left_coefficient,
+                        right + sample * right_coefficient,
+                    )
+                },
+            );
+            output.extend_from_slice(&[left, right]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Front left, front right, centre, LFE, side left, side right
+    const SURROUND_5_1_SIDE: u32 = 0x60F;
+
+    #[test]
+    fn test_itu_5_1_downmix() {
+        let downmix = Downmix::new(6, SURROUND_5_1_SIDE, UnknownChannelLayout::default());
+
+        let mut output = vec![];
+        downmix.process(&[0.1, 0.2, 0.4, 1.0, 0.2, 0.0], &mut output);
+
+        assert!((output[0] - (0.1 + 0.4 * FRAC_1_SQRT_2 + 0.2 * FRAC_1_SQRT_2)).abs() < 1e-12);
+        assert!((output[1] - (0.2 + 0.4 * FRAC_1_SQRT_2)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_zero_mask_uses_unknown_layout() {
+        let frame = [0.1, 0.2, 0.3, 0.4];
+        let mix = |unknown_layout| {
+            let mut output = vec![];
+            Downmix::new(4, 0, unknown_layout).process(&frame, &mut output);
+            output
+        };
+
+        assert_eq!(mix(UnknownChannelLayout::FirstPair), [0.1, 0.2]);
+        assert_eq!(mix(UnknownChannelLayout::Alternate), [0.1 + 0.3, 0.2 + 0.4]);
+        // Front left, front right, centre, LFE
+        let speaker_order = mix(UnknownChannelLayout::SpeakerOrder);
+        assert!((speaker_order[0] - (0.1 + 0.3 * FRAC_1_SQRT_2)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_channels_beyond_mask_are_dropped() {
+        let downmix = Downmix::new(3, 0x3, UnknownChannelLayout::default());
+
+        let mut output = vec![];
+        downmix.process(&[0.1, 0.2, 0.9], &mut output);
+
+        assert_eq!(output, [0.1, 0.2]);
+    }
+}
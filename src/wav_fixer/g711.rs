@@ -0,0 +1,95 @@
+//! ITU-T G.711 A-law and μ-law expansion to 16-bit linear PCM
+
+const fn alaw_to_linear(encoded: u8) -> i16 {
+    // Even bits are inverted on the wire
+    let encoded = encoded ^ 0x55;
+    let segment = (encoded & 0x70) >> 4;
+    let mut magnitude = ((encoded & 0x0F) as i16) << 4;
+
+    magnitude += match segment {
+        0 => 8,
+        _ => 0x108,
+    };
+    if segment > 1 {
+        magnitude <<= segment - 1;
+    }
+
+    // A set sign bit means a positive sample
+    if encoded & 0x80 != 0 {
+        magnitude
+    } else {
+        -magnitude
+    }
+}
+
+/// Added before μ-law expansion so the segments line up, removed again afterwards
+const ULAW_BIAS: i16 = 0x84;
+
+const fn ulaw_to_linear(encoded: u8) -> i16 {
+    // All bits are inverted on the wire
+    let encoded = !encoded;
+    let segment = (encoded & 0x70) >> 4;
+    let magnitude = ((((encoded & 0x0F) as i16) << 3) + ULAW_BIAS) << segment;
+
+    // A set sign bit means a negative sample
+    if encoded & 0x80 != 0 {
+        ULAW_BIAS - magnitude
+    } else {
+        magnitude - ULAW_BIAS
+    }
+}
+
+// Function pointers cannot be called in const context, hence the flag
+const fn build_table(is_alaw: bool) -> [i16; 256] {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < table.len() {
+        table[index] = if is_alaw {
+            alaw_to_linear(index as u8)
+        } else {
+            ulaw_to_linear(index as u8)
+        };
+        index += 1;
+    }
+    table
+}
+
+pub(crate) const ALAW_TO_LINEAR: [i16; 256] = build_table(true);
+pub(crate) const ULAW_TO_LINEAR: [i16; 256] = build_table(false);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alaw_reference_values() {
+        assert_eq!(ALAW_TO_LINEAR[0xD5], 8);
+        assert_eq!(ALAW_TO_LINEAR[0x55], -8);
+        assert_eq!(ALAW_TO_LINEAR[0xAA], 32256);
+        assert_eq!(ALAW_TO_LINEAR[0x2A], -32256);
+        assert_eq!(ALAW_TO_LINEAR[0x80], 5504);
+    }
+
+    #[test]
+    fn test_ulaw_reference_values() {
+        assert_eq!(ULAW_TO_LINEAR[0xFF], 0);
+        assert_eq!(ULAW_TO_LINEAR[0x7F], 0);
+        assert_eq!(ULAW_TO_LINEAR[0x80], 32124);
+        assert_eq!(ULAW_TO_LINEAR[0x00], -32124);
+        assert_eq!(ULAW_TO_LINEAR[0xFE], 8);
+    }
+
+    #[test]
+    fn test_tables_are_symmetric() {
+        for encoded in 0..128u8 {
+            assert_eq!(
+                ALAW_TO_LINEAR[encoded as usize],
+                -ALAW_TO_LINEAR[(encoded | 0x80) as usize]
+            );
+            assert_eq!(
+                ULAW_TO_LINEAR[encoded as usize],
+                -ULAW_TO_LINEAR[(encoded | 0x80) as usize]
+            );
+        }
+    }
+}
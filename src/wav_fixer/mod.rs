@@ -0,0 +1,1599 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+
+use crate::DWORD_SIZE;
+use crate::diagnostics::ParseMode;
+use crate::errors::{DJWavFixerError, Result};
+use crate::metadata::{SERATO_GEOB_DESCRIPTIONS, read_id3_tag};
+use crate::riff_parser::{
+    DATA_MAGIC, FACT_MAGIC, FMT_MAGIC, RIFF_CHUNK_HEADER_SIZE, RiffFile, padded_size,
+};
+use crate::wav_file::{WaveAudioChannels, WaveFormatExtensible};
+
+use downmix::Downmix;
+pub use downmix::UnknownChannelLayout;
+pub use output_tree::{OutputTreeAction, UnfixedFileAction};
+pub(crate) use output_tree::{is_same_file, mirrored_output_path, place_unfixed_file};
+pub use requantizer::{DitherMode, TargetBitDepth};
+pub(crate) use safe_writer::BACKUP_DIRECTORY_NAME;
+pub use safe_writer::{BackupMode, WriteStage};
+
+use safe_writer::{SafeWriter, bak_path_for};
+use sample_encoding::SampleEncoding;
+pub(crate) use split_writer::MAX_RIFF_SIZE;
+use split_writer::SplitRiffWriter;
+use subchunk_policy::{SubchunkPolicy, SubchunkUpdate, update_subchunk};
+pub use transcoder::ClipHandling;
+use transcoder::{SampleConversion, TranscodeStatistics};
+
+mod adpcm;
+mod downmix;
+mod g711;
+mod output_tree;
+mod requantizer;
+mod resampler;
+mod safe_writer;
+mod sample_encoding;
+mod split_writer;
+mod subchunk_policy;
+mod transcoder;
+
+/// Settings shared by every fix operation
+#[derive(Clone, Debug, Default)]
+pub struct FixOptions {
+    /// How the previous version of an overwritten file is preserved
+    pub backup_mode: BackupMode,
+    /// Where content-addressed backups are kept, defaults to a `.djwavfixer-backups` directory
+    /// next to each file
+    pub backup_directory: Option<PathBuf>,
+    /// What to place in an output tree for files that need no fixing
+    pub unfixed_file_action: UnfixedFileAction,
+    /// Bit depth for files whose samples must be converted, files already at 16 or 24 bits keep
+    /// theirs
+    pub target_bit_depth: TargetBitDepth,
+    /// How precision is removed when samples are converted to a lower bit depth
+    pub dither: DitherMode,
+    /// What to do with float samples beyond full scale
+    pub clip_handling: ClipHandling,
+    /// Highest sample rate to leave as-is, files above it are resampled down to it
+    pub target_sample_rate: Option<NonZeroU32>,
+    /// Where the channels of multichannel files without a `channel_mask` are placed when
+    /// downmixing to stereo
+    pub unknown_channel_layout: UnknownChannelLayout,
+}
+
+/// The outcome of a fix that was written out
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FixReport {
+    transcode_statistics: Option<TranscodeStatistics>,
+    part_count: usize,
+    trimmed_bytes: u64,
+    verified_serato_frames: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl FixReport {
+    /// Number of samples that were beyond full scale after any gain, `None` if samples were
+    /// copied as-is
+    pub fn clipped_samples(&self) -> Option<u64> {
+        self.transcode_statistics
+            .map(|statistics| statistics.clipped_samples)
+    }
+
+    /// Gain applied to the samples in dB, `None` if no gain was applied
+    pub fn applied_gain_db(&self) -> Option<f64> {
+        self.transcode_statistics
+            .filter(|statistics| statistics.gain != 1.0)
+            .map(|statistics| 20.0 * statistics.gain.log10())
+    }
+
+    /// Number of files the fixed file was split into, 1 unless it was too large for RIFF
+    pub fn part_count(&self) -> usize {
+        self.part_count
+    }
+
+    /// Bytes of a partial frame dropped from the end of the audio
+    pub fn trimmed_bytes(&self) -> u64 {
+        self.trimmed_bytes
+    }
+
+    /// Serato `GEOB` frames read back from the fixed file and found identical to the original
+    pub fn verified_serato_frames(&self) -> &[String] {
+        &self.verified_serato_frames
+    }
+
+    /// Things the fix could not carry over from the original file, or did differently than asked
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub(crate) fn write_information(&self, mut writer: impl std::fmt::Write) -> Result<()> {
+        if self.trimmed_bytes > 0 {
+            writeln!(
+                writer,
+                "  Trimmed Partial Frame: {} bytes",
+                self.trimmed_bytes
+            )?;
+        }
+        if self.part_count > 1 {
+            writeln!(writer, "  Split Into: {} parts", self.part_count)?;
+        }
+        if let Some(clipped_samples) = self.clipped_samples() {
+            writeln!(writer, "  Clipped Samples: {}", clipped_samples)?;
+        }
+        if let Some(applied_gain_db) = self.applied_gain_db() {
+            writeln!(writer, "  Applied Gain: {:.2} dB", applied_gain_db)?;
+        }
+        if !self.verified_serato_frames.is_empty() {
+            writeln!(
+                writer,
+                "  Verified Serato Frames: {}",
+                self.verified_serato_frames.join(", ")
+            )?;
+        }
+        for warning in &self.warnings {
+            writeln!(writer, "  Warning: {}", warning)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What a fix does to a file
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct FixPlan {
+    pub(crate) target_format: WaveFormatExtensible,
+    /// `None` when the `data` subchunk can be copied byte for byte
+    pub(crate) conversion: Option<SampleConversion>,
+}
+
+/// Works out how to make a file playable, or returns `None` if it is already playable or we
+/// do not know how to fix its format
+pub(crate) fn plan_fix(
+    wave_format_info: &WaveFormatExtensible,
+    options: &FixOptions,
+) -> Option<FixPlan> {
+    let source_encoding = SampleEncoding::from_format(wave_format_info)?;
+    let source_precision = wave_format_info
+        .valid_bits_per_sample
+        .unwrap_or(wave_format_info.bits_per_sample);
+
+    let target_bits_per_sample = if wave_format_info.is_sample_bits_supported_by_players() {
+        wave_format_info.bits_per_sample
+    } else if source_precision <= 16 {
+        // Low resolution sources gain nothing from more than 16 bits, this keeps them lossless
+        16
+    } else {
+        options.target_bit_depth.bits_per_sample()
+    };
+
+    let target_sample_rate = match options.target_sample_rate {
+        Some(target_sample_rate) if wave_format_info.sample_rate > target_sample_rate.get() => {
+            target_sample_rate.get()
+        }
+        _ => wave_format_info.sample_rate,
+    };
+    let resamples = target_sample_rate != wave_format_info.sample_rate;
+
+    let channels = wave_format_info.channels.as_u16() as usize;
+    let downmix = (!wave_format_info.are_channels_supported_by_players()).then(|| {
+        Downmix::new(
+            channels,
+            wave_format_info.channel_mask,
+            options.unknown_channel_layout,
+        )
+    });
+
+    let target_format = WaveFormatExtensible::integer_pcm(
+        match downmix {
+            Some(_) => WaveAudioChannels::Stereo,
+            None => wave_format_info.channels,
+        },
+        target_sample_rate,
+        target_bits_per_sample,
+    );
+    if &target_format == wave_format_info {
+        return None;
+    }
+
+    let downmixes = downmix.is_some();
+    let conversion = (resamples
+        || downmixes
+        || SampleEncoding::from_format(&target_format).as_ref() != Some(&source_encoding))
+    .then_some(SampleConversion {
+        source_encoding,
+        channels,
+        downmix,
+        frame_count: None,
+        target_bits_per_sample,
+        source_sample_rate: wave_format_info.sample_rate,
+        target_sample_rate,
+        // Samples that already fit in the target bit depth are converted exactly, resampled and
+        // downmixed ones never fit
+        dither: if resamples || downmixes || source_precision > target_bits_per_sample {
+            options.dither
+        } else {
+            DitherMode::Truncate
+        },
+        clip_handling: options.clip_handling,
+    });
+
+    Some(FixPlan {
+        target_format,
+        conversion,
+    })
+}
+
+/// Writes a fixed copy of the RIFF file, subchunks other than `fmt ` and `data` are copied byte
+/// for byte or left out as their `SubchunkPolicy` says. `open_part` gives the writer for each
+/// part, more than one is only asked for when the fixed file is larger than `max_riff_size` allows
+pub(crate) fn write_fixed_wav<R: Read + Seek, W: Write + Seek>(
+    riff_file: &mut RiffFile<R>,
+    wave_format_info: &WaveFormatExtensible,
+    open_part: impl FnMut(usize) -> Result<W>,
+    options: &FixOptions,
+    max_riff_size: u64,
+) -> Result<(Vec<W>, FixReport)> {
+    // Leniently parsed headers were corrected on load, writing them out repairs the file
+    let repairs_header = !riff_file.diagnostics().is_empty();
+    let (reader, chunk) =
+        riff_file
+            .get_riff_chunk_and_reader()
+            .ok_or(DJWavFixerError::RiffHeaderError(
+                "Missing 'RIFF' chunk".to_string(),
+            ))?;
+
+    // RF64 files and broken headers only need the container rewritten if the samples are fine
+    let container_plan = (repairs_header || chunk.needs_rewrite()).then(|| FixPlan {
+        target_format: wave_format_info.clone(),
+        conversion: None,
+    });
+    let mut plan = plan_fix(wave_format_info, options)
+        .or(container_plan)
+        .ok_or_else(|| {
+            DJWavFixerError::FixError(format!(
+                "No fix available for {} with {} bits per sample",
+                wave_format_info.format_tag, wave_format_info.bits_per_sample
+            ))
+        })?;
+
+    if let Some(conversion) = plan.conversion.as_mut()
+        && conversion.source_encoding.frames_per_unit() > 1
+        && let Some(fact_subchunk) = chunk.get_subchunk_mut(&FACT_MAGIC)
+    {
+        // The last ADPCM block is padded out, `fact` says how many frames are real
+        let fact_data = fact_subchunk.read_data(reader)?;
+        if fact_data.len() >= DWORD_SIZE {
+            conversion.frame_count = Some(u32::from_le_bytes(unsafe {
+                fact_data[..DWORD_SIZE].try_into().unwrap_unchecked()
+            }) as u64);
+        }
+    }
+
+    let gain = match (&plan.conversion, chunk.get_subchunk(&DATA_MAGIC)) {
+        (Some(conversion), Some(data_subchunk)) if conversion.needs_gain() => {
+            conversion.measure_gain(data_subchunk.data_reader(reader)?)?
+        }
+        _ => 1.0,
+    };
+
+    let mut report = FixReport::default();
+    let mut updates = Vec::with_capacity(chunk.subchunks().len());
+    for subchunk in chunk.subchunks_mut() {
+        let update = match SubchunkPolicy::of(subchunk).updates() {
+            true => update_subchunk(subchunk.id(), subchunk.read_data(reader)?, &plan),
+            false => SubchunkUpdate::Unchanged,
+        };
+        if let SubchunkUpdate::Dropped(ref warning) = update {
+            log::warn!("{}", warning);
+            report.warnings.push(warning.clone());
+        }
+        updates.push(update);
+    }
+
+    let trailing_size = chunk
+        .subchunks()
+        .iter()
+        .zip(&updates)
+        .skip_while(|(subchunk, _)| subchunk.id() != DATA_MAGIC)
+        .skip(1)
+        .filter_map(|(subchunk, update)| {
+            let size = match update {
+                _ if subchunk.id() == FMT_MAGIC => return None,
+                _ if SubchunkPolicy::of(subchunk) == SubchunkPolicy::Drop => return None,
+                SubchunkUpdate::Dropped(_) => return None,
+                SubchunkUpdate::Rewritten(body) => body.len() as u64,
+                SubchunkUpdate::Unchanged => subchunk.size(),
+            };
+            Some(RIFF_CHUNK_HEADER_SIZE as u64 + padded_size(size))
+        })
+        .sum();
+
+    let fmt_data = plan.target_format.to_bytes();
+    let mut split_writer = SplitRiffWriter::try_new(
+        open_part,
+        chunk.format(),
+        fmt_data.clone(),
+        plan.target_format.block_align,
+        max_riff_size,
+    )?;
+
+    for (subchunk, update) in chunk
+        .subchunks_mut()
+        .iter_mut()
+        .zip(&updates)
+        .skip_while(|(subchunk, _)| subchunk.id() != DATA_MAGIC)
+        .skip(1)
+        .filter(|(subchunk, _)| SubchunkPolicy::of(subchunk).relocates())
+    {
+        let data = match update {
+            SubchunkUpdate::Unchanged => subchunk.read_data(reader)?.to_vec(),
+            SubchunkUpdate::Rewritten(body) => body.clone(),
+            SubchunkUpdate::Dropped(_) => continue,
+        };
+        split_writer.relocate_to_first_part(subchunk.id(), data);
+    }
+
+    for (subchunk, update) in chunk.subchunks().iter().zip(&updates) {
+        if subchunk.id() == FMT_MAGIC {
+            split_writer
+                .riff_writer()
+                .write_subchunk(FMT_MAGIC, &fmt_data)?;
+        } else if subchunk.id() == DATA_MAGIC {
+            split_writer.begin_data(trailing_size)?;
+            match plan.conversion {
+                Some(ref conversion) => {
+                    let statistics = conversion.transcode(
+                        subchunk.data_reader(reader)?,
+                        &mut split_writer,
+                        gain,
+                    )?;
+                    report.trimmed_bytes = statistics.trimmed_bytes;
+                    report.transcode_statistics = Some(statistics);
+                }
+                None => {
+                    // Recordings that were cut off can end partway through a frame
+                    let block_align = plan.target_format.block_align.max(1) as u64;
+                    report.trimmed_bytes = subchunk.size() % block_align;
+                    subchunk.copy_data_prefix(
+                        reader,
+                        &mut split_writer,
+                        subchunk.size() - report.trimmed_bytes,
+                    )?;
+                }
+            }
+            split_writer.end_data()?;
+        } else {
+            let policy = SubchunkPolicy::of(subchunk);
+            if policy == SubchunkPolicy::Drop
+                // Already written after the audio of the first part
+                || (policy.relocates() && split_writer.is_split())
+            {
+                continue;
+            }
+
+            let riff_writer = split_writer.riff_writer();
+            match update {
+                SubchunkUpdate::Unchanged => {
+                    riff_writer.begin_subchunk(subchunk.id())?;
+                    subchunk.copy_data(reader, riff_writer.writer())?;
+                    riff_writer.end_subchunk()?;
+                }
+                SubchunkUpdate::Rewritten(body) => {
+                    riff_writer.write_subchunk(subchunk.id(), body)?
+                }
+                SubchunkUpdate::Dropped(_) => {}
+            }
+        }
+    }
+
+    let parts = split_writer.finish()?;
+    report.part_count = parts.len();
+    Ok((parts, report))
+}
+
+/// Path of the part with index `part` when a fixed file is split, the first part keeps `path`
+fn split_part_path(path: &Path, part: usize) -> PathBuf {
+    if part == 0 {
+        return path.to_path_buf();
+    }
+
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(format!(".part{}", part + 1));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+/// Reads the Serato `GEOB` frames back from `fixed` and checks they are byte for byte those of
+/// `source`, listing the frames checked in `report`
+pub(crate) fn verify_serato_frames<R: Read + Seek, F: Read + Seek>(
+    source: &mut RiffFile<R>,
+    mut fixed: F,
+    report: &mut FixReport,
+) -> Result<()> {
+    let Some(Ok(source_tag)) = source
+        .get_riff_chunk_and_reader()
+        .and_then(|(reader, chunk)| read_id3_tag(chunk, reader))
+    else {
+        return Ok(());
+    };
+    let expected = SERATO_GEOB_DESCRIPTIONS
+        .into_iter()
+        .filter_map(|description| Some((description, source_tag.object(description)?)))
+        .collect::<Vec<_>>();
+    if expected.is_empty() {
+        return Ok(());
+    }
+
+    let file_size = fixed.seek(SeekFrom::End(0))?;
+    fixed.rewind()?;
+    let mut fixed_file = RiffFile::try_new(
+        fixed,
+        file_size.saturating_sub(RIFF_CHUNK_HEADER_SIZE as u64),
+        ParseMode::Strict,
+    )?;
+    let fixed_tag = fixed_file
+        .get_riff_chunk_and_reader()
+        .and_then(|(reader, chunk)| read_id3_tag(chunk, reader))
+        .transpose()?;
+    for (description, data) in &expected {
+        if fixed_tag.as_ref().and_then(|tag| tag.object(description)) != Some(*data) {
+            return Err(DJWavFixerError::FixError(format!(
+                "'{}' frame differs in the fixed file",
+                description
+            )));
+        }
+    }
+
+    report.verified_serato_frames = expected
+        .into_iter()
+        .map(|(description, _)| description.to_string())
+        .collect();
+    Ok(())
+}
+
+/// Crash-safely writes the fixed file to `path`, replacing whatever is there. Files too large for
+/// RIFF are split, with the extra parts written next to `path`. No part is moved into place
+/// unless all of them were written, and parts already moved are rolled back if a later one fails
+pub(crate) fn write_fixed_file<R: Read + Seek>(
+    riff_file: &mut RiffFile<R>,
+    wave_format_info: &WaveFormatExtensible,
+    path: &Path,
+    options: &FixOptions,
+) -> Result<FixReport> {
+    let safe_writer = SafeWriter {
+        backup_mode: options.backup_mode,
+        backup_directory: options.backup_directory.clone(),
+    };
+
+    let open_part = |part| {
+        let part_path = split_part_path(path, part);
+        if part > 0 {
+            log::info!(
+                "Splitting `{}` into `{}`",
+                path.display(),
+                part_path.display()
+            );
+        }
+        safe_writer.create(&part_path)
+    };
+    let (mut parts, mut report) = write_fixed_wav(
+        riff_file,
+        wave_format_info,
+        open_part,
+        options,
+        MAX_RIFF_SIZE,
+    )?;
+
+    // Nothing is committed yet, the original is left alone if the cues did not survive
+    verify_serato_frames(riff_file, parts[0].read_back()?, &mut report)?;
+
+    let part_paths = parts
+        .iter()
+        .map(|part| part.path().to_path_buf())
+        .collect::<Vec<_>>();
+    let backup_paths = safe_writer.commit(parts)?;
+    for (part_path, backup_path) in part_paths.iter().zip(backup_paths) {
+        if let Some(backup_path) = backup_path {
+            log::debug!(
+                "Backed up `{}` to `{}`",
+                part_path.display(),
+                backup_path.display()
+            );
+            let bak_path = bak_path_for(part_path);
+            if options.backup_mode == BackupMode::Bak && backup_path != bak_path {
+                report.warnings.push(format!(
+                    "Kept the existing backup `{}`, backed up to `{}` instead",
+                    bak_path.display(),
+                    backup_path.display()
+                ));
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::ParseMode;
+    use crate::file_loader::blocking_loader::load_wav_reader;
+    use crate::metadata::{
+        BEXT_MAGIC, BroadcastExtension, CART_MAGIC, Cart, build_id3_tag, build_markers2,
+    };
+    use crate::riff_parser::DATA_MAGIC;
+    use crate::riff_parser::tests::{
+        build_rf64_wave, build_riff_wave, build_riff_wave_with_padding,
+    };
+    use crate::wav_file::{WavFileLoadStatus, WaveFormatType};
+    use std::io::Cursor;
+
+    fn extensible_24bit_fmt() -> Vec<u8> {
+        WaveFormatExtensible {
+            format_tag: WaveFormatType::WaveFormatExtensible,
+            channels: 2.into(),
+            sample_rate: 44100,
+            avg_bytes_per_second: 264600,
+            block_align: 6,
+            bits_per_sample: 24,
+            cb_size: 22,
+            valid_bits_per_sample: Some(24),
+            channel_mask: 3,
+            subformat_data: vec![1, 0, 0, 0, 0, 0, 16, 0, 128, 0, 0, 170, 0, 56, 155, 113],
+        }
+        .to_bytes()
+    }
+
+    #[test]
+    fn test_fix_extensible_to_integer_pcm() {
+        let audio_data = (0..6 * 64).map(|i| i as u8).collect::<Vec<_>>();
+        let original = build_riff_wave(&[
+            (FMT_MAGIC, extensible_24bit_fmt()),
+            (DATA_MAGIC, audio_data.clone()),
+            (*b"LIST", b"INFOISFT".to_vec()),
+        ]);
+
+        let path = PathBuf::from("extensible.wav");
+        let mut wav_file = load_wav_reader(&path, Cursor::new(original), ParseMode::Strict)
+            .expect("Failed to load WAV");
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV")
+            .into_inner();
+
+        let expected = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(2.into(), 44100, 24).to_bytes(),
+            ),
+            (DATA_MAGIC, audio_data),
+            (*b"LIST", b"INFOISFT".to_vec()),
+        ]);
+        assert_eq!(fixed, expected);
+
+        let fixed_file = load_wav_reader(&path, Cursor::new(fixed), ParseMode::Strict)
+            .expect("Failed to load fixed WAV");
+        let WavFileLoadStatus::Success {
+            wave_format_info, ..
+        } = &fixed_file.load_status
+        else {
+            panic!(
+                "Expected fixed file to load, got {:?}",
+                fixed_file.load_status
+            );
+        };
+        assert_eq!(wave_format_info.format_tag, WaveFormatType::IntegerPCM);
+        assert_eq!(fixed_file.needs_fixing(&FixOptions::default()), Some(false));
+    }
+
+    #[test]
+    fn test_fix_rejects_playable_file() {
+        let original = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(2.into(), 44100, 16).to_bytes(),
+            ),
+            (DATA_MAGIC, vec![0; 16]),
+        ]);
+
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("int16.wav"),
+            Cursor::new(original),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        assert!(matches!(
+            wav_file.write_fixed(Cursor::new(vec![]), &FixOptions::default()),
+            Err(DJWavFixerError::FixError(_))
+        ));
+    }
+
+    #[test]
+    fn test_fix_float_reports_clipping() {
+        let samples = [0.25f32, -0.25, 1.25, -1.0];
+        let original = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible {
+                    format_tag: WaveFormatType::FloatPCM,
+                    channels: 2.into(),
+                    sample_rate: 48000,
+                    avg_bytes_per_second: 384000,
+                    block_align: 8,
+                    bits_per_sample: 32,
+                    cb_size: 0,
+                    valid_bits_per_sample: None,
+                    channel_mask: 0,
+                    subformat_data: vec![],
+                }
+                .to_bytes(),
+            ),
+            (
+                DATA_MAGIC,
+                samples
+                    .iter()
+                    .flat_map(|sample| sample.to_le_bytes())
+                    .collect(),
+            ),
+        ]);
+
+        let options = FixOptions {
+            target_bit_depth: TargetBitDepth::Bits16,
+            dither: DitherMode::Truncate,
+            ..FixOptions::default()
+        };
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("f32.wav"),
+            Cursor::new(original),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &options)
+            .expect("Failed to fix WAV")
+            .into_inner();
+
+        let expected = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(2.into(), 48000, 16).to_bytes(),
+            ),
+            (
+                DATA_MAGIC,
+                [8192i16, -8192, i16::MAX, i16::MIN]
+                    .iter()
+                    .flat_map(|sample| sample.to_le_bytes())
+                    .collect(),
+            ),
+        ]);
+        assert_eq!(fixed, expected);
+
+        let report = wav_file.fix_report().expect("Expected a fix report");
+        assert_eq!(report.clipped_samples(), Some(1));
+        assert_eq!(report.applied_gain_db(), None);
+
+        let mut information = String::new();
+        wav_file
+            .write_information(&mut information, &options)
+            .expect("Failed to write information");
+        assert!(information.contains("  Clipped Samples: 1\n"));
+    }
+
+    #[test]
+    fn test_fix_extensible_float_is_decoded_as_float() {
+        let float_extensible_format = WaveFormatExtensible {
+            format_tag: WaveFormatType::WaveFormatExtensible,
+            channels: 1.into(),
+            sample_rate: 48000,
+            avg_bytes_per_second: 192000,
+            block_align: 4,
+            bits_per_sample: 32,
+            cb_size: 22,
+            valid_bits_per_sample: Some(32),
+            channel_mask: 0x4,
+            subformat_data: vec![3, 0, 0, 0, 0, 0, 16, 0, 128, 0, 0, 170, 0, 56, 155, 113],
+        };
+        let original = build_riff_wave(&[
+            (FMT_MAGIC, float_extensible_format.to_bytes()),
+            (
+                DATA_MAGIC,
+                [0.5f32, -0.25]
+                    .iter()
+                    .flat_map(|sample| sample.to_le_bytes())
+                    .collect(),
+            ),
+        ]);
+
+        let options = FixOptions {
+            target_bit_depth: TargetBitDepth::Bits16,
+            dither: DitherMode::Truncate,
+            ..FixOptions::default()
+        };
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("float_extensible.wav"),
+            Cursor::new(original),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &options)
+            .expect("Failed to fix WAV")
+            .into_inner();
+
+        let expected = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(1.into(), 48000, 16).to_bytes(),
+            ),
+            (
+                DATA_MAGIC,
+                [16384i16, -8192]
+                    .iter()
+                    .flat_map(|sample| sample.to_le_bytes())
+                    .collect(),
+            ),
+        ]);
+        assert_eq!(fixed, expected);
+    }
+
+    #[test]
+    fn test_unknown_sub_format_cannot_be_fixed() {
+        let mut format = WaveFormatExtensible::try_from(extensible_24bit_fmt().as_slice())
+            .expect("Failed to parse fmt");
+        format.subformat_data[0] = 0x92;
+
+        assert!(plan_fix(&format, &FixOptions::default()).is_none());
+    }
+
+    #[test]
+    fn test_fix_uint8_to_int16() {
+        let original = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(1.into(), 22050, 8).to_bytes(),
+            ),
+            (DATA_MAGIC, vec![0, 1, 127, 128, 129, 255]),
+        ]);
+
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("uint8.wav"),
+            Cursor::new(original),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV")
+            .into_inner();
+
+        let expected = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(1.into(), 22050, 16).to_bytes(),
+            ),
+            (
+                DATA_MAGIC,
+                [i16::MIN, -32512, -256, 0, 256, 32512]
+                    .iter()
+                    .flat_map(|sample| sample.to_le_bytes())
+                    .collect(),
+            ),
+        ]);
+        assert_eq!(fixed, expected);
+    }
+
+    #[test]
+    fn test_fix_ulaw_drops_fact_chunk() {
+        let ulaw_format = WaveFormatExtensible {
+            format_tag: WaveFormatType::ULaw,
+            channels: 1.into(),
+            sample_rate: 8000,
+            avg_bytes_per_second: 8000,
+            block_align: 1,
+            bits_per_sample: 8,
+            cb_size: 0,
+            valid_bits_per_sample: None,
+            channel_mask: 0,
+            subformat_data: vec![],
+        };
+        let original = build_riff_wave(&[
+            (FMT_MAGIC, ulaw_format.to_bytes()),
+            (FACT_MAGIC, 4u32.to_le_bytes().to_vec()),
+            (DATA_MAGIC, vec![0xFF, 0x80, 0x00, 0xFE]),
+        ]);
+
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("ulaw.wav"),
+            Cursor::new(original),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV")
+            .into_inner();
+
+        let expected = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(1.into(), 8000, 16).to_bytes(),
+            ),
+            (
+                DATA_MAGIC,
+                [0i16, 32124, -32124, 8]
+                    .iter()
+                    .flat_map(|sample| sample.to_le_bytes())
+                    .collect(),
+            ),
+        ]);
+        assert_eq!(fixed, expected);
+    }
+
+    #[test]
+    fn test_fix_ima_adpcm_trims_to_fact_length() {
+        let ima_format = WaveFormatExtensible {
+            format_tag: WaveFormatType::ImaAdpcm,
+            channels: 1.into(),
+            sample_rate: 8000,
+            avg_bytes_per_second: 7111,
+            block_align: 8,
+            bits_per_sample: 4,
+            cb_size: 2,
+            valid_bits_per_sample: None,
+            channel_mask: 0,
+            subformat_data: 9u16.to_le_bytes().to_vec(),
+        };
+        let original = build_riff_wave(&[
+            (FMT_MAGIC, ima_format.to_bytes()),
+            (FACT_MAGIC, 12u32.to_le_bytes().to_vec()),
+            (
+                DATA_MAGIC,
+                vec![0, 0, 0, 0, 0x07, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 0],
+            ),
+        ]);
+
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("ima.wav"),
+            Cursor::new(original),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV")
+            .into_inner();
+
+        let expected = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(1.into(), 8000, 16).to_bytes(),
+            ),
+            (
+                DATA_MAGIC,
+                [0i16, 11, 13, 14, 15, 16, 17, 18, 19, 100, 100, 100]
+                    .iter()
+                    .flat_map(|sample| sample.to_le_bytes())
+                    .collect(),
+            ),
+        ]);
+        assert_eq!(fixed, expected);
+    }
+
+    #[test]
+    fn test_fix_resamples_above_target_rate() {
+        let audio_data = (0..960i32)
+            .flat_map(|frame| {
+                let sample = (frame % 100 - 50) * 1000;
+                [sample, -sample]
+            })
+            .flat_map(|sample| sample.to_le_bytes().into_iter().take(3))
+            .collect::<Vec<_>>();
+        let original = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(2.into(), 96000, 24).to_bytes(),
+            ),
+            (DATA_MAGIC, audio_data),
+        ]);
+
+        let options = FixOptions {
+            target_sample_rate: NonZeroU32::new(48000),
+            ..FixOptions::default()
+        };
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("96k.wav"),
+            Cursor::new(original),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        assert_eq!(wav_file.needs_fixing(&FixOptions::default()), Some(false));
+        assert_eq!(wav_file.needs_fixing(&options), Some(true));
+
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &options)
+            .expect("Failed to fix WAV")
+            .into_inner();
+
+        // RIFF header, 16-byte `fmt ` and 480 frames of 24-bit stereo
+        assert_eq!(fixed.len(), 12 + 8 + 16 + 8 + 480 * 6);
+
+        let fixed_file = load_wav_reader(
+            &PathBuf::from("48k.wav"),
+            Cursor::new(fixed),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load fixed WAV");
+        let WavFileLoadStatus::Success {
+            wave_format_info, ..
+        } = &fixed_file.load_status
+        else {
+            panic!(
+                "Expected fixed file to load, got {:?}",
+                fixed_file.load_status
+            );
+        };
+        assert_eq!(
+            wave_format_info,
+            &WaveFormatExtensible::integer_pcm(2.into(), 48000, 24)
+        );
+        assert_eq!(fixed_file.needs_fixing(&options), Some(false));
+    }
+
+    #[test]
+    fn test_fix_downmixes_5_1_to_stereo() {
+        let surround_format = WaveFormatExtensible {
+            format_tag: WaveFormatType::WaveFormatExtensible,
+            channels: 6.into(),
+            sample_rate: 48000,
+            avg_bytes_per_second: 576000,
+            block_align: 12,
+            bits_per_sample: 16,
+            cb_size: 22,
+            valid_bits_per_sample: Some(16),
+            // Front left, front right, centre, LFE, back left, back right
+            channel_mask: 0x3F,
+            subformat_data: vec![1, 0, 0, 0, 0, 0, 16, 0, 128, 0, 0, 170, 0, 56, 155, 113],
+        };
+        let original = build_riff_wave(&[
+            (FMT_MAGIC, surround_format.to_bytes()),
+            (
+                DATA_MAGIC,
+                [8192i16, 0, 0, 0, 0, 0, 0, 0, 8192, 16384, 0, 0]
+                    .iter()
+                    .flat_map(|sample| sample.to_le_bytes())
+                    .collect(),
+            ),
+        ]);
+
+        let options = FixOptions {
+            dither: DitherMode::Truncate,
+            ..FixOptions::default()
+        };
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("5.1.wav"),
+            Cursor::new(original),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        assert_eq!(wav_file.needs_fixing(&options), Some(true));
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &options)
+            .expect("Failed to fix WAV")
+            .into_inner();
+
+        // The centre goes to both sides at -3 dB, the LFE is dropped
+        let expected = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(2.into(), 48000, 16).to_bytes(),
+            ),
+            (
+                DATA_MAGIC,
+                [8192i16, 0, 5792, 5792]
+                    .iter()
+                    .flat_map(|sample| sample.to_le_bytes())
+                    .collect(),
+            ),
+        ]);
+        assert_eq!(fixed, expected);
+    }
+
+    #[test]
+    fn test_fix_int32_to_int24() {
+        let samples = [i32::MAX, i32::MIN, 0x0123_4567, -0x0123_4567, 0x100, -0x100];
+        let original = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(2.into(), 44100, 32).to_bytes(),
+            ),
+            (
+                DATA_MAGIC,
+                samples
+                    .iter()
+                    .flat_map(|sample| sample.to_le_bytes())
+                    .collect(),
+            ),
+        ]);
+
+        let options = FixOptions {
+            dither: DitherMode::Truncate,
+            ..FixOptions::default()
+        };
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("int32.wav"),
+            Cursor::new(original),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &options)
+            .expect("Failed to fix WAV")
+            .into_inner();
+
+        let expected = build_riff_wave(&[
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(2.into(), 44100, 24).to_bytes(),
+            ),
+            (
+                DATA_MAGIC,
+                samples
+                    .iter()
+                    .flat_map(|sample| (sample >> 8).to_le_bytes()[..3].to_vec())
+                    .collect(),
+            ),
+        ]);
+        assert_eq!(fixed, expected);
+    }
+
+    fn int16_stereo_subchunks(frames: usize) -> Vec<([u8; DWORD_SIZE], Vec<u8>)> {
+        vec![
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(2.into(), 44100, 16).to_bytes(),
+            ),
+            (DATA_MAGIC, (0..4 * frames).map(|i| i as u8).collect()),
+            (*b"LIST", b"INFOISFT".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn test_fix_rf64_to_riff() {
+        let subchunks = int16_stereo_subchunks(16);
+        let path = PathBuf::from("rf64.wav");
+        let mut wav_file = load_wav_reader(
+            &path,
+            Cursor::new(build_rf64_wave(&subchunks)),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        assert_eq!(wav_file.needs_fixing(&FixOptions::default()), Some(true));
+
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV")
+            .into_inner();
+        assert_eq!(fixed, build_riff_wave(&subchunks));
+    }
+
+    #[test]
+    fn test_fix_splits_files_too_large_for_riff() {
+        let subchunks = int16_stereo_subchunks(25);
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("rf64.wav"),
+            Cursor::new(build_rf64_wave(&subchunks)),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        let WavFileLoadStatus::Success {
+            ref mut riff_file,
+            ref wave_format_info,
+        } = wav_file.load_status
+        else {
+            panic!("Expected file to load, got {:?}", wav_file.load_status);
+        };
+
+        // `WAVE`, `fmt ` and the `data` header take 36 bytes, `LIST` 16, leaving 10 frames a part
+        let (parts, report) = write_fixed_wav(
+            riff_file,
+            wave_format_info,
+            |_| Ok(Cursor::new(vec![])),
+            &FixOptions::default(),
+            92,
+        )
+        .expect("Failed to fix WAV");
+        assert_eq!(report.part_count(), 3);
+
+        let audio_data = &subchunks[1].1;
+        let fmt_data = subchunks[0].1.clone();
+        let expected = [
+            build_riff_wave(&[
+                (FMT_MAGIC, fmt_data.clone()),
+                (DATA_MAGIC, audio_data[..40].to_vec()),
+            ]),
+            build_riff_wave(&[
+                (FMT_MAGIC, fmt_data.clone()),
+                (DATA_MAGIC, audio_data[40..80].to_vec()),
+            ]),
+            build_riff_wave(&[
+                (FMT_MAGIC, fmt_data),
+                (DATA_MAGIC, audio_data[80..].to_vec()),
+                subchunks[2].clone(),
+            ]),
+        ];
+        let parts = parts
+            .into_iter()
+            .map(Cursor::into_inner)
+            .collect::<Vec<_>>();
+        assert_eq!(parts, expected);
+    }
+
+    #[test]
+    fn test_fix_restores_missing_pad_bytes() {
+        let subchunks = [
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(1.into(), 44100, 24).to_bytes(),
+            ),
+            (DATA_MAGIC, vec![1, 2, 3]),
+            (*b"LIST", b"INFOISFTabc".to_vec()),
+        ];
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("unpadded.wav"),
+            Cursor::new(build_riff_wave_with_padding(&subchunks, false)),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        assert_eq!(wav_file.needs_fixing(&FixOptions::default()), Some(true));
+
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV")
+            .into_inner();
+        assert_eq!(fixed, build_riff_wave(&subchunks));
+    }
+
+    #[test]
+    fn test_fix_repairs_leniently_parsed_header() {
+        let subchunks = int16_stereo_subchunks(4);
+        let mut original = build_riff_wave(&subchunks);
+        // Off by one RIFF size and a block align that ignores the channel count
+        let riff_size = original.len() as u32 - 7;
+        original[4..8].copy_from_slice(&riff_size.to_le_bytes());
+        original[32..34].copy_from_slice(&2u16.to_le_bytes());
+
+        let path = PathBuf::from("broken.wav");
+        let strict = load_wav_reader(&path, Cursor::new(original.clone()), ParseMode::Strict)
+            .expect("Failed to load WAV");
+        assert!(matches!(
+            strict.load_status,
+            WavFileLoadStatus::RiffFileInvalid { .. }
+        ));
+
+        let mut wav_file = load_wav_reader(&path, Cursor::new(original), ParseMode::Lenient)
+            .expect("Failed to load WAV");
+        assert_eq!(wav_file.diagnostics().len(), 2);
+        assert_eq!(wav_file.needs_fixing(&FixOptions::default()), Some(true));
+
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV")
+            .into_inner();
+        assert_eq!(fixed, build_riff_wave(&subchunks));
+    }
+
+    #[test]
+    fn test_fix_unfinalized_recording() {
+        let subchunks = int16_stereo_subchunks(5);
+        let mut original = build_riff_wave(&subchunks[..2]);
+        // Power was lost mid-frame, before either size was written
+        original.extend_from_slice(&[0xAA, 0xBB]);
+        original[4..8].copy_from_slice(&0u32.to_le_bytes());
+        original[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("unfinalized.wav"),
+            Cursor::new(original),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        assert_eq!(wav_file.diagnostics().len(), 2);
+        assert_eq!(wav_file.needs_fixing(&FixOptions::default()), Some(true));
+
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV")
+            .into_inner();
+        assert_eq!(fixed, build_riff_wave(&subchunks[..2]));
+        assert_eq!(wav_file.fix_report().unwrap().trimmed_bytes(), 2);
+    }
+
+    #[test]
+    fn test_fix_reports_partial_frame_dropped_while_converting() {
+        // Two 32-bit stereo frames and half of a third
+        let mut data = [1i32 << 20; 4]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect::<Vec<_>>();
+        data.extend_from_slice(&[0xAA; 4]);
+        let subchunks = [
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(2.into(), 44100, 32).to_bytes(),
+            ),
+            (DATA_MAGIC, data),
+        ];
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("partial.wav"),
+            Cursor::new(build_riff_wave(&subchunks)),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+
+        wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV");
+        let report = wav_file.fix_report().unwrap();
+        assert!(report.clipped_samples().is_some());
+        assert_eq!(report.trimmed_bytes(), 4);
+    }
+
+    #[test]
+    fn test_fix_keeps_repeated_subchunks() {
+        let subchunks = [
+            (*b"LIST", b"INFOISFT".to_vec()),
+            (*b"JUNK", vec![0; 4]),
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(1.into(), 44100, 8).to_bytes(),
+            ),
+            (DATA_MAGIC, vec![128, 0]),
+            (*b"LIST", b"adtl".to_vec()),
+            (*b"JUNK", vec![0; 2]),
+        ];
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("repeated.wav"),
+            Cursor::new(build_riff_wave(&subchunks)),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV")
+            .into_inner();
+
+        let mut expected = subchunks.to_vec();
+        expected[2].1 = WaveFormatExtensible::integer_pcm(1.into(), 44100, 16).to_bytes();
+        expected[3].1 = vec![0, 0, 0, 0x80];
+        assert_eq!(fixed, build_riff_wave(&expected));
+    }
+
+    /// Parses a fixed file back into its subchunk IDs and bodies, in file order
+    fn read_subchunks(file: Vec<u8>) -> Vec<([u8; DWORD_SIZE], Vec<u8>)> {
+        let data_size = file.len() as u64 - 8;
+        let mut riff_file = RiffFile::try_new(Cursor::new(file), data_size, ParseMode::Strict)
+            .expect("Failed to parse fixed file");
+        let (reader, chunk) = riff_file.get_riff_chunk_and_reader().unwrap();
+        chunk
+            .subchunks_mut()
+            .iter_mut()
+            .map(|subchunk| {
+                let data = subchunk.read_data(reader).unwrap().to_vec();
+                (subchunk.id(), data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fix_carries_metadata_subchunks() {
+        let subchunks = [
+            (*b"bext", vec![1; 602]),
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(1.into(), 44100, 8).to_bytes(),
+            ),
+            (*b"LIST", b"INFOINAM\x05\0\0\0Song\0\0".to_vec()),
+            (DATA_MAGIC, vec![128, 255, 0]),
+            (*b"cue ", vec![0; 28]),
+            (*b"LIST", b"adtllabl\x05\0\0\0\x01\0\0\0A\0".to_vec()),
+            (*b"smpl", vec![2; 36]),
+            (*b"id3 ", b"ID3\x04\0\0\0\0\0\0".to_vec()),
+            (*b"JUNK", vec![0; 3]),
+        ];
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("metadata.wav"),
+            Cursor::new(build_riff_wave(&subchunks)),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV")
+            .into_inner();
+
+        let without_audio = |subchunks: Vec<([u8; DWORD_SIZE], Vec<u8>)>| {
+            subchunks
+                .into_iter()
+                .filter(|(id, _)| *id != FMT_MAGIC && *id != DATA_MAGIC)
+                .collect::<Vec<_>>()
+        };
+        // The samples were converted, which only `bext` records
+        let mut expected = without_audio(subchunks.to_vec());
+        expected[0]
+            .1
+            .extend_from_slice(b"A=PCM,F=44100,W=16,M=mono,T=djwavfixer\r\n");
+        assert_eq!(without_audio(read_subchunks(fixed)), expected);
+    }
+
+    #[test]
+    fn test_fix_split_keeps_cues_with_first_part() {
+        let mut subchunks = int16_stereo_subchunks(25);
+        subchunks.push((*b"cue ", vec![0; 4]));
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("rf64.wav"),
+            Cursor::new(build_rf64_wave(&subchunks)),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        let WavFileLoadStatus::Success {
+            ref mut riff_file,
+            ref wave_format_info,
+        } = wav_file.load_status
+        else {
+            panic!("Expected file to load, got {:?}", wav_file.load_status);
+        };
+
+        // `LIST` and `cue ` take 28 bytes after the audio, leaving 7 frames a part
+        let (parts, report) = write_fixed_wav(
+            riff_file,
+            wave_format_info,
+            |_| Ok(Cursor::new(vec![])),
+            &FixOptions::default(),
+            92,
+        )
+        .expect("Failed to fix WAV");
+        assert_eq!(report.part_count(), 4);
+
+        let audio_data = &subchunks[1].1;
+        let fmt_data = subchunks[0].1.clone();
+        let mut parts = parts.into_iter().map(Cursor::into_inner);
+        assert_eq!(
+            parts.next().unwrap(),
+            build_riff_wave(&[
+                (FMT_MAGIC, fmt_data.clone()),
+                (DATA_MAGIC, audio_data[..28].to_vec()),
+                subchunks[3].clone(),
+            ])
+        );
+        assert_eq!(
+            parts.next_back().unwrap(),
+            build_riff_wave(&[
+                (FMT_MAGIC, fmt_data),
+                (DATA_MAGIC, audio_data[84..].to_vec()),
+                subchunks[2].clone(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_fix_resamples_cue_positions() {
+        let dwords = |values: &[u32]| {
+            values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect::<Vec<_>>()
+        };
+        let cue = dwords(&[1, 1, 96000, u32::from_le_bytes(DATA_MAGIC), 0, 0, 96000]);
+        let smpl = dwords(&[0, 0, 10417, 60, 0, 0, 0, 1, 0, 1, 0, 9600, 19199, 0, 0]);
+        let plst = dwords(&[1, 1, 48000, 2]);
+        let adtl = [
+            b"adtlltxt\x14\0\0\0".to_vec(),
+            dwords(&[1, 9600]),
+            b"rgn \0\0\0\0\0\0\0\0labl\x06\0\0\0\x01\0\0\0A\0".to_vec(),
+        ]
+        .concat();
+        let subchunks = [
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(1.into(), 96000, 16).to_bytes(),
+            ),
+            (DATA_MAGIC, vec![0; 2 * 96]),
+            (*b"cue ", cue),
+            (*b"smpl", smpl),
+            (*b"plst", plst),
+            (*b"LIST", adtl),
+            // Too short to hold the sample period, so it cannot be resampled
+            (*b"smpl", vec![0; 8]),
+        ];
+        let options = FixOptions {
+            target_sample_rate: NonZeroU32::new(48000),
+            ..FixOptions::default()
+        };
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("cues.wav"),
+            Cursor::new(build_riff_wave(&subchunks)),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &options)
+            .expect("Failed to fix WAV")
+            .into_inner();
+
+        let expected_adtl = [
+            b"adtlltxt\x14\0\0\0".to_vec(),
+            dwords(&[1, 4800]),
+            b"rgn \0\0\0\0\0\0\0\0labl\x06\0\0\0\x01\0\0\0A\0".to_vec(),
+        ]
+        .concat();
+        assert_eq!(
+            read_subchunks(fixed)[2..],
+            [
+                (
+                    *b"cue ",
+                    dwords(&[1, 1, 48000, u32::from_le_bytes(DATA_MAGIC), 0, 0, 48000])
+                ),
+                (
+                    *b"smpl",
+                    dwords(&[0, 0, 20834, 60, 0, 0, 0, 1, 0, 1, 0, 4800, 9599, 0, 0])
+                ),
+                (*b"plst", dwords(&[1, 1, 24000, 2])),
+                (*b"LIST", expected_adtl),
+            ]
+        );
+        assert_eq!(wav_file.fix_report().unwrap().warnings().len(), 1);
+    }
+
+    #[test]
+    fn test_fix_updates_bext_for_converted_samples() {
+        let bext = BroadcastExtension {
+            description: "Field recording".to_string(),
+            time_reference: 44100 * 60,
+            version: 2,
+            umid: vec![0; 64],
+            coding_history: "A=PCM,F=44100,W=8,M=mono\r\n".to_string(),
+            ..Default::default()
+        };
+        let cart = Cart {
+            version: "0101".to_string(),
+            title: "Jingle".to_string(),
+            ..Default::default()
+        };
+        let subchunks = [
+            (BEXT_MAGIC, bext.to_bytes()),
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(1.into(), 44100, 8).to_bytes(),
+            ),
+            (DATA_MAGIC, vec![128, 255]),
+            (CART_MAGIC, cart.to_bytes()),
+        ];
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("bext.wav"),
+            Cursor::new(build_riff_wave(&subchunks)),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        assert_eq!(wav_file.metadata().bext(), Some(&bext));
+
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV")
+            .into_inner();
+        let fixed_file = load_wav_reader(
+            &PathBuf::from("bext.wav"),
+            Cursor::new(fixed),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load fixed WAV");
+
+        let expected = BroadcastExtension {
+            coding_history: [
+                bext.coding_history.as_str(),
+                "A=PCM,F=44100,W=16,M=mono,T=djwavfixer\r\n",
+            ]
+            .concat(),
+            ..bext
+        };
+        assert_eq!(fixed_file.metadata().bext(), Some(&expected));
+        // The sample rate is unchanged, so the cart timers are too
+        assert_eq!(fixed_file.metadata().cart(), Some(&cart));
+    }
+
+    #[test]
+    fn test_fix_keeps_serato_cues_byte_for_byte() {
+        let geob = [
+            b"\0application/octet-stream\0\0Serato Markers2\0".to_vec(),
+            build_markers2(),
+        ]
+        .concat();
+        let id3 = build_id3_tag(4, &[(b"GEOB", 0, geob)]);
+        let subchunks = [
+            (
+                FMT_MAGIC,
+                WaveFormatExtensible::integer_pcm(2.into(), 44100, 32).to_bytes(),
+            ),
+            (DATA_MAGIC, vec![0; 16]),
+            (*b"id3 ", id3.clone()),
+        ];
+        let mut wav_file = load_wav_reader(
+            &PathBuf::from("serato.wav"),
+            Cursor::new(build_riff_wave(&subchunks)),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load WAV");
+        let cues = wav_file
+            .metadata()
+            .serato_markers()
+            .expect("Serato markers not read")
+            .cues()
+            .to_vec();
+        assert_eq!(cues.len(), 1);
+
+        let fixed = wav_file
+            .write_fixed(Cursor::new(vec![]), &FixOptions::default())
+            .expect("Failed to fix WAV")
+            .into_inner();
+        assert!(read_subchunks(fixed.clone()).contains(&(*b"id3 ", id3)));
+
+        let fixed_file = load_wav_reader(
+            &PathBuf::from("serato.wav"),
+            Cursor::new(fixed),
+            ParseMode::Strict,
+        )
+        .expect("Failed to load fixed WAV");
+        assert_eq!(
+            fixed_file
+                .metadata()
+                .serato_markers()
+                .map(|markers| markers.cues()),
+            Some(cues.as_slice())
+        );
+        assert_eq!(
+            wav_file.fix_report().map(FixReport::verified_serato_frames),
+            Some(["Serato Markers2".to_string()].as_slice())
+        );
+    }
+
+    #[test]
+    fn test_verify_serato_frames_rejects_changed_bytes() {
+        let wave = |markers2: Vec<u8>| {
+            let geob = [
+                b"\0application/octet-stream\0\0Serato Markers2\0".to_vec(),
+                markers2,
+            ]
+            .concat();
+            Cursor::new(build_riff_wave(&[
+                (
+                    FMT_MAGIC,
+                    WaveFormatExtensible::integer_pcm(2.into(), 44100, 16).to_bytes(),
+                ),
+                (DATA_MAGIC, vec![0; 16]),
+                (*b"id3 ", build_id3_tag(4, &[(b"GEOB", 0, geob)])),
+            ]))
+        };
+        let source_wave = wave(build_markers2());
+        let size = source_wave.get_ref().len() as u64 - RIFF_CHUNK_HEADER_SIZE as u64;
+        let mut source =
+            RiffFile::try_new(source_wave, size, ParseMode::Strict).expect("Failed to parse RIFF");
+        let mut changed = build_markers2();
+        *changed.last_mut().expect("Markers2 is empty") ^= 1;
+
+        let mut report = FixReport::default();
+        verify_serato_frames(&mut source, wave(build_markers2()), &mut report)
+            .expect("Identical frames rejected");
+        assert_eq!(report.verified_serato_frames(), ["Serato Markers2"]);
+        assert!(matches!(
+            verify_serato_frames(&mut source, wave(changed), &mut report),
+            Err(DJWavFixerError::FixError(_))
+        ));
+    }
+}
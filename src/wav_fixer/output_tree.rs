@@ -0,0 +1,197 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::{DJWavFixerError, Result};
+use crate::wav_fixer::safe_writer::temp_path_for;
+
+/// What to place in the output tree for files that do not need fixing
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum UnfixedFileAction {
+    /// Copy the original file
+    Copy,
+    /// Hard-link the original file, falls back to copying across filesystems
+    HardLink,
+    /// Leave the file out of the output tree
+    #[default]
+    Skip,
+}
+
+/// What ended up in the output tree for a single file
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OutputTreeAction {
+    Fixed,
+    Copied,
+    HardLinked,
+    Skipped,
+}
+
+/// Maps `file` under `source_root` to the same relative location under `output_root`
+pub(crate) fn mirrored_output_path(
+    source_root: &Path,
+    file: &Path,
+    output_root: &Path,
+) -> Result<PathBuf> {
+    let relative_path = file.strip_prefix(source_root).map_err(|_| {
+        DJWavFixerError::GeneralError(format!(
+            "`{}` is not inside `{}`",
+            file.display(),
+            source_root.display()
+        ))
+    })?;
+
+    if relative_path.as_os_str().is_empty() {
+        return Err(DJWavFixerError::GeneralError(format!(
+            "`{}` has no file name relative to `{}`",
+            file.display(),
+            source_root.display()
+        )));
+    }
+
+    Ok(output_root.join(relative_path))
+}
+
+fn create_parent_directories(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+/// Whether `path` and `other` name the same file, however they are spelled
+pub(crate) fn is_same_file(path: &Path, other: &Path) -> Result<bool> {
+    if !other.exists() {
+        return Ok(false);
+    }
+    Ok(fs::canonicalize(path)? == fs::canonicalize(other)?)
+}
+
+/// Hard-links or copies `source` to `temp_path`, returning whether it was linked
+fn link_or_copy(source: &Path, temp_path: &Path, action: UnfixedFileAction) -> Result<bool> {
+    if action == UnfixedFileAction::HardLink {
+        match fs::hard_link(source, temp_path) {
+            Ok(()) => return Ok(true),
+            Err(error) => log::debug!(
+                "Could not hard-link `{}`, copying instead: {}",
+                source.display(),
+                error
+            ),
+        }
+    }
+
+    fs::copy(source, temp_path)?;
+    Ok(false)
+}
+
+/// Places an unmodified copy of `source` at `destination` according to `action`
+pub(crate) fn place_unfixed_file(
+    source: &Path,
+    destination: &Path,
+    action: UnfixedFileAction,
+) -> Result<OutputTreeAction> {
+    if action == UnfixedFileAction::Skip {
+        return Ok(OutputTreeAction::Skipped);
+    }
+
+    if is_same_file(source, destination)? {
+        return Err(DJWavFixerError::GeneralError(format!(
+            "Output path `{}` is the same as the source file",
+            destination.display()
+        )));
+    }
+
+    create_parent_directories(destination)?;
+    // Whatever is at `destination` stays until the new file can be renamed over it
+    let temp_path = temp_path_for(destination);
+    if temp_path.exists() {
+        fs::remove_file(&temp_path)?;
+    }
+    let linked = link_or_copy(source, &temp_path, action).and_then(|linked| {
+        fs::rename(&temp_path, destination)?;
+        Ok(linked)
+    });
+    if linked.is_err() && temp_path.exists() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    Ok(match linked? {
+        true => OutputTreeAction::HardLinked,
+        false => OutputTreeAction::Copied,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_mirrored_output_path() {
+        let source_root = PathBuf::from("/music/library");
+        let output_root = PathBuf::from("/music/fixed");
+
+        assert_eq!(
+            mirrored_output_path(
+                &source_root,
+                &source_root.join("house").join("track.wav"),
+                &output_root
+            ),
+            Ok(output_root.join("house").join("track.wav"))
+        );
+        assert!(
+            mirrored_output_path(
+                &source_root,
+                Path::new("/elsewhere/track.wav"),
+                &output_root
+            )
+            .is_err()
+        );
+        assert!(mirrored_output_path(&source_root, &source_root, &output_root).is_err());
+    }
+
+    #[test]
+    fn test_place_unfixed_file() {
+        let root = env::temp_dir().join(format!("djwavfixer-output-tree-{}", std::process::id()));
+        let source = root.join("source").join("track.wav");
+        create_parent_directories(&source).expect("Failed to create source directory");
+        fs::write(&source, b"RIFF").expect("Failed to write source file");
+
+        let skipped = root.join("skip").join("track.wav");
+        assert_eq!(
+            place_unfixed_file(&source, &skipped, UnfixedFileAction::Skip),
+            Ok(OutputTreeAction::Skipped)
+        );
+        assert!(!skipped.exists());
+
+        let copied = root.join("copy").join("nested").join("track.wav");
+        assert_eq!(
+            place_unfixed_file(&source, &copied, UnfixedFileAction::Copy),
+            Ok(OutputTreeAction::Copied)
+        );
+        assert_eq!(fs::read(&copied).unwrap(), b"RIFF");
+
+        let linked = root.join("link").join("track.wav");
+        assert!(place_unfixed_file(&source, &linked, UnfixedFileAction::HardLink).is_ok());
+        assert_eq!(fs::read(&linked).unwrap(), b"RIFF");
+
+        // The source reached through `..` must be left alone
+        let aliased = root
+            .join("copy")
+            .join("..")
+            .join("source")
+            .join("track.wav");
+        assert!(place_unfixed_file(&source, &aliased, UnfixedFileAction::Copy).is_err());
+        assert!(place_unfixed_file(&source, &aliased, UnfixedFileAction::HardLink).is_err());
+        assert_eq!(fs::read(&source).unwrap(), b"RIFF");
+
+        // Placing again replaces the earlier copy
+        fs::write(&source, b"RIFF2").expect("Failed to rewrite source file");
+        assert_eq!(
+            place_unfixed_file(&source, &copied, UnfixedFileAction::Copy),
+            Ok(OutputTreeAction::Copied)
+        );
+        assert_eq!(fs::read(&copied).unwrap(), b"RIFF2");
+        assert!(!temp_path_for(&copied).exists());
+
+        fs::remove_dir_all(&root).expect("Failed to clean up");
+    }
+}
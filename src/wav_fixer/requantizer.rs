@@ -0,0 +1,179 @@
+/// Bit depth that converted files are written with
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum TargetBitDepth {
+    #[value(name = "16")]
+    Bits16,
+    #[default]
+    #[value(name = "24")]
+    Bits24,
+}
+
+impl TargetBitDepth {
+    pub(crate) fn bits_per_sample(&self) -> u16 {
+        match self {
+            TargetBitDepth::Bits16 => 16,
+            TargetBitDepth::Bits24 => 24,
+        }
+    }
+}
+
+/// How precision is removed when reducing the bit depth of samples
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum DitherMode {
+    /// Drop the low bits, cheapest but leaves correlated distortion in quiet passages
+    Truncate,
+    /// Add triangular noise of ±1 LSB before rounding
+    #[default]
+    Tpdf,
+    /// TPDF dither with error feedback that moves the noise towards less audible frequencies
+    NoiseShaped,
+}
+
+/// Error feedback coefficients from Lipshitz, Vanderkooy and Wannamaker, designed for 44.1 kHz
+const NOISE_SHAPING_COEFFICIENTS: [f64; 5] = [2.033, -2.165, 1.959, -1.590, 0.6149];
+
+/// Small deterministic xorshift generator, so conversions are reproducible between runs
+struct Xorshift64(u64);
+
+impl Xorshift64 {
+    fn next_unit(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        // Use the top 53 bits, which is all the precision an f64 mantissa holds
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Triangular noise in `(-1.0, 1.0)` LSB
+    fn next_tpdf(&mut self) -> f64 {
+        self.next_unit() - self.next_unit()
+    }
+}
+
+/// Converts normalized samples into little-endian integer PCM of a given bit depth
+pub(crate) struct Requantizer {
+    bits_per_sample: u16,
+    dither: DitherMode,
+    random: Xorshift64,
+    error_history: Vec<[f64; NOISE_SHAPING_COEFFICIENTS.len()]>,
+}
+
+impl Requantizer {
+    pub(crate) fn new(bits_per_sample: u16, dither: DitherMode, channels: usize) -> Self {
+        Self {
+            bits_per_sample,
+            dither,
+            random: Xorshift64(0x9E37_79B9_7F4A_7C15),
+            error_history: vec![[0.0; NOISE_SHAPING_COEFFICIENTS.len()]; channels],
+        }
+    }
+
+    fn quantize(&mut self, sample: f64, channel: usize) -> i32 {
+        let scale = (1i64 << (self.bits_per_sample - 1)) as f64;
+        let scaled = sample * scale;
+
+        let quantized = match self.dither {
+            DitherMode::Truncate => scaled.floor(),
+            DitherMode::Tpdf => (scaled + self.random.next_tpdf()).round(),
+            DitherMode::NoiseShaped => {
+                let history = &mut self.error_history[channel];
+                let shaped = scaled
+                    - NOISE_SHAPING_COEFFICIENTS
+                        .iter()
+                        .zip(history.iter())
+                        .map(|(coefficient, error)| coefficient * error)
+                        .sum::<f64>();
+                let quantized = (shaped + self.random.next_tpdf()).round();
+
+                history.rotate_right(1);
+                // Measured before clamping, so a clipped sample cannot make the filter run away
+                history[0] = quantized - shaped;
+                quantized
+            }
+        };
+
+        quantized.clamp(-scale, scale - 1.0) as i32
+    }
+
+    /// Quantizes interleaved `samples` and appends the encoded bytes to `output`
+    pub(crate) fn encode(&mut self, samples: &[f64], output: &mut Vec<u8>) {
+        let channels = self.error_history.len();
+        let bytes_per_sample = self.bits_per_sample.div_ceil(8) as usize;
+
+        for (index, sample) in samples.iter().enumerate() {
+            let quantized = self.quantize(*sample, index % channels);
+            output.extend_from_slice(&quantized.to_le_bytes()[..bytes_per_sample]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_24bit(data: &[u8]) -> Vec<i32> {
+        data.chunks_exact(3)
+            .map(|sample| i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) >> 8)
+            .collect()
+    }
+
+    #[test]
+    fn test_truncate_drops_low_bits() {
+        let mut requantizer = Requantizer::new(24, DitherMode::Truncate, 1);
+        let samples = [0x7FFF_FFFFi32, -0x8000_0000, 0x0000_01FF, -1]
+            .map(|sample| sample as f64 / (1u64 << 31) as f64);
+
+        let mut output = vec![];
+        requantizer.encode(&samples, &mut output);
+
+        assert_eq!(decode_24bit(&output), [0x7F_FFFF, -0x80_0000, 0x01, -1]);
+    }
+
+    #[test]
+    fn test_dither_error_is_bounded() {
+        let samples = (0..4096)
+            .map(|index| (index as f64 / 100.0).sin() * 0.5)
+            .collect::<Vec<_>>();
+
+        for dither in [DitherMode::Tpdf, DitherMode::NoiseShaped] {
+            let mut requantizer = Requantizer::new(16, dither, 2);
+            let mut output = vec![];
+            requantizer.encode(&samples, &mut output);
+
+            let max_error = output
+                .chunks_exact(2)
+                .zip(samples.iter())
+                .map(|(sample, expected)| {
+                    (i16::from_le_bytes([sample[0], sample[1]]) as f64 - expected * 32768.0).abs()
+                })
+                .fold(0.0, f64::max);
+
+            // TPDF adds at most 1 LSB plus rounding, noise shaping trades some extra for a better
+            // spectrum
+            let allowed_error = match dither {
+                DitherMode::NoiseShaped => 16.0,
+                _ => 1.5,
+            };
+            assert!(
+                max_error <= allowed_error,
+                "{:?} error {} exceeds {}",
+                dither,
+                max_error,
+                allowed_error
+            );
+        }
+    }
+
+    #[test]
+    fn test_clamps_to_full_scale() {
+        let mut requantizer = Requantizer::new(16, DitherMode::Truncate, 1);
+        let mut output = vec![];
+        requantizer.encode(&[1.5, -1.5, 1.0, 0.0], &mut output);
+
+        let decoded = output
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect::<Vec<_>>();
+        assert_eq!(&decoded[..3], [i16::MAX, i16::MIN, i16::MAX]);
+    }
+}
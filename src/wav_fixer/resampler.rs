@@ -0,0 +1,272 @@
+//! Band-limited sample rate conversion with a polyphase windowed-sinc filter
+
+use std::f64::consts::PI;
+
+/// Zero crossings of the sinc on each side of the filter centre, more gives a steeper transition
+/// band
+const ZERO_CROSSINGS: usize = 32;
+
+/// Passband edge as a fraction of the lower Nyquist frequency, the rest is the transition band
+const ROLLOFF: f64 = 0.945;
+
+/// Kaiser window shape, around 90 dB of stopband attenuation
+const KAISER_BETA: f64 = 9.0;
+
+/// Most filter phases tabulated. Rate pairs needing more, such as nearly equal odd rates, get
+/// taps interpolated between the two nearest phases instead
+const MAX_PHASES: usize = 1024;
+
+fn greatest_common_divisor(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Zeroth order modified Bessel function of the first kind, used by the Kaiser window
+fn bessel_i0(x: f64) -> f64 {
+    let (mut sum, mut term) = (1.0, 1.0);
+    for k in 1..64 {
+        term *= (x / (2.0 * k as f64)).powi(2);
+        sum += term;
+        if term < sum * f64::EPSILON {
+            break;
+        }
+    }
+    sum
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Converts interleaved samples between two sample rates, keeping state between blocks
+pub(crate) struct Resampler {
+    /// Output samples per `decimation` input samples
+    interpolation: usize,
+    decimation: usize,
+    /// Input frames on each side of an output frame that contribute to it
+    half_width: usize,
+    /// Filter taps for each fractional output position, `2 * half_width` per phase. Holds
+    /// `MAX_PHASES + 1` evenly spaced phases when `interpolation` is larger than that
+    phases: Vec<Vec<f64>>,
+    /// Taps interpolated for the current output frame, when `phases` is not one per phase
+    interpolated_taps: Vec<f64>,
+    channels: usize,
+    /// Interleaved input frames that upcoming output frames still depend on
+    history: Vec<f64>,
+    /// Frame in `history` that the next output frame is centred on
+    position: usize,
+    /// Fractional part of the next output position, in `1 / interpolation` frames
+    phase: usize,
+    input_frames: u64,
+    output_frames: u64,
+}
+
+impl Resampler {
+    pub(crate) fn new(source_sample_rate: u32, target_sample_rate: u32, channels: usize) -> Self {
+        let divisor = greatest_common_divisor(source_sample_rate as u64, target_sample_rate as u64);
+        let interpolation = (target_sample_rate as u64 / divisor) as usize;
+        let decimation = (source_sample_rate as u64 / divisor) as usize;
+
+        // In cycles per input sample, below the Nyquist frequency of whichever rate is lower
+        let cutoff = 0.5 * (interpolation as f64 / decimation as f64).min(1.0) * ROLLOFF;
+        let half_width = (ZERO_CROSSINGS as f64 / (2.0 * cutoff)).ceil() as usize;
+
+        let window_scale = bessel_i0(KAISER_BETA);
+        // The extra phase at a fraction of 1.0 is the far end for interpolating the last one
+        let phase_count = if interpolation > MAX_PHASES {
+            MAX_PHASES + 1
+        } else {
+            interpolation
+        };
+        let phase_spacing = interpolation.min(MAX_PHASES);
+        let phases = (0..phase_count)
+            .map(|phase| {
+                let fraction = phase as f64 / phase_spacing as f64;
+                let taps = (0..2 * half_width)
+                    .map(|tap| {
+                        let offset = tap as f64 - (half_width - 1) as f64 - fraction;
+                        let window_position = offset / half_width as f64;
+                        let window = bessel_i0(
+                            KAISER_BETA * (1.0 - window_position * window_position).max(0.0).sqrt(),
+                        ) / window_scale;
+                        2.0 * cutoff * sinc(2.0 * cutoff * offset) * window
+                    })
+                    .collect::<Vec<_>>();
+
+                // Normalizing every phase keeps DC exact, whatever the window did to the sum
+                let sum = taps.iter().sum::<f64>();
+                taps.into_iter().map(|tap| tap / sum).collect()
+            })
+            .collect();
+
+        Self {
+            interpolation,
+            decimation,
+            half_width,
+            phases,
+            interpolated_taps: vec![],
+            channels,
+            // Silence before the first frame, so the first output frames have a full filter
+            history: vec![0.0; (half_width - 1) * channels],
+            position: half_width - 1,
+            phase: 0,
+            input_frames: 0,
+            output_frames: 0,
+        }
+    }
+
+    /// Produces output frames for as long as `history` holds their inputs, up to `frame_limit`
+    fn drain(&mut self, frame_limit: u64, output: &mut Vec<f64>) {
+        let channels = self.channels;
+        while self.history.len() / channels > self.position + self.half_width
+            && self.output_frames < frame_limit
+        {
+            let taps = if self.phases.len() == self.interpolation {
+                &self.phases[self.phase]
+            } else {
+                let position = self.phase as f64 * MAX_PHASES as f64 / self.interpolation as f64;
+                let (index, weight) = (position as usize, position.fract());
+                self.interpolated_taps.clear();
+                self.interpolated_taps.extend(
+                    self.phases[index]
+                        .iter()
+                        .zip(&self.phases[index + 1])
+                        .map(|(low, high)| low + (high - low) * weight),
+                );
+                &self.interpolated_taps
+            };
+            let first_frame = self.position + 1 - self.half_width;
+            let window = &self.history[first_frame * channels..][..taps.len() * channels];
+
+            for channel in 0..channels {
+                output.push(
+                    taps.iter()
+                        .zip(window.iter().skip(channel).step_by(channels))
+                        .map(|(tap, sample)| tap * sample)
+                        .sum(),
+                );
+            }
+            self.output_frames += 1;
+
+            self.phase += self.decimation;
+            self.position += self.phase / self.interpolation;
+            self.phase %= self.interpolation;
+        }
+
+        // Forget frames that are behind the filter of the next output frame
+        let consumed_frames =
+            (self.position + 1 - self.half_width).min(self.history.len() / channels);
+        self.history.drain(..consumed_frames * channels);
+        self.position -= consumed_frames;
+    }
+
+    /// Feeds interleaved `input` samples and appends the output frames they complete
+    pub(crate) fn process(&mut self, input: &[f64], output: &mut Vec<f64>) {
+        self.history.extend_from_slice(input);
+        self.input_frames += (input.len() / self.channels) as u64;
+        self.drain(u64::MAX, output);
+    }
+
+    /// Appends the remaining output frames, so the output lasts exactly as long as the input
+    pub(crate) fn finish(&mut self, output: &mut Vec<f64>) {
+        let total_frames =
+            (self.input_frames * self.interpolation as u64).div_ceil(self.decimation as u64);
+
+        self.history.resize(
+            self.history.len() + (self.half_width + 1) * self.channels,
+            0.0,
+        );
+        self.drain(total_frames, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resample(source_sample_rate: u32, target_sample_rate: u32, input: &[f64]) -> Vec<f64> {
+        let mut resampler = Resampler::new(source_sample_rate, target_sample_rate, 1);
+        let mut output = vec![];
+        // Uneven blocks, to check state carries over between them
+        for block in input.chunks(1000) {
+            resampler.process(block, &mut output);
+        }
+        resampler.finish(&mut output);
+        output
+    }
+
+    fn sine(sample_rate: u32, frequency: f64, frames: usize) -> Vec<f64> {
+        (0..frames)
+            .map(|frame| 0.5 * (2.0 * PI * frequency * frame as f64 / sample_rate as f64).sin())
+            .collect()
+    }
+
+    fn peak(samples: &[f64]) -> f64 {
+        samples
+            .iter()
+            .fold(0.0, |peak, sample| sample.abs().max(peak))
+    }
+
+    #[test]
+    fn test_output_length_follows_ratio() {
+        assert_eq!(resample(96000, 48000, &[0.0; 9601]).len(), 4801);
+        assert_eq!(resample(88200, 48000, &[0.0; 88200]).len(), 48000);
+        assert_eq!(resample(192000, 44100, &[0.0; 1000]).len(), 230);
+        assert_eq!(resample(44100, 48000, &[0.0; 441]).len(), 480);
+    }
+
+    #[test]
+    fn test_passband_is_preserved() {
+        let output = resample(96000, 44100, &sine(96000, 1000.0, 96000));
+        let expected = sine(44100, 1000.0, 44100);
+
+        // Skip the edges, where the filter runs into the silence around the input
+        let max_error = output[1000..43000]
+            .iter()
+            .zip(&expected[1000..43000])
+            .map(|(sample, expected)| (sample - expected).abs())
+            .fold(0.0, f64::max);
+        assert!(
+            max_error < 1e-3,
+            "Passband error {} is too large",
+            max_error
+        );
+    }
+
+    #[test]
+    fn test_frequencies_above_target_nyquist_are_removed() {
+        let output = resample(96000, 48000, &sine(96000, 30000.0, 96000));
+
+        // 0.5 full scale input, -60 dB below that is plenty to make the alias inaudible
+        let alias_peak = peak(&output[1000..47000]);
+        assert!(alias_peak < 0.0005, "Alias peak {} is too loud", alias_peak);
+    }
+
+    #[test]
+    fn test_coprime_rates_interpolate_between_phases() {
+        // Reduces to 44101 / 96001, far more phases than are tabulated
+        let resampler = Resampler::new(96001, 44101, 1);
+        assert_eq!(resampler.interpolation, 44101);
+        assert_eq!(resampler.phases.len(), MAX_PHASES + 1);
+
+        let output = resample(96001, 44101, &sine(96001, 1000.0, 96001));
+        assert_eq!(output.len(), 44101);
+        let expected = sine(44101, 1000.0, 44101);
+        let max_error = output[1000..43000]
+            .iter()
+            .zip(&expected[1000..43000])
+            .map(|(sample, expected)| (sample - expected).abs())
+            .fold(0.0, f64::max);
+        assert!(
+            max_error < 1e-3,
+            "Passband error {} is too large",
+            max_error
+        );
+    }
+}
@@ -0,0 +1,500 @@
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::errors::{DJWavFixerError, Result};
+
+pub(crate) const BACKUP_DIRECTORY_NAME: &str = ".djwavfixer-backups";
+
+/// How the previous version of a file is preserved before it is replaced
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum BackupMode {
+    /// Do not keep the previous version
+    #[default]
+    None,
+    /// Keep the previous version next to the file, as `<name>.bak`. An existing backup is kept,
+    /// the new one goes to the first free `<name>.bak.N`
+    Bak,
+    /// Keep the previous version in a backup directory, named after the SHA-256 of its content
+    ContentAddressed,
+}
+
+/// The step of a crash-safe write that failed
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WriteStage {
+    CreateDirectory,
+    CreateTempFile,
+    WriteTempFile,
+    SyncTempFile,
+    Backup,
+    Rename,
+    SyncDirectory,
+}
+
+impl Display for WriteStage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteStage::CreateDirectory => write!(f, "creating the output directory"),
+            WriteStage::CreateTempFile => write!(f, "creating the temporary file"),
+            WriteStage::WriteTempFile => write!(f, "writing the temporary file"),
+            WriteStage::SyncTempFile => write!(f, "syncing the temporary file to disk"),
+            WriteStage::Backup => write!(f, "backing up the original file"),
+            WriteStage::Rename => write!(f, "renaming the temporary file over the original"),
+            WriteStage::SyncDirectory => write!(f, "syncing the parent directory"),
+        }
+    }
+}
+
+fn stage_error(stage: WriteStage, path: &Path) -> impl FnOnce(DJWavFixerError) -> DJWavFixerError {
+    move |error| DJWavFixerError::SafeWriteError {
+        stage,
+        path: path.to_path_buf(),
+        source: Box::new(error),
+    }
+}
+
+pub(crate) fn temp_path_for(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".djwavfixer-tmp");
+    path.with_file_name(file_name)
+}
+
+/// Where the previous version of a file is kept while a commit may still be rolled back
+fn rollback_path_for(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".djwavfixer-old");
+    path.with_file_name(file_name)
+}
+
+pub(crate) fn bak_path_for(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".bak");
+    path.with_file_name(file_name)
+}
+
+/// `<name>.bak`, or the first free `<name>.bak.N` when earlier backups exist
+fn free_bak_path_for(path: &Path) -> PathBuf {
+    let bak_path = bak_path_for(path);
+    let mut candidate = bak_path.clone();
+    let mut number = 1;
+    while candidate.exists() {
+        let mut file_name = bak_path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(format!(".{}", number));
+        candidate = bak_path.with_file_name(file_name);
+        number += 1;
+    }
+    candidate
+}
+
+fn sha256_hex(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Copies `source` to `destination`, preferring a hard link since the original inode survives
+/// the rename
+fn link_or_copy_synced(source: &Path, destination: &Path) -> Result<()> {
+    if fs::hard_link(source, destination).is_err() {
+        fs::copy(source, destination)?;
+        File::open(destination)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> Result<()> {
+    File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> Result<()> {
+    // Directories cannot be opened as files here, renames are journaled by the filesystem
+    Ok(())
+}
+
+/// A file being written to a sibling temp file, which `SafeWriter::commit` moves into place. The
+/// temp file is removed if it is dropped before then
+pub(crate) struct PendingFile {
+    path: PathBuf,
+    temp_path: PathBuf,
+    writer: BufWriter<File>,
+    committed: bool,
+}
+
+impl PendingFile {
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Opens what has been written so far for reading, before it is committed
+    pub(crate) fn read_back(&mut self) -> Result<BufReader<File>> {
+        self.writer.flush()?;
+        Ok(BufReader::new(File::open(&self.temp_path)?))
+    }
+
+    /// Flushes the temp file and syncs it to disk
+    fn sync(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .map_err(DJWavFixerError::from)
+            .map_err(stage_error(WriteStage::WriteTempFile, &self.path))?;
+        self.writer
+            .get_ref()
+            .sync_all()
+            .map_err(DJWavFixerError::from)
+            .map_err(stage_error(WriteStage::SyncTempFile, &self.path))
+    }
+}
+
+impl Write for PendingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Seek for PendingFile {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.writer.seek(position)
+    }
+}
+
+impl Drop for PendingFile {
+    fn drop(&mut self) {
+        if !self.committed && self.temp_path.exists() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Writes files through sibling temp files that are synced and atomically renamed over the
+/// destination, so an interrupted write never leaves a partially written file behind
+pub(crate) struct SafeWriter {
+    pub(crate) backup_mode: BackupMode,
+    pub(crate) backup_directory: Option<PathBuf>,
+}
+
+impl SafeWriter {
+    fn backup(&self, path: &Path) -> Result<Option<PathBuf>> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let backup_path = match self.backup_mode {
+            BackupMode::None => return Ok(None),
+            BackupMode::Bak => free_bak_path_for(path),
+            BackupMode::ContentAddressed => {
+                let backup_directory = self.backup_directory.clone().unwrap_or_else(|| {
+                    path.parent()
+                        .unwrap_or(Path::new(""))
+                        .join(BACKUP_DIRECTORY_NAME)
+                });
+                fs::create_dir_all(&backup_directory)?;
+
+                let extension = path.extension().unwrap_or_default().to_string_lossy();
+                let backup_path = backup_directory
+                    .join(sha256_hex(path)?)
+                    .with_extension(extension.as_ref());
+                if backup_path.is_file() {
+                    // Same content is already backed up
+                    return Ok(Some(backup_path));
+                }
+                backup_path
+            }
+        };
+
+        link_or_copy_synced(path, &backup_path)?;
+        Ok(Some(backup_path))
+    }
+
+    /// Starts writing `path` through a temp file next to it, nothing at `path` changes until the
+    /// returned file is committed
+    pub(crate) fn create(&self, path: &Path) -> Result<PendingFile> {
+        let parent = path.parent().unwrap_or(Path::new(""));
+        fs::create_dir_all(parent)
+            .map_err(DJWavFixerError::from)
+            .map_err(stage_error(WriteStage::CreateDirectory, path))?;
+
+        let temp_path = temp_path_for(path);
+        let temp_file = File::create(&temp_path)
+            .map_err(DJWavFixerError::from)
+            .map_err(stage_error(WriteStage::CreateTempFile, path))?;
+
+        Ok(PendingFile {
+            path: path.to_path_buf(),
+            temp_path,
+            writer: BufWriter::new(temp_file),
+            committed: false,
+        })
+    }
+
+    /// Moves the pending files into place once all of them are synced to disk, the first one
+    /// last. If any of them cannot be moved into place, the ones already moved are rolled back
+    /// to what was there before. Returns where the previous version of each was backed up to, if
+    /// anywhere
+    pub(crate) fn commit(&self, mut files: Vec<PendingFile>) -> Result<Vec<Option<PathBuf>>> {
+        for file in &mut files {
+            file.sync()?;
+        }
+
+        let mut replaced = vec![];
+        let result = self.move_into_place(&mut files, &mut replaced);
+        for (path, rollback_path) in replaced.into_iter().rev() {
+            let restored = match (&result, rollback_path) {
+                (Ok(_), Some(rollback_path)) => fs::remove_file(rollback_path),
+                (Ok(_), None) => Ok(()),
+                (Err(_), Some(rollback_path)) => fs::rename(rollback_path, &path),
+                (Err(_), None) => fs::remove_file(&path),
+            };
+            if let Err(error) = restored {
+                log::error!(
+                    "Could not clean up after writing `{}`: {}",
+                    path.display(),
+                    error
+                );
+            }
+        }
+
+        result
+    }
+
+    /// Renames each file over its destination, recording in `replaced` where the previous version
+    /// of every destination it replaced was kept, `None` if there was nothing there
+    fn move_into_place(
+        &self,
+        files: &mut [PendingFile],
+        replaced: &mut Vec<(PathBuf, Option<PathBuf>)>,
+    ) -> Result<Vec<Option<PathBuf>>> {
+        let mut backup_paths = vec![None; files.len()];
+        for (index, file) in files.iter_mut().enumerate().rev() {
+            backup_paths[index] = self
+                .backup(&file.path)
+                .map_err(stage_error(WriteStage::Backup, &file.path))?;
+
+            let rollback_path = match file.path.is_file() {
+                true => {
+                    let rollback_path = rollback_path_for(&file.path);
+                    link_or_copy_synced(&file.path, &rollback_path)
+                        .map_err(stage_error(WriteStage::Backup, &file.path))?;
+                    Some(rollback_path)
+                }
+                false => None,
+            };
+            fs::rename(&file.temp_path, &file.path)
+                .map_err(DJWavFixerError::from)
+                .map_err(stage_error(WriteStage::Rename, &file.path))
+                .inspect_err(|_| {
+                    if let Some(rollback_path) = &rollback_path {
+                        let _ = fs::remove_file(rollback_path);
+                    }
+                })?;
+            file.committed = true;
+            replaced.push((file.path.clone(), rollback_path));
+
+            let parent = file.path.parent().unwrap_or(Path::new(""));
+            sync_directory(if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            })
+            .map_err(stage_error(WriteStage::SyncDirectory, &file.path))?;
+        }
+
+        Ok(backup_paths)
+    }
+
+    /// Runs `write` against a temp file next to `path` and moves the result into place,
+    /// returning where the previous version was backed up to, if anywhere
+    #[cfg(test)]
+    pub(crate) fn write<F>(&self, path: &Path, write: F) -> Result<Option<PathBuf>>
+    where
+        F: FnOnce(&mut PendingFile) -> Result<()>,
+    {
+        let mut file = self.create(path)?;
+        write(&mut file).map_err(stage_error(WriteStage::WriteTempFile, path))?;
+        Ok(self.commit(vec![file])?.remove(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!(
+            "djwavfixer-safe-writer-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&directory).expect("Failed to create test directory");
+        directory
+    }
+
+    #[test]
+    fn test_safe_write_with_bak_backup() {
+        let directory = test_directory("bak");
+        let path = directory.join("track.wav");
+        fs::write(&path, b"original").unwrap();
+
+        let writer = SafeWriter {
+            backup_mode: BackupMode::Bak,
+            backup_directory: None,
+        };
+        let backup_path = writer
+            .write(&path, |file| Ok(file.write_all(b"fixed")?))
+            .expect("Safe write failed");
+
+        assert_eq!(backup_path, Some(directory.join("track.wav.bak")));
+        assert_eq!(fs::read(&path).unwrap(), b"fixed");
+        assert_eq!(
+            fs::read(directory.join("track.wav.bak")).unwrap(),
+            b"original"
+        );
+        assert!(!temp_path_for(&path).exists());
+
+        // Fixing again keeps the first backup
+        let backup_path = writer
+            .write(&path, |file| Ok(file.write_all(b"fixed again")?))
+            .expect("Safe write failed");
+        assert_eq!(backup_path, Some(directory.join("track.wav.bak.1")));
+        assert_eq!(
+            fs::read(directory.join("track.wav.bak")).unwrap(),
+            b"original"
+        );
+        assert_eq!(
+            fs::read(directory.join("track.wav.bak.1")).unwrap(),
+            b"fixed"
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_safe_write_with_content_addressed_backup() {
+        let directory = test_directory("content-addressed");
+        let path = directory.join("track.wav");
+        fs::write(&path, b"original").unwrap();
+
+        let writer = SafeWriter {
+            backup_mode: BackupMode::ContentAddressed,
+            backup_directory: None,
+        };
+        let backup_path = writer
+            .write(&path, |file| Ok(file.write_all(b"fixed")?))
+            .expect("Safe write failed")
+            .expect("Expected a backup");
+
+        assert_eq!(
+            backup_path,
+            directory
+                .join(BACKUP_DIRECTORY_NAME)
+                .join("0682c5f2076f099c34cfdd15a9e063849ed437a49677e6fcc5b4198c76575be5.wav")
+        );
+        assert_eq!(fs::read(&backup_path).unwrap(), b"original");
+        assert_eq!(fs::read(&path).unwrap(), b"fixed");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_keeps_original() {
+        let directory = test_directory("failure");
+        let path = directory.join("track.wav");
+        fs::write(&path, b"original").unwrap();
+
+        let writer = SafeWriter {
+            backup_mode: BackupMode::None,
+            backup_directory: None,
+        };
+        let result = writer.write(&path, |file| {
+            file.write_all(b"partial")?;
+            Err(DJWavFixerError::FixError("Interrupted".to_string()))
+        });
+
+        assert!(matches!(
+            result,
+            Err(DJWavFixerError::SafeWriteError {
+                stage: WriteStage::WriteTempFile,
+                ..
+            })
+        ));
+        assert_eq!(fs::read(&path).unwrap(), b"original");
+        assert!(!temp_path_for(&path).exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_commit_replaces_every_file_or_none() {
+        let directory = test_directory("commit");
+        let paths = [
+            directory.join("track.wav"),
+            directory.join("track.part2.wav"),
+        ];
+        for path in &paths {
+            fs::write(path, b"original").unwrap();
+        }
+
+        let writer = SafeWriter {
+            backup_mode: BackupMode::Bak,
+            backup_directory: None,
+        };
+        let open = || {
+            paths
+                .iter()
+                .map(|path| {
+                    let mut file = writer.create(path).expect("Failed to create file");
+                    file.write_all(b"fixed").unwrap();
+                    file
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Abandoned halfway, as when a later part fails to write
+        drop(open());
+        for path in &paths {
+            assert_eq!(fs::read(path).unwrap(), b"original");
+            assert!(!temp_path_for(path).exists());
+        }
+
+        let backup_paths = writer.commit(open()).expect("Commit failed");
+        for (path, backup_path) in paths.iter().zip(backup_paths) {
+            assert_eq!(fs::read(path).unwrap(), b"fixed");
+            assert_eq!(fs::read(backup_path.unwrap()).unwrap(), b"original");
+        }
+        assert!(!rollback_path_for(&paths[1]).exists());
+
+        // The first part is moved into place last, a directory in its way stops the commit
+        fs::write(&paths[1], b"original").unwrap();
+        fs::remove_file(&paths[0]).unwrap();
+        fs::create_dir_all(paths[0].join("blocking")).unwrap();
+        assert!(matches!(
+            writer.commit(open()),
+            Err(DJWavFixerError::SafeWriteError {
+                stage: WriteStage::Rename,
+                ..
+            })
+        ));
+        assert_eq!(fs::read(&paths[1]).unwrap(), b"original");
+        for path in &paths {
+            assert!(!temp_path_for(path).exists());
+            assert!(!rollback_path_for(path).exists());
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
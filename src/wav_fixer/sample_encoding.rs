@@ -0,0 +1,141 @@
+use crate::wav_file::{AdpcmFormat, WaveFormatExtensible, WaveFormatType};
+use crate::wav_fixer::adpcm;
+use crate::wav_fixer::g711::{ALAW_TO_LINEAR, ULAW_TO_LINEAR};
+
+/// How individual samples are stored in a `data` subchunk
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum SampleEncoding {
+    /// 8-bit samples offset by 128, the only unsigned PCM layout WAV allows
+    UnsignedInteger8,
+    /// Little-endian two's complement integers, 2 to 4 bytes wide
+    SignedInteger { bytes_per_sample: usize },
+    /// Little-endian IEEE 754 single precision floats
+    Float32,
+    /// Little-endian IEEE 754 double precision floats
+    Float64,
+    /// G.711 A-law companded bytes
+    ALaw,
+    /// G.711 μ-law companded bytes
+    ULaw,
+    /// 4-bit IMA or Microsoft ADPCM, decoded a whole block at a time
+    Adpcm {
+        adpcm_format: AdpcmFormat,
+        channels: usize,
+        block_align: usize,
+    },
+}
+
+impl SampleEncoding {
+    pub(crate) fn from_format(wave_format_info: &WaveFormatExtensible) -> Option<Self> {
+        let bytes_per_sample = wave_format_info.bits_per_sample.div_ceil(8) as usize;
+
+        // Extensible headers are decoded by their SubFormat
+        match wave_format_info.sample_format()? {
+            WaveFormatType::IntegerPCM if bytes_per_sample == 1 => {
+                Some(SampleEncoding::UnsignedInteger8)
+            }
+            WaveFormatType::IntegerPCM if (2..=4).contains(&bytes_per_sample) => {
+                Some(SampleEncoding::SignedInteger { bytes_per_sample })
+            }
+            WaveFormatType::FloatPCM if wave_format_info.bits_per_sample == 32 => {
+                Some(SampleEncoding::Float32)
+            }
+            WaveFormatType::FloatPCM if wave_format_info.bits_per_sample == 64 => {
+                Some(SampleEncoding::Float64)
+            }
+            WaveFormatType::ALaw if wave_format_info.bits_per_sample == 8 => {
+                Some(SampleEncoding::ALaw)
+            }
+            WaveFormatType::ULaw if wave_format_info.bits_per_sample == 8 => {
+                Some(SampleEncoding::ULaw)
+            }
+            WaveFormatType::MicrosoftADPCM | WaveFormatType::ImaAdpcm
+                if wave_format_info.bits_per_sample == 4 =>
+            {
+                Some(SampleEncoding::Adpcm {
+                    adpcm_format: wave_format_info.adpcm_format()?,
+                    channels: wave_format_info.channels.as_u16() as usize,
+                    block_align: wave_format_info.block_align as usize,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Size in bytes of the smallest piece of data that decodes on its own, a frame for PCM
+    /// encodings and a whole block for ADPCM
+    pub(crate) fn unit_size(&self, channels: usize) -> usize {
+        let bytes_per_sample = match self {
+            SampleEncoding::UnsignedInteger8 | SampleEncoding::ALaw | SampleEncoding::ULaw => 1,
+            SampleEncoding::SignedInteger { bytes_per_sample } => *bytes_per_sample,
+            SampleEncoding::Float32 => 4,
+            SampleEncoding::Float64 => 8,
+            SampleEncoding::Adpcm { block_align, .. } => return *block_align,
+        };
+        bytes_per_sample * channels
+    }
+
+    /// Number of frames decoded from one unit, see `unit_size`
+    pub(crate) fn frames_per_unit(&self) -> usize {
+        match self {
+            SampleEncoding::Adpcm { adpcm_format, .. } => adpcm_format.samples_per_block() as usize,
+            _ => 1,
+        }
+    }
+
+    /// Whether a truncated final unit still holds samples worth decoding
+    pub(crate) fn decodes_partial_units(&self) -> bool {
+        matches!(self, SampleEncoding::Adpcm { .. })
+    }
+
+    /// Whether samples can go beyond full scale, which integer encodings cannot represent
+    pub(crate) fn is_float(&self) -> bool {
+        matches!(self, SampleEncoding::Float32 | SampleEncoding::Float64)
+    }
+
+    /// Decodes whole units from `data` into `output`, normalized to `[-1.0, 1.0)`
+    pub(crate) fn decode(&self, data: &[u8], output: &mut Vec<f64>) {
+        match self {
+            SampleEncoding::UnsignedInteger8 => {
+                output.extend(data.iter().map(|sample| (*sample as f64 - 128.0) / 128.0))
+            }
+            SampleEncoding::ALaw => output.extend(
+                data.iter()
+                    .map(|sample| ALAW_TO_LINEAR[*sample as usize] as f64 / 32768.0),
+            ),
+            SampleEncoding::ULaw => output.extend(
+                data.iter()
+                    .map(|sample| ULAW_TO_LINEAR[*sample as usize] as f64 / 32768.0),
+            ),
+            SampleEncoding::SignedInteger { bytes_per_sample } => {
+                let shift = 32 - 8 * *bytes_per_sample as u32;
+                output.extend(data.chunks_exact(*bytes_per_sample).map(|sample| {
+                    // Place the sample in the top bytes of an i32 so the sign is extended by the
+                    // shift
+                    let mut bytes = [0; 4];
+                    bytes[4 - sample.len()..].copy_from_slice(sample);
+                    (i32::from_le_bytes(bytes) >> shift) as f64 / (1u32 << (31 - shift)) as f64
+                }));
+            }
+            SampleEncoding::Float32 => output.extend(data.chunks_exact(4).map(|sample| {
+                f32::from_le_bytes(unsafe { sample.try_into().unwrap_unchecked() }) as f64
+            })),
+            SampleEncoding::Float64 => {
+                output.extend(data.chunks_exact(8).map(|sample| {
+                    f64::from_le_bytes(unsafe { sample.try_into().unwrap_unchecked() })
+                }))
+            }
+            SampleEncoding::Adpcm {
+                adpcm_format,
+                channels,
+                block_align,
+            } => {
+                let mut decoded = vec![];
+                for block in data.chunks(*block_align) {
+                    adpcm::decode_block(adpcm_format, *channels, block, &mut decoded);
+                }
+                output.extend(decoded.iter().map(|sample| *sample as f64 / 32768.0));
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::io::{self, Seek, Write};
+
+use crate::DWORD_SIZE;
+use crate::errors::{DJWavFixerError, Result};
+use crate::riff_parser::{DATA_MAGIC, FMT_MAGIC, RiffWriter};
+
+/// Largest size a RIFF chunk can declare, fixed files beyond it are split into parts
+pub(crate) const MAX_RIFF_SIZE: u64 = u32::MAX as u64;
+
+/// Writes a RIFF file that moves on to a new part whenever its `data` subchunk would grow past
+/// `max_riff_size`. Later parts hold `fmt ` and the rest of the audio, subchunks after `data`
+/// end up in the last part unless they are relocated to the first
+pub(crate) struct SplitRiffWriter<W, F> {
+    open_part: F,
+    format: [u8; DWORD_SIZE],
+    fmt_data: Vec<u8>,
+    block_align: u64,
+    max_riff_size: u64,
+    riff_writer: Option<RiffWriter<W>>,
+    finished_parts: Vec<W>,
+    /// Subchunks written after the audio of the first part if the file is split
+    relocated: Vec<([u8; DWORD_SIZE], Vec<u8>)>,
+    /// Room left for subchunks that come after `data`, in every part as the last one is unknown
+    trailing_size: u64,
+    /// Bytes the open `data` subchunk of the current part can still take
+    data_capacity: u64,
+}
+
+impl<W: Write + Seek, F: FnMut(usize) -> Result<W>> SplitRiffWriter<W, F> {
+    /// `open_part` is called with the part index whenever a new part is needed
+    pub(crate) fn try_new(
+        mut open_part: F,
+        format: [u8; DWORD_SIZE],
+        fmt_data: Vec<u8>,
+        block_align: u16,
+        max_riff_size: u64,
+    ) -> Result<Self> {
+        let riff_writer = RiffWriter::try_new(open_part(0)?, format)?;
+
+        Ok(Self {
+            open_part,
+            format,
+            fmt_data,
+            block_align: block_align.max(1) as u64,
+            max_riff_size,
+            riff_writer: Some(riff_writer),
+            finished_parts: vec![],
+            relocated: vec![],
+            trailing_size: 0,
+            data_capacity: 0,
+        })
+    }
+
+    pub(crate) fn riff_writer(&mut self) -> &mut RiffWriter<W> {
+        // Only `None` while switching parts
+        self.riff_writer.as_mut().unwrap()
+    }
+
+    fn begin_data_in_current_part(&mut self) -> Result<()> {
+        let trailing_size = self.trailing_size;
+        let max_riff_size = self.max_riff_size;
+        let riff_writer = self.riff_writer();
+        riff_writer.begin_subchunk(DATA_MAGIC)?;
+
+        let available = max_riff_size.saturating_sub(riff_writer.riff_size()? + trailing_size);
+        // Parts end on whole frames, with room for the pad byte after an odd-sized `data`
+        self.data_capacity = available - available % self.block_align;
+        if self.data_capacity % 2 == 1 && self.data_capacity == available {
+            self.data_capacity -= self.block_align;
+        }
+        if self.data_capacity == 0 {
+            return Err(DJWavFixerError::FixError(
+                "Subchunks leave no room for audio in a RIFF file".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Opens the `data` subchunk, `trailing_size` is the space needed by the subchunks after it
+    pub(crate) fn begin_data(&mut self, trailing_size: u64) -> Result<()> {
+        self.trailing_size = trailing_size;
+        self.begin_data_in_current_part()
+    }
+
+    pub(crate) fn end_data(&mut self) -> Result<()> {
+        self.riff_writer().end_subchunk()
+    }
+
+    /// Has a subchunk that comes after `data` follow the audio of the first part, where it would
+    /// otherwise end up in the last, its size must be part of the trailing size
+    pub(crate) fn relocate_to_first_part(&mut self, id: [u8; DWORD_SIZE], data: Vec<u8>) {
+        self.relocated.push((id, data));
+    }
+
+    /// Whether the audio did not fit in one part
+    pub(crate) fn is_split(&self) -> bool {
+        !self.finished_parts.is_empty()
+    }
+
+    fn start_next_part(&mut self) -> Result<()> {
+        let mut riff_writer = self.riff_writer.take().unwrap();
+        riff_writer.end_subchunk()?;
+        if self.finished_parts.is_empty() {
+            for (id, data) in self.relocated.drain(..) {
+                riff_writer.write_subchunk(id, &data)?;
+            }
+        }
+        self.finished_parts.push(riff_writer.finish()?);
+
+        let part = (self.open_part)(self.finished_parts.len())?;
+        let mut riff_writer = RiffWriter::try_new(part, self.format)?;
+        riff_writer.write_subchunk(FMT_MAGIC, &self.fmt_data)?;
+        self.riff_writer = Some(riff_writer);
+
+        self.begin_data_in_current_part()
+    }
+
+    /// Finishes the last part, returning the writers of all parts in order
+    pub(crate) fn finish(mut self) -> Result<Vec<W>> {
+        let riff_writer = self.riff_writer.take().unwrap();
+        self.finished_parts.push(riff_writer.finish()?);
+        Ok(self.finished_parts)
+    }
+}
+
+/// Streams the body of the open `data` subchunk, splitting it across parts as needed
+impl<W: Write + Seek, F: FnMut(usize) -> Result<W>> Write for SplitRiffWriter<W, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.data_capacity == 0 {
+            self.start_next_part().map_err(io::Error::other)?;
+        }
+
+        let length = buf
+            .len()
+            .min(self.data_capacity.min(usize::MAX as u64) as usize);
+        self.riff_writer().writer().write_all(&buf[..length])?;
+        self.data_capacity -= length as u64;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.riff_writer().writer().flush()
+    }
+}
@@ -0,0 +1,135 @@
+use crate::DWORD_SIZE;
+use crate::errors::Result;
+use crate::metadata::{
+    ADTL_LIST_TYPE, BEXT_MAGIC, CART_MAGIC, CUE_MAGIC, Cart, PLST_MAGIC, SMPL_MAGIC,
+    resample_positions, update_bext,
+};
+use crate::riff_parser::{DS64_MAGIC, FACT_MAGIC, LIST_MAGIC, RiffSubchunk};
+use crate::wav_file::WaveFormatExtensible;
+use crate::wav_fixer::FixPlan;
+
+/// Subchunks holding positions in sample frames from the first sample of the recording
+const SAMPLE_POSITION_IDS: [[u8; DWORD_SIZE]; 3] = [CUE_MAGIC, SMPL_MAGIC, PLST_MAGIC];
+/// Subchunks holding positions in time from the start of the recording, such as Serato cues
+const TIME_POSITION_IDS: [[u8; DWORD_SIZE]; 2] = [*b"id3 ", *b"ID3 "];
+
+/// What a fix does with a subchunk it neither rewrites nor transcodes
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum SubchunkPolicy {
+    /// Copied as-is in its original place
+    Keep,
+    /// Left out of the fixed file
+    Drop,
+    /// Copied as-is in its original place, unless the fix splits the file. Then it follows the
+    /// audio of the first part instead of ending up in the last one, as the positions in it
+    /// count from the start of the recording
+    Relocate,
+    /// Rewritten to match the audio when the fix converts samples, copied as-is otherwise
+    Update,
+    /// Placed like `Relocate`, with its sample positions rescaled when the fix changes the
+    /// sample rate. Left out if they cannot be
+    Reposition,
+}
+
+impl SubchunkPolicy {
+    pub(crate) fn of(subchunk: &RiffSubchunk) -> Self {
+        match subchunk.id() {
+            // The sample count in `fact` only describes compressed formats, plain PCM goes without
+            FACT_MAGIC => SubchunkPolicy::Drop,
+            // Only RF64 needs it, fixed files are always plain RIFF
+            DS64_MAGIC => SubchunkPolicy::Drop,
+            BEXT_MAGIC | CART_MAGIC => SubchunkPolicy::Update,
+            id if SAMPLE_POSITION_IDS.contains(&id) => SubchunkPolicy::Reposition,
+            _ if subchunk.list_type() == Some(ADTL_LIST_TYPE) => SubchunkPolicy::Reposition,
+            id if TIME_POSITION_IDS.contains(&id) => SubchunkPolicy::Relocate,
+            _ => SubchunkPolicy::Keep,
+        }
+    }
+
+    /// Whether the subchunk follows the audio of the first part when the fix splits the file
+    pub(crate) fn relocates(self) -> bool {
+        matches!(self, SubchunkPolicy::Relocate | SubchunkPolicy::Reposition)
+    }
+
+    /// Whether the subchunk may need rewriting when the fix converts samples
+    pub(crate) fn updates(self) -> bool {
+        matches!(self, SubchunkPolicy::Update | SubchunkPolicy::Reposition)
+    }
+}
+
+/// What `update_subchunk` made of a subchunk
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum SubchunkUpdate {
+    /// Copied as-is
+    Unchanged,
+    /// Written with this body instead
+    Rewritten(Vec<u8>),
+    /// Left out of the fixed file, for the reason given
+    Dropped(String),
+}
+
+/// Describes audio in `format` as a line of `bext` coding history, as in EBU R98
+fn coding_history_line(format: &WaveFormatExtensible) -> String {
+    let mode = match format.channels.as_u16() {
+        1 => "mono".to_string(),
+        2 => "stereo".to_string(),
+        channels => format!("{} channels", channels),
+    };
+    format!(
+        "A=PCM,F={},W={},M={},T={}",
+        format.sample_rate,
+        format.bits_per_sample,
+        mode,
+        env!("CARGO_PKG_NAME")
+    )
+}
+
+/// What becomes of an `Update` or `Reposition` subchunk once `plan` is applied
+pub(crate) fn update_subchunk(id: [u8; DWORD_SIZE], data: &[u8], plan: &FixPlan) -> SubchunkUpdate {
+    let Some(conversion) = plan.conversion.as_ref() else {
+        return SubchunkUpdate::Unchanged;
+    };
+    let (source_rate, target_rate) = (conversion.source_sample_rate, conversion.target_sample_rate);
+    let resamples = source_rate != target_rate;
+
+    let updated: Result<Vec<u8>> = match id {
+        BEXT_MAGIC => update_bext(
+            data,
+            source_rate,
+            target_rate,
+            &coding_history_line(&plan.target_format),
+        ),
+        // Only the timers depend on the audio
+        CART_MAGIC if resamples => Cart::try_from(data).map(|mut cart| {
+            cart.resample(source_rate, target_rate);
+            cart.to_bytes()
+        }),
+        CUE_MAGIC | SMPL_MAGIC | PLST_MAGIC | LIST_MAGIC if resamples => {
+            let mut data = data.to_vec();
+            match resample_positions(id, &mut data, source_rate, target_rate) {
+                Ok(()) => Ok(data),
+                // Positions left at the old sample rate would point at the wrong audio
+                Err(error) => {
+                    return SubchunkUpdate::Dropped(format!(
+                        "Dropped '{}', its positions could not be resampled: {}",
+                        String::from_utf8_lossy(&id),
+                        error
+                    ));
+                }
+            }
+        }
+        _ => return SubchunkUpdate::Unchanged,
+    };
+
+    match updated {
+        Ok(data) => SubchunkUpdate::Rewritten(data),
+        Err(error) => {
+            log::warn!(
+                "Copying '{}' as-is, it could not be updated: {}",
+                String::from_utf8_lossy(&id),
+                error
+            );
+            SubchunkUpdate::Unchanged
+        }
+    }
+}
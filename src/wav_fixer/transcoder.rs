@@ -0,0 +1,272 @@
+use std::io::{Read, Write};
+
+use crate::errors::Result;
+use crate::wav_fixer::downmix::Downmix;
+use crate::wav_fixer::requantizer::{DitherMode, Requantizer};
+use crate::wav_fixer::resampler::Resampler;
+use crate::wav_fixer::sample_encoding::SampleEncoding;
+
+const FRAMES_PER_BLOCK: usize = 4096;
+
+/// Peak level that normalized files are scaled to, -0.1 dBFS leaves room for dither
+const NORMALIZED_PEAK: f64 = 0.988_553_094_656_938_9;
+
+/// What to do with float samples that go beyond full scale
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum ClipHandling {
+    /// Clamp samples to full scale
+    #[default]
+    Clip,
+    /// Scale the whole file down so its peak fits, only when it would otherwise clip
+    NormalizePeak,
+}
+
+/// What happened to the samples while transcoding
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct TranscodeStatistics {
+    pub(crate) clipped_samples: u64,
+    pub(crate) gain: f64,
+    /// Bytes of a trailing partial frame left out
+    pub(crate) trimmed_bytes: u64,
+}
+
+/// How the samples of the `data` subchunk are rewritten by a fix
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SampleConversion {
+    pub(crate) source_encoding: SampleEncoding,
+    pub(crate) channels: usize,
+    /// Mixes the source channels down to stereo, for layouts players cannot handle
+    pub(crate) downmix: Option<Downmix>,
+    /// Frames to keep, from the `fact` subchunk of compressed formats whose last block is padded
+    pub(crate) frame_count: Option<u64>,
+    pub(crate) target_bits_per_sample: u16,
+    pub(crate) source_sample_rate: u32,
+    pub(crate) target_sample_rate: u32,
+    pub(crate) dither: DitherMode,
+    pub(crate) clip_handling: ClipHandling,
+}
+
+impl SampleConversion {
+    fn target_channels(&self) -> usize {
+        match self.downmix {
+            Some(_) => 2,
+            None => self.channels,
+        }
+    }
+
+    /// Decodes and downmixes `reader` block by block, a trailing partial frame is dropped and
+    /// decoding stops after `frame_count` frames. Returns the number of bytes dropped
+    fn for_each_block<R: Read>(
+        &self,
+        mut reader: R,
+        mut process: impl FnMut(&[f64]) -> Result<()>,
+    ) -> Result<u64> {
+        let unit_size = self.source_encoding.unit_size(self.channels);
+        let units_per_block = (FRAMES_PER_BLOCK / self.source_encoding.frames_per_unit()).max(1);
+        let block_size = unit_size * units_per_block;
+
+        let mut frames_left = self.frame_count.unwrap_or(u64::MAX);
+        let (mut input, mut samples, mut downmixed) = (vec![], vec![], vec![]);
+        loop {
+            input.clear();
+            let read = (&mut reader)
+                .take(block_size as u64)
+                .read_to_end(&mut input)?;
+
+            let whole_units_size = if self.source_encoding.decodes_partial_units() {
+                read
+            } else {
+                read - read % unit_size
+            };
+            if whole_units_size != read {
+                log::warn!(
+                    "Dropping {} bytes of trailing partial frame",
+                    read - whole_units_size
+                );
+            }
+
+            samples.clear();
+            self.source_encoding
+                .decode(&input[..whole_units_size], &mut samples);
+
+            let frames = (samples.len() / self.channels) as u64;
+            if frames > frames_left {
+                samples.truncate(frames_left as usize * self.channels);
+            }
+            frames_left -= frames.min(frames_left);
+
+            match self.downmix {
+                Some(ref downmix) => {
+                    downmixed.clear();
+                    downmix.process(&samples, &mut downmixed);
+                    process(&downmixed)?;
+                }
+                None => process(&samples)?,
+            }
+
+            if read < block_size || frames_left == 0 {
+                return Ok((read - whole_units_size) as u64);
+            }
+        }
+    }
+
+    /// Whether `transcode` needs a gain measured up front with `measure_gain`
+    pub(crate) fn needs_gain(&self) -> bool {
+        // Downmixing sums channels, so integer sources can go beyond full scale too
+        self.clip_handling == ClipHandling::NormalizePeak
+            && (self.source_encoding.is_float() || self.downmix.is_some())
+    }
+
+    /// Reads all samples to find the gain that keeps the peak within full scale
+    pub(crate) fn measure_gain<R: Read>(&self, reader: R) -> Result<f64> {
+        let mut peak = 0f64;
+        self.for_each_block(reader, |samples| {
+            peak = samples
+                .iter()
+                .filter(|sample| sample.is_finite())
+                .fold(peak, |peak, sample| peak.max(sample.abs()));
+            Ok(())
+        })?;
+
+        Ok(if peak > 1.0 {
+            NORMALIZED_PEAK / peak
+        } else {
+            1.0
+        })
+    }
+
+    /// Streams the source samples from `reader` into `writer` in the target encoding and rate
+    pub(crate) fn transcode<R: Read, W: Write>(
+        &self,
+        reader: R,
+        writer: &mut W,
+        gain: f64,
+    ) -> Result<TranscodeStatistics> {
+        let mut requantizer = Requantizer::new(
+            self.target_bits_per_sample,
+            self.dither,
+            self.target_channels(),
+        );
+        let mut resampler = (self.source_sample_rate != self.target_sample_rate).then(|| {
+            Resampler::new(
+                self.source_sample_rate,
+                self.target_sample_rate,
+                self.target_channels(),
+            )
+        });
+        let mut statistics = TranscodeStatistics {
+            clipped_samples: 0,
+            gain,
+            trimmed_bytes: 0,
+        };
+
+        let (mut scaled, mut output) = (vec![], vec![]);
+        let mut write_samples = |samples: &[f64]| -> Result<()> {
+            scaled.clear();
+            // NaN carries no signal, it is written as silence rather than counted as clipped
+            scaled.extend(
+                samples
+                    .iter()
+                    .map(|sample| if sample.is_nan() { 0.0 } else { sample * gain }),
+            );
+            // Integer PCM stops one step short of +1.0, so +1.0 itself is clamped too
+            statistics.clipped_samples += scaled
+                .iter()
+                .filter(|sample| !(-1.0..1.0).contains(*sample))
+                .count() as u64;
+
+            output.clear();
+            requantizer.encode(&scaled, &mut output);
+            Ok(writer.write_all(&output)?)
+        };
+
+        let mut resampled = vec![];
+        let trimmed_bytes = self.for_each_block(reader, |samples| match resampler.as_mut() {
+            Some(resampler) => {
+                resampled.clear();
+                resampler.process(samples, &mut resampled);
+                write_samples(&resampled)
+            }
+            None => write_samples(samples),
+        })?;
+
+        if let Some(mut resampler) = resampler {
+            resampled.clear();
+            resampler.finish(&mut resampled);
+            write_samples(&resampled)?;
+        }
+
+        statistics.trimmed_bytes = trimmed_bytes;
+        Ok(statistics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn float_conversion(clip_handling: ClipHandling) -> SampleConversion {
+        SampleConversion {
+            source_encoding: SampleEncoding::Float32,
+            channels: 1,
+            downmix: None,
+            frame_count: None,
+            target_bits_per_sample: 16,
+            source_sample_rate: 48000,
+            target_sample_rate: 48000,
+            dither: DitherMode::Truncate,
+            clip_handling,
+        }
+    }
+
+    fn float_data(samples: &[f32]) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect()
+    }
+
+    fn decode_16bit(data: &[u8]) -> Vec<i16> {
+        data.chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn test_clipping_is_counted() {
+        let conversion = float_conversion(ClipHandling::Clip);
+        let data = float_data(&[0.5, 1.5, -2.0, -0.5, f32::NAN, 1.0, -1.0]);
+
+        assert!(!conversion.needs_gain());
+        let mut output = vec![];
+        let statistics = conversion
+            .transcode(data.as_slice(), &mut output, 1.0)
+            .expect("Failed to transcode");
+
+        assert_eq!(statistics.clipped_samples, 3);
+        assert_eq!(
+            decode_16bit(&output),
+            [16384, i16::MAX, i16::MIN, -16384, 0, i16::MAX, i16::MIN]
+        );
+    }
+
+    #[test]
+    fn test_normalize_peak_avoids_clipping() {
+        let conversion = float_conversion(ClipHandling::NormalizePeak);
+        let data = float_data(&[0.5, 2.0, -1.0]);
+
+        assert!(conversion.needs_gain());
+        let gain = conversion
+            .measure_gain(data.as_slice())
+            .expect("Failed to measure gain");
+        assert_eq!(gain, NORMALIZED_PEAK / 2.0);
+
+        let mut output = vec![];
+        let statistics = conversion
+            .transcode(data.as_slice(), &mut output, gain)
+            .expect("Failed to transcode");
+
+        assert_eq!(statistics.clipped_samples, 0);
+        assert_eq!(decode_16bit(&output), [8098, 32392, -16197]);
+    }
+}